@@ -7,8 +7,16 @@ pub enum Command {
     Help,
     #[command(description = "🎉 Start the bot")]
     Start,
-    #[command(description = "🔍 Find a random chat partner")]
-    Find,
+    #[command(description = "🔍 Find a chat partner, optionally by interests (usage: /find [tags...])")]
+    Find(String),
+    #[command(description = "🛑 Stop searching for a chat partner")]
+    Cancel,
+    #[command(description = "⏭️ Leave your current partner and find a new one (usage: /next [tags...])")]
+    Next(String),
+    #[command(description = "🌐 Set your chat language (usage: /language <code|auto>)")]
+    Language(String),
+    #[command(description = "👀 Share typing indicators and read receipts with partners (usage: /presence <on|off>)")]
+    Presence(String),
     #[command(description = "👋 Create a new chat room (usage: /createroom <name> <max_members>)", parse_with = "split")]
     CreateRoom {
         name: String,
@@ -20,6 +28,14 @@ pub enum Command {
     JoinRoom(String),
     #[command(description = "👋 Leave current chat or room")]
     Leave,
+    #[command(description = "🗑️ Delete a message you sent for everyone else (reply to it with /unsend)")]
+    Unsend,
+    #[command(description = "🚫 Block your current or last chat partner")]
+    Block,
+    #[command(description = "🚨 Report abuse in your current or last chat (usage: /report [reason])")]
+    Report(String),
+    #[command(description = "🪪 Show the anonymous id staff know you by, or get a new one (usage: /myid [new])")]
+    MyId(String),
     #[command(description = "👤 Set your profile (usage: /setprofile <nickname> <emoji> <bio>)", parse_with = "split")]
     SetProfile {
         nickname: String,
@@ -37,6 +53,26 @@ pub enum Command {
     ViewMood,
     #[command(description = "📈 View anonymous mood statistics")]
     MoodStats,
-    #[command(description = "📢 Broadcast message (usage: /broadcast <message>)")]
+    #[command(description = "📢 Broadcast message (usage: /broadcast <message>, admins only)")]
     Broadcast(String),
+    #[command(description = "🛡️ Reload the content filter word lists (admins only)")]
+    ReloadFilter,
+    #[command(description = "📋 List open reports (staff only)")]
+    Reports,
+    #[command(description = "🔎 Review a report and its messages (usage: /review <report_id>, staff only)")]
+    Review(String),
+    #[command(description = "✅ Resolve a report (usage: /resolve <report_id> [note], staff only)")]
+    Resolve(String),
+    #[command(description = "🚫 Ban a user (usage: /ban <pseudonym> [duration], e.g. 7d, staff only)")]
+    Ban(String),
+    #[command(description = "🔇 Stop a user sending messages (usage: /mute <pseudonym> [duration], staff only)")]
+    Mute(String),
+    #[command(description = "👻 Shadow-ban a user (usage: /shadowban <pseudonym> [duration], staff only)")]
+    Shadowban(String),
+    #[command(description = "✅ Lift a user's ban, mute and shadow ban (usage: /unban <pseudonym>, staff only)")]
+    Unban(String),
+    #[command(description = "🛡️ Make a user staff (usage: /promote <pseudonym> <moderator|admin>, admins only)")]
+    Promote(String),
+    #[command(description = "🧹 Take a user's staff role away (usage: /demote <pseudonym>, admins only)")]
+    Demote(String),
 } 
\ No newline at end of file
@@ -1,42 +1,53 @@
 use teloxide::prelude::*;
 use teloxide::utils::command::BotCommands;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use anyhow::Result;
+use chrono::Utc;
 use crate::{
-    models::{AppState, UserState},
+    models::{AppState, Permission, Role, SanctionKind, UserState},
     commands::Command,
-    services::{redis_service, chat_room, profile_service},
+    services::{
+        broadcast::{self, Broadcast},
+        matchmaking::{self, MatchOutcome, PARTNER_LEFT_MESSAGE},
+        moderation,
+        pseudonyms::{self, Rotation},
+        rate_limit::{self, Traffic},
+        relay,
+        reports::{self, REPORT_LIST_LIMIT},
+        roles::{self, RoleChange},
+    },
 };
 
 pub async fn handle_command(
     bot: Bot,
     msg: Message,
     cmd: Command,
-    state: Arc<Mutex<AppState>>,
+    state: Arc<AppState>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let chat_id = msg.chat.id.0;
-    let state_guard = state.lock().await;
-    let mut redis = state_guard.redis.get_async_connection().await.map_err(|e| anyhow::anyhow!(e))?;
     
     log::info!("📝 Received command: {:?} from user {}", cmd, chat_id);
+
+    if !rate_limit::admit(&bot, &state, chat_id, Traffic::Command, None).await? {
+        return Ok(());
+    }
     
     match cmd {
         Command::Help => {
             bot.send_message(
                 msg.chat.id,
-                format!("🌟 Welcome to Anonymous Chat! 🌟\n\n{}", Command::descriptions().to_string())
+                format!("🌟 Welcome to Anonymous Chat! 🌟\n\n{}", Command::descriptions())
             ).await?;
         }
         Command::Start => {
             log::info!("🎉 New user starting bot: {}", chat_id);
             
             // Clean up old data if exists
-            redis_service::cleanup_user_state(&mut redis, chat_id).await.map_err(|e| anyhow::anyhow!(e))?;
+            state.sessions.cleanup_user_state(chat_id).await.map_err(|e| anyhow::anyhow!(e))?;
             
             // Create new user state
             let new_state = UserState::new(chat_id);
-            redis_service::set_user_state(&mut redis, &new_state).await.map_err(|e| anyhow::anyhow!(e))?;
+            state.sessions.set_user_state(&new_state).await.map_err(|e| anyhow::anyhow!(e))?;
             
             bot.send_message(
                 msg.chat.id,
@@ -53,17 +64,19 @@ pub async fn handle_command(
                 /help - Show all commands\n\n\
                 📱 Supported messages:\n\
                 • Text messages 💬\n\
-                • Photos 📸\n\
+                • Photos, videos & GIFs 📸\n\
                 • Stickers 🎯\n\
-                • Voice Notes 🎤\n\n\
+                • Voice & video notes 🎤\n\
+                • Files & music 📎\n\
+                • Locations, polls & dice 📍\n\n\
                 🔒 Your privacy is our priority! Stay safe and have fun!"
             ).await?;
             
             log::info!("✅ User {} initialized successfully", chat_id);
         }
-        Command::Find => {
+        Command::Find(args) => {
             // Check if user is in a room
-            if let Ok(Some(current_state)) = redis_service::get_user_state(&mut redis, chat_id).await {
+            if let Ok(Some(current_state)) = state.sessions.get_user_state(chat_id).await {
                 if current_state.current_room.is_some() {
                     bot.send_message(
                         msg.chat.id,
@@ -80,36 +93,136 @@ pub async fn handle_command(
                 }
             }
 
-            // Set user as searching
-            let user_state = UserState::new(chat_id);
-            let mut searching_state = user_state.clone();
-            searching_state.is_searching = true;
-            redis_service::set_user_state(&mut redis, &searching_state).await.map_err(|e| anyhow::anyhow!(e))?;
+            start_search(&bot, &msg, &state, &args).await?;
+        }
+        Command::Cancel => {
+            if state.sessions.cancel_search(chat_id).await.map_err(|e| anyhow::anyhow!(e))? {
+                bot.send_message(
+                    msg.chat.id,
+                    "🛑 Search cancelled.\n\
+                    Use /find whenever you're ready to chat again!"
+                ).await?;
+            } else {
+                bot.send_message(
+                    msg.chat.id,
+                    "❌ You're not searching for a chat partner."
+                ).await?;
+            }
+        }
+        Command::Next(args) => {
+            if let Ok(Some(current_state)) = state.sessions.get_user_state(chat_id).await {
+                if current_state.current_room.is_some() {
+                    bot.send_message(
+                        msg.chat.id,
+                        "❌ You're currently in a chat room! Use /leave first to find a private chat partner."
+                    ).await?;
+                    return Ok(());
+                }
+                if let Some(partner_id) = current_state.partner_id {
+                    state.sessions.end_private_chat(chat_id, partner_id).await.map_err(|e| anyhow::anyhow!(e))?;
+                    tell_partner_left(&bot, partner_id).await;
+                    bot.send_message(msg.chat.id, "👋 You've left the chat.").await?;
+                }
+            }
 
-            // Try to find a partner
-            if let Some(partner_id) = redis_service::find_random_partner(&mut redis, chat_id).await.map_err(|e| anyhow::anyhow!(e))? {
-                // Match found!
-                redis_service::connect_users(&mut redis, chat_id, partner_id).await.map_err(|e| anyhow::anyhow!(e))?;
-                
-                let match_message = "🎉 Chat partner found! Say hi! 👋\n\
-                    You can send:\n\
-                    • Text messages 💬\n\
-                    • Photos 📸\n\
-                    • Stickers 🎯\n\
-                    • Voice Notes 🎤\n\n\
-                    Use /leave when you want to end the chat.";
-                
-                bot.send_message(ChatId(chat_id), match_message).await?;
-                bot.send_message(ChatId(partner_id), match_message).await?;
+            start_search(&bot, &msg, &state, &args).await?;
+        }
+        Command::Presence(setting) => {
+            // `None` toggles the current setting
+            let share_presence = match setting.trim().to_lowercase().as_str() {
+                "" => None,
+                "on" => Some(true),
+                "off" => Some(false),
+                _ => {
+                    bot.send_message(msg.chat.id, "❌ Usage: /presence <on|off>").await?;
+                    return Ok(());
+                }
+            };
+            let Some(current_state) = state
+                .sessions
+                .update_user_state(chat_id, &|user_state| {
+                    user_state.share_presence = share_presence.unwrap_or(!user_state.share_presence);
+                })
+                .await
+                .map_err(|e| anyhow::anyhow!(e))?
+            else {
+                bot.send_message(msg.chat.id, "❌ Please use /start first!").await?;
+                return Ok(());
+            };
+
+            if current_state.share_presence {
+                bot.send_message(
+                    msg.chat.id,
+                    "👀 Presence sharing is on.\n\
+                    Your chat partners will see when you're typing and a 👀 reaction once you've seen their messages."
+                ).await?;
+            } else {
+                bot.send_message(
+                    msg.chat.id,
+                    "🙈 Presence sharing is off.\n\
+                    Your chat partners won't see typing indicators or read receipts from you."
+                ).await?;
+            }
+        }
+        Command::Language(code) => {
+            let code = code.trim();
+            if code.is_empty() {
+                let current = match state.profiles.get_language(chat_id).await {
+                    Ok(Some(language)) => language,
+                    _ => "auto (from your Telegram settings)".to_string(),
+                };
+                bot.send_message(
+                    msg.chat.id,
+                    format!("🌐 Your chat language: {}\n\n\
+                        Use /language <code> to change it (e.g. /language id or /language en),\n\
+                        or /language auto to follow your Telegram settings.", current)
+                ).await?;
+                return Ok(());
+            }
+
+            let language = if code.eq_ignore_ascii_case("auto") {
+                None
             } else {
+                match matchmaking::normalize_language(code) {
+                    Some(language) => Some(language),
+                    None => {
+                        bot.send_message(
+                            msg.chat.id,
+                            "❌ Invalid language code. Use a code like 'en' or 'id', or 'auto'."
+                        ).await?;
+                        return Ok(());
+                    }
+                }
+            };
+
+            if let Err(e) = state.profiles.save_language(chat_id, language.as_deref()).await {
+                log::error!("❌ Failed to save language for user {}: {}", chat_id, e);
                 bot.send_message(
                     msg.chat.id,
-                    "🔍 Looking for a chat partner... Please wait!"
+                    "❌ Failed to save your language. Please try again later."
                 ).await?;
+                return Ok(());
             }
+            state.sessions.cache_language(chat_id, language.as_deref(), matchmaking::LANGUAGE_CACHE_SECS).await.map_err(|e| anyhow::anyhow!(e))?;
+
+            // Apply right away in case the user is already waiting in /find
+            let effective_language = language.clone().or_else(|| {
+                msg.from()
+                    .and_then(|user| user.language_code.as_deref())
+                    .and_then(matchmaking::normalize_language)
+            });
+            state.sessions.set_search_language(chat_id, effective_language.as_deref()).await.map_err(|e| anyhow::anyhow!(e))?;
+
+            bot.send_message(
+                msg.chat.id,
+                format!("✅ Chat language set to {}.\n\
+                    We'll pair you with people speaking it first.",
+                    language.as_deref().unwrap_or("auto")
+                )
+            ).await?;
         }
         Command::CreateRoom { name, max_members } => {
-            if let Ok(Some(current_state)) = redis_service::get_user_state(&mut redis, chat_id).await {
+            if let Ok(Some(current_state)) = state.sessions.get_user_state(chat_id).await {
                 if current_state.partner_id.is_some() || current_state.current_room.is_some() {
                     bot.send_message(
                         msg.chat.id,
@@ -121,7 +234,7 @@ pub async fn handle_command(
 
             // Parse max_members from string to number
             let max_members = match max_members.parse::<usize>() {
-                Ok(num) => num.min(50).max(2), // Limit room size between 2 and 50
+                Ok(num) => num.clamp(2, 50), // Limit room size between 2 and 50
                 Err(_) => {
                     bot.send_message(
                         msg.chat.id,
@@ -132,7 +245,7 @@ pub async fn handle_command(
                 }
             };
 
-            let room = chat_room::create_room(&mut redis, name.clone(), max_members).await?;
+            let room = state.rooms.create_room(name.clone(), max_members).await?;
             
             bot.send_message(
                 msg.chat.id,
@@ -145,7 +258,7 @@ pub async fn handle_command(
             ).await?;
         }
         Command::ListRooms => {
-            let rooms = chat_room::list_rooms(&mut redis).await?;
+            let rooms = state.rooms.list_rooms().await?;
             if rooms.is_empty() {
                 bot.send_message(
                     msg.chat.id,
@@ -170,7 +283,7 @@ pub async fn handle_command(
             }
         }
         Command::JoinRoom(room_id) => {
-            if let Ok(Some(mut current_state)) = redis_service::get_user_state(&mut redis, chat_id).await {
+            if let Ok(Some(mut current_state)) = state.sessions.get_user_state(chat_id).await {
                 if current_state.partner_id.is_some() || current_state.current_room.is_some() {
                     bot.send_message(
                         msg.chat.id,
@@ -179,10 +292,17 @@ pub async fn handle_command(
                     return Ok(());
                 }
 
-                if chat_room::join_room(&mut redis, &room_id, &mut current_state).await? {
-                    redis_service::set_user_state(&mut redis, &current_state).await.map_err(|e| anyhow::anyhow!(e))?;
+                if state.rooms.join_room(&room_id, &mut current_state).await? {
+                    state
+                        .sessions
+                        .update_user_state(chat_id, &|user_state| {
+                            user_state.current_room = current_state.current_room.clone();
+                            user_state.update_activity();
+                        })
+                        .await
+                        .map_err(|e| anyhow::anyhow!(e))?;
                     
-                    if let Some(room) = chat_room::get_room(&mut redis, &room_id).await? {
+                    if let Some(room) = state.rooms.get_room(&room_id).await? {
                         bot.send_message(
                             msg.chat.id,
                             format!("🎉 Welcome to chat room '{}'!\n\
@@ -213,11 +333,15 @@ pub async fn handle_command(
             }
         }
         Command::Leave => {
-            if let Ok(Some(mut current_state)) = redis_service::get_user_state(&mut redis, chat_id).await {
+            if let Ok(Some(mut current_state)) = state.sessions.get_user_state(chat_id).await {
                 if let Some(room_id) = current_state.current_room.clone() {
                     // Leave chat room
-                    chat_room::leave_room(&mut redis, &room_id, &mut current_state).await?;
-                    redis_service::set_user_state(&mut redis, &current_state).await.map_err(|e| anyhow::anyhow!(e))?;
+                    state.rooms.leave_room(&room_id, &mut current_state).await?;
+                    state
+                        .sessions
+                        .update_user_state(chat_id, &|user_state| user_state.current_room = None)
+                        .await
+                        .map_err(|e| anyhow::anyhow!(e))?;
                     
                     bot.send_message(
                         msg.chat.id,
@@ -226,23 +350,9 @@ pub async fn handle_command(
                     ).await?;
                 } else if let Some(partner_id) = current_state.partner_id {
                     // Leave private chat
-                    bot.send_message(
-                        ChatId(partner_id),
-                        "👋 Your chat partner has left the chat.\n\
-                        Use /find to start a new chat!"
-                    ).await?;
-                    
-                    // Clear partner's state
-                    if let Ok(Some(mut partner_state)) = redis_service::get_user_state(&mut redis, partner_id).await {
-                        partner_state.partner_id = None;
-                        partner_state.is_searching = false;
-                        redis_service::set_user_state(&mut redis, &partner_state).await.map_err(|e| anyhow::anyhow!(e))?;
-                    }
-                    
-                    // Clear user's state
-                    current_state.partner_id = None;
-                    current_state.is_searching = false;
-                    redis_service::set_user_state(&mut redis, &current_state).await.map_err(|e| anyhow::anyhow!(e))?;
+                    // Clear both users' states
+                    state.sessions.end_private_chat(chat_id, partner_id).await.map_err(|e| anyhow::anyhow!(e))?;
+                    tell_partner_left(&bot, partner_id).await;
                     
                     bot.send_message(
                         msg.chat.id,
@@ -258,33 +368,138 @@ pub async fn handle_command(
                 }
             }
         }
+        Command::Unsend => {
+            let Some(original) = msg.reply_to_message() else {
+                bot.send_message(
+                    msg.chat.id,
+                    "↩️ Reply to one of your messages with /unsend to delete it for everyone else."
+                ).await?;
+                return Ok(());
+            };
+            if original.from().map(|user| user.id.0 as i64) != Some(chat_id) {
+                bot.send_message(msg.chat.id, "❌ You can only unsend your own messages.").await?;
+                return Ok(());
+            }
+
+            let deleted = relay::unsend(&bot, state.sessions.as_ref(), chat_id, original.id).await?;
+
+            if deleted == 0 {
+                bot.send_message(msg.chat.id, "❌ That message can no longer be unsent.").await?;
+            } else {
+                bot.send_message(msg.chat.id, "🗑️ Message unsent.").await?;
+            }
+        }
+        Command::Block => {
+            let current_state = state.sessions.get_user_state(chat_id).await.map_err(|e| anyhow::anyhow!(e))?;
+            let Some(current_state) = current_state else {
+                bot.send_message(msg.chat.id, "❌ There's no chat partner to block.").await?;
+                return Ok(());
+            };
+
+            let Some(blocked_id) = current_state.partner_id.or(current_state.last_partner_id) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "❌ There's no chat partner to block.\n\
+                    Use /block during a private chat or right after it ends."
+                ).await?;
+                return Ok(());
+            };
+
+            state.sessions.block_user(chat_id, blocked_id).await.map_err(|e| anyhow::anyhow!(e))?;
+            log::info!("🚫 User {} blocked a chat partner", chat_id);
+
+            if current_state.partner_id == Some(blocked_id) {
+                // The partner sees an ordinary leave, never the block
+                state.sessions.end_private_chat(chat_id, blocked_id).await.map_err(|e| anyhow::anyhow!(e))?;
+                tell_partner_left(&bot, blocked_id).await;
+            }
+
+            bot.send_message(
+                msg.chat.id,
+                "🚫 Partner blocked. You won't be matched or share a room with them again.\n\
+                Use /find to start a new chat!"
+            ).await?;
+        }
+        Command::Report(reason) => {
+            let current_state = state.sessions.get_user_state(chat_id).await.map_err(|e| anyhow::anyhow!(e))?;
+            let report = match &current_state {
+                Some(current_state) => reports::file_report(&state, current_state, &reason).await?,
+                None => None,
+            };
+            let Some(report) = report else {
+                bot.send_message(
+                    msg.chat.id,
+                    "❌ There's nothing to report.\n\
+                    Use /report during a chat or right after it ends."
+                ).await?;
+                return Ok(());
+            };
+
+            let block_hint = if report.reported_id.is_some() {
+                "\nUse /block if you don't want to meet this person again."
+            } else {
+                ""
+            };
+            bot.send_message(
+                msg.chat.id,
+                format!("🚨 Thank you, your report #{} has been filed and a moderator will review it.{}",
+                    report.report_id, block_hint)
+            ).await?;
+        }
+        Command::MyId(args) => {
+            let text = match args.trim() {
+                "" => {
+                    let pseudonym = pseudonyms::pseudonym_of(&state, chat_id).await?;
+                    format!(
+                        "🪪 Staff know you as {}. They never see your Telegram account.\n\
+                        Use /myid new to get a new id.",
+                        pseudonym
+                    )
+                }
+                "new" => match pseudonyms::rotate(&state, chat_id).await? {
+                    Rotation::Done(pseudonym) => {
+                        format!("🪪 Your new id is {}. The old one no longer works.", pseudonym)
+                    }
+                    Rotation::UnderReview => {
+                        "❌ You can't get a new id while a report about you is open.".to_string()
+                    }
+                    Rotation::TooSoon => "❌ You can only get a new id once a day.".to_string(),
+                },
+                _ => "❌ Usage: /myid [new]".to_string(),
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
         Command::SetProfile { nickname, emoji, bio } => {
             log::info!("🔄 Processing /setprofile command for user {}", chat_id);
             log::info!("👤 Setting profile for user {}: {} {} {}", chat_id, nickname, emoji, bio);
             
             // Save to Redis for session data
-            if let Ok(Some(mut current_state)) = redis_service::get_user_state(&mut redis, chat_id).await {
-                current_state.set_profile(nickname.clone(), emoji.clone(), bio.clone());
-                if let Err(e) = redis_service::set_user_state(&mut redis, &current_state).await {
-                    log::error!("❌ Failed to save profile to Redis for user {}: {}", chat_id, e);
-                    bot.send_message(
-                        msg.chat.id,
-                        "❌ Failed to save profile. Please try again later."
-                    ).await?;
-                    return Ok(());
-                }
+            let saved = state
+                .sessions
+                .update_user_state(chat_id, &|user_state| {
+                    user_state.set_profile(nickname.clone(), emoji.clone(), bio.clone());
+                })
+                .await;
+            if let Err(e) = saved {
+                log::error!("❌ Failed to save profile to Redis for user {}: {}", chat_id, e);
+                bot.send_message(
+                    msg.chat.id,
+                    "❌ Failed to save profile. Please try again later."
+                ).await?;
+                return Ok(());
+            } else if let Ok(Some(_)) = saved {
                 log::info!("✅ Profile saved to Redis for user {}", chat_id);
             } else {
                 log::warn!("⚠️ No existing state found for user {}, creating new", chat_id);
                 let mut new_state = UserState::new(chat_id);
                 new_state.set_profile(nickname.clone(), emoji.clone(), bio.clone());
-                redis_service::set_user_state(&mut redis, &new_state).await?;
+                state.sessions.set_user_state(&new_state).await?;
             }
 
             // Save to MongoDB for persistence
-            if let Ok(Some(current_state)) = redis_service::get_user_state(&mut redis, chat_id).await {
+            if let Ok(Some(current_state)) = state.sessions.get_user_state(chat_id).await {
                 if let Some(profile) = current_state.profile.clone() {
-                    match state_guard.mongodb.save_profile(chat_id, profile.clone()).await {
+                    match state.profiles.save_profile(chat_id, profile.clone()).await {
                         Ok(_) => {
                             log::info!("✅ Profile saved to MongoDB for user {}", chat_id);
                             bot.send_message(
@@ -311,8 +526,7 @@ pub async fn handle_command(
         Command::ViewProfile => {
             log::info!("🔄 Processing /viewprofile command for user {}", chat_id);
             
-            let state_guard = state.lock().await;
-            match state_guard.mongodb.get_profile(chat_id).await {
+            match state.profiles.get_profile(chat_id).await {
                 Ok(Some(profile)) => {
                     log::info!("✅ Retrieved profile for user {}", chat_id);
                     bot.send_message(
@@ -349,12 +563,15 @@ pub async fn handle_command(
             }
         }
         Command::SetMood { mood, note } => {
-            if let Ok(Some(mut current_state)) = redis_service::get_user_state(&mut redis, chat_id).await {
-                current_state.set_mood(mood.clone(), Some(note.clone()));
-                redis_service::set_user_state(&mut redis, &current_state).await.map_err(|e| anyhow::anyhow!(e))?;
+            let updated = state
+                .sessions
+                .update_user_state(chat_id, &|user_state| user_state.set_mood(mood.clone(), Some(note.clone())))
+                .await
+                .map_err(|e| anyhow::anyhow!(e))?;
+            if let Some(current_state) = updated {
                 
                 if let Some(mood_entry) = &current_state.daily_mood {
-                    profile_service::save_mood_history(&mut redis, chat_id, mood_entry).await?;
+                    state.profiles.save_mood_history(chat_id, mood_entry).await?;
                 }
                 
                 bot.send_message(
@@ -366,7 +583,7 @@ pub async fn handle_command(
             }
         }
         Command::ViewMood => {
-            if let Ok(moods) = profile_service::get_mood_history(&mut redis, chat_id).await {
+            if let Ok(moods) = state.profiles.get_mood_history(chat_id).await {
                 if moods.is_empty() {
                     bot.send_message(
                         msg.chat.id,
@@ -389,7 +606,7 @@ pub async fn handle_command(
             }
         }
         Command::MoodStats => {
-            if let Ok(stats) = profile_service::get_mood_stats(&mut redis).await {
+            if let Ok(stats) = state.profiles.get_mood_stats().await {
                 if stats.is_empty() {
                     bot.send_message(
                         msg.chat.id,
@@ -405,22 +622,336 @@ pub async fn handle_command(
             }
         }
         Command::Broadcast(message) => {
-            if let Ok(Some(current_state)) = redis_service::get_user_state(&mut redis, chat_id).await {
-                if !current_state.is_admin {
-                    bot.send_message(
-                        msg.chat.id,
-                        "❌ This command is only available for administrators."
-                    ).await?;
+            if !require(&bot, &msg, &state, Permission::Broadcast).await? {
+                return Ok(());
+            }
+
+            let broadcast = Broadcast::parse(chat_id, &message);
+            if broadcast.message.is_empty() {
+                bot.send_message(
+                    msg.chat.id,
+                    "❌ Usage: /broadcast [in <duration> | at <YYYY-MM-DD HH:MM>] <message>"
+                ).await?;
+                return Ok(());
+            }
+            if let Some(send_at) = broadcast.send_at {
+                if send_at <= Utc::now() {
+                    bot.send_message(msg.chat.id, "❌ That time has already passed.").await?;
                     return Ok(());
                 }
-                
-                let sent_count = profile_service::broadcast_message(&bot, &mut redis, &message, chat_id).await?;
                 bot.send_message(
                     msg.chat.id,
-                    format!("✅ Broadcast message sent to {} users.", sent_count)
+                    format!("🗓️ Broadcast #{} will be sent at {}.\n\
+                        ⚠️ Scheduled broadcasts are lost if the bot restarts before then.",
+                        broadcast.broadcast_id, send_at.format("%Y-%m-%d %H:%M UTC"))
                 ).await?;
             }
+            broadcast::start(bot.clone(), state.clone(), broadcast);
+        }
+        Command::ReloadFilter => {
+            if !require(&bot, &msg, &state, Permission::ReloadFilter).await? {
+                return Ok(());
+            }
+
+            match state.content_filter.reload().await {
+                Ok(count) => {
+                    let per_language: Vec<String> = state
+                        .content_filter
+                        .word_counts()
+                        .into_iter()
+                        .map(|(language, count)| format!("• {}: {}", language, count))
+                        .collect();
+                    log::info!("🛡️ Admin {} reloaded {} filtered words", chat_id, count);
+                    bot.send_message(
+                        msg.chat.id,
+                        format!("✅ Reloaded {} filtered words from {}.\n\n{}",
+                            count, state.content_filter.source(), per_language.join("\n"))
+                    ).await?;
+                }
+                Err(e) => {
+                    log::error!("❌ Failed to reload content filter: {}", e);
+                    bot.send_message(
+                        msg.chat.id,
+                        format!("❌ Failed to reload the word lists, keeping the current ones: {}", e)
+                    ).await?;
+                }
+            }
+        }
+        Command::Reports => {
+            if !require(&bot, &msg, &state, Permission::ReviewReports).await? {
+                return Ok(());
+            }
+
+            let open = state.moderation.list_open_reports(REPORT_LIST_LIMIT).await.map_err(|e| anyhow::anyhow!(e))?;
+            if open.is_empty() {
+                bot.send_message(msg.chat.id, "✅ No open reports.").await?;
+                return Ok(());
+            }
+            let lines: Vec<String> = open.iter().map(reports::summary_line).collect();
+            bot.send_message(
+                msg.chat.id,
+                format!("🚨 Open reports (oldest first):\n\n{}\n\nUse /review <report_id> to see one.", lines.join("\n"))
+            ).await?;
+        }
+        Command::Review(report_id) => {
+            if !require(&bot, &msg, &state, Permission::ReviewReports).await? {
+                return Ok(());
+            }
+
+            let report_id = report_id.trim().trim_start_matches('#');
+            match state.moderation.get_report(report_id).await.map_err(|e| anyhow::anyhow!(e))? {
+                Some(report) => {
+                    let parts = reports::format_report(&state, &report).await.map_err(|e| anyhow::anyhow!(e))?;
+                    for text in parts {
+                        bot.send_message(msg.chat.id, text).await?;
+                    }
+                }
+                None => {
+                    bot.send_message(msg.chat.id, format!("❌ No report #{}.", report_id)).await?;
+                }
+            }
+        }
+        Command::Resolve(args) => {
+            if !require(&bot, &msg, &state, Permission::ReviewReports).await? {
+                return Ok(());
+            }
+
+            let (report_id, note) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+            let report_id = report_id.trim_start_matches('#');
+            let note = if note.trim().is_empty() { "Resolved" } else { note.trim() };
+            let resolved = state.moderation
+                .resolve_report(report_id, chat_id, note)
+                .await
+                .map_err(|e| anyhow::anyhow!(e))?;
+            if !resolved {
+                bot.send_message(msg.chat.id, format!("❌ No open report #{}.", report_id)).await?;
+                return Ok(());
+            }
+
+            log::info!("✅ Admin {} resolved report {}", chat_id, report_id);
+            if let Some(report) = state.moderation.get_report(report_id).await.map_err(|e| anyhow::anyhow!(e))? {
+                let thanks = bot.send_message(
+                    ChatId(report.reporter_id),
+                    format!("✅ Your report #{} has been reviewed by a moderator. Thank you for helping keep chats safe!", report_id)
+                ).await;
+                if let Err(e) = thanks {
+                    log::warn!("⚠️ Failed to notify reporter {}: {}", report.reporter_id, e);
+                }
+            }
+            bot.send_message(msg.chat.id, format!("✅ Report #{} resolved.", report_id)).await?;
+        }
+        Command::Ban(args) => sanction_command(&bot, &msg, &state, SanctionKind::Ban, &args).await?,
+        Command::Mute(args) => sanction_command(&bot, &msg, &state, SanctionKind::Mute, &args).await?,
+        Command::Shadowban(args) => sanction_command(&bot, &msg, &state, SanctionKind::Shadowban, &args).await?,
+        Command::Unban(reference) => {
+            if !require(&bot, &msg, &state, Permission::Sanction).await? {
+                return Ok(());
+            }
+
+            let Some((target_id, pseudonym)) = resolve_pseudonym(&bot, &msg, &state, &reference).await? else {
+                return Ok(());
+            };
+            if !roles::may_act_on(&state, chat_id, target_id).await? {
+                bot.send_message(msg.chat.id, "❌ You can only lift sanctions from users below you.").await?;
+                return Ok(());
+            }
+            let lifted = moderation::lift(&bot, &state, target_id).await?;
+            bot.send_message(
+                msg.chat.id,
+                format!("✅ Lifted {} sanctions from user {}.", lifted, pseudonym)
+            ).await?;
+        }
+        Command::Promote(args) => {
+            if !require(&bot, &msg, &state, Permission::ManageRoles).await? {
+                return Ok(());
+            }
+
+            let mut args = args.split_whitespace();
+            let (Some(reference), Some(Ok(role))) = (args.next(), args.next().map(str::parse::<Role>)) else {
+                bot.send_message(msg.chat.id, "❌ Usage: /promote <pseudonym> <moderator|admin>").await?;
+                return Ok(());
+            };
+            let Some((target_id, pseudonym)) = resolve_pseudonym(&bot, &msg, &state, reference).await? else {
+                return Ok(());
+            };
+
+            let reply = match roles::promote(&state, chat_id, target_id, role).await? {
+                RoleChange::Done => {
+                    if let Err(e) = bot.send_message(
+                        ChatId(target_id),
+                        format!("🛡️ You are now {}. Send /help to see your commands.", role.describe())
+                    ).await {
+                        log::warn!("⚠️ Failed to notify user {}: {}", target_id, e);
+                    }
+                    format!("🛡️ User {} is now {}.", pseudonym, role.describe())
+                }
+                RoleChange::Unchanged => format!("ℹ️ User {} is already {}.", pseudonym, role.describe()),
+                RoleChange::NotAllowed => "❌ You can only promote users below you to roles below yours.".to_string(),
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+        }
+        Command::Demote(reference) => {
+            if !require(&bot, &msg, &state, Permission::ManageRoles).await? {
+                return Ok(());
+            }
+
+            let Some((target_id, pseudonym)) = resolve_pseudonym(&bot, &msg, &state, &reference).await? else {
+                return Ok(());
+            };
+
+            let reply = match roles::demote(&state, chat_id, target_id).await? {
+                RoleChange::Done => format!("✅ User {} is no longer staff.", pseudonym),
+                RoleChange::Unchanged => format!("ℹ️ User {} is not staff.", pseudonym),
+                RoleChange::NotAllowed => "❌ You can only demote staff below you.".to_string(),
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+        }
+    }
+    Ok(())
+}
+
+/// `/ban`, `/mute` and `/shadowban`: `<pseudonym> [duration]`.
+async fn sanction_command(
+    bot: &Bot,
+    msg: &Message,
+    state: &AppState,
+    kind: SanctionKind,
+    args: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !require(bot, msg, state, Permission::Sanction).await? {
+        return Ok(());
+    }
+
+    let mut args = args.split_whitespace();
+    let (Some(reference), duration) = (args.next(), args.next()) else {
+        bot.send_message(msg.chat.id, "❌ Usage: <pseudonym> [duration], e.g. U-7F3A2C 7d").await?;
+        return Ok(());
+    };
+    let duration = match duration.map(moderation::parse_duration) {
+        None => None,
+        Some(Some(duration)) => Some(duration),
+        Some(None) => {
+            bot.send_message(msg.chat.id, "❌ Durations look like 90s, 30m, 12h, 7d or 2w.").await?;
+            return Ok(());
+        }
+    };
+    let Some((target_id, pseudonym)) = resolve_pseudonym(bot, msg, state, reference).await? else {
+        return Ok(());
+    };
+    if !roles::may_act_on(state, msg.chat.id.0, target_id).await? {
+        bot.send_message(msg.chat.id, "❌ You can only sanction users below you.").await?;
+        return Ok(());
+    }
+
+    let sanction = moderation::impose(bot, state, target_id, kind, duration, msg.chat.id.0).await?;
+    let what = match kind {
+        SanctionKind::Ban => "banned",
+        SanctionKind::Mute => "muted",
+        SanctionKind::Shadowban => "shadow-banned",
+    };
+    bot.send_message(
+        msg.chat.id,
+        format!("⚖️ User {} {} {}.", pseudonym, what, moderation::describe_until(sanction.expires_at))
+    ).await?;
+    Ok(())
+}
+
+/// The user behind a pseudonym, and the pseudonym as staff should see it;
+/// tells the staff member when there is no such user.
+async fn resolve_pseudonym(
+    bot: &Bot,
+    msg: &Message,
+    state: &AppState,
+    reference: &str,
+) -> Result<Option<(i64, String)>> {
+    let pseudonym = pseudonyms::normalize(reference);
+    let target_id = pseudonyms::resolve(state, &pseudonym).await?;
+    if target_id.is_none() {
+        bot.send_message(msg.chat.id, format!("❌ Unknown user {}.", pseudonym)).await?;
+    }
+    Ok(target_id.map(|target_id| (target_id, pseudonym)))
+}
+
+// The partner may have blocked the bot, which must not keep the chat going
+async fn tell_partner_left(bot: &Bot, partner_id: i64) {
+    if let Err(e) = bot.send_message(ChatId(partner_id), PARTNER_LEFT_MESSAGE).await {
+        log::warn!("⚠️ Failed to tell user {} their partner left: {}", partner_id, e);
+    }
+}
+
+/// Whether the user's staff role grants `permission`; tells them otherwise.
+/// Every staff command goes through here.
+async fn require(bot: &Bot, msg: &Message, state: &AppState, permission: Permission) -> Result<bool> {
+    let role = roles::role_of(state, msg.chat.id.0).await?;
+    let allowed = role.is_some_and(|role| role.can(permission));
+    if !allowed {
+        let text = match role {
+            Some(role) => format!("❌ This command is not available to {}.", role.describe()),
+            None => "❌ This command is only available for administrators.".to_string(),
+        };
+        bot.send_message(msg.chat.id, text).await?;
+    }
+    Ok(allowed)
+}
+
+/// Puts the user in the search queue with their interest tags and language,
+/// announcing the match right away if a partner is already waiting.
+async fn start_search(
+    bot: &Bot,
+    msg: &Message,
+    state: &AppState,
+    args: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let chat_id = msg.chat.id.0;
+
+    // Given tags replace the saved interests; otherwise reuse the saved ones
+    let mut tags = matchmaking::parse_interest_tags(args);
+    if tags.is_empty() {
+        tags = state.profiles.get_interests(chat_id).await.unwrap_or_else(|e| {
+            log::error!("❌ Failed to load interests for user {}: {}", chat_id, e);
+            Vec::new()
+        });
+    } else if let Err(e) = state.profiles.save_interests(chat_id, &tags).await {
+        log::error!("❌ Failed to save interests for user {}: {}", chat_id, e);
+    }
+    state.sessions.set_search_tags(chat_id, &tags).await.map_err(|e| anyhow::anyhow!(e))?;
+
+    let language = matchmaking::effective_language(state, msg).await;
+    state.sessions.set_search_language(chat_id, language.as_deref()).await.map_err(|e| anyhow::anyhow!(e))?;
+
+    // Set user as searching, keeping their profile, mood and preferences
+    let start_searching = |user_state: &mut UserState| {
+        user_state.is_searching = true;
+        user_state.update_activity();
+    };
+    let updated = state
+        .sessions
+        .update_user_state(chat_id, &start_searching)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+    if updated.is_none() {
+        let mut searching_state = UserState::new(chat_id);
+        start_searching(&mut searching_state);
+        state.sessions.set_user_state(&searching_state).await.map_err(|e| anyhow::anyhow!(e))?;
+    }
+
+    // Try to find a partner
+    match matchmaking::find_match(state.sessions.as_ref(), &state.matching, chat_id).await.map_err(|e| anyhow::anyhow!(e))? {
+        MatchOutcome::Matched { partner_id, shared_tags } => {
+            matchmaking::announce_match(bot, chat_id, partner_id, &shared_tags).await?;
+        }
+        MatchOutcome::Waiting => {
+            let looking_for = if tags.is_empty() {
+                String::new()
+            } else {
+                format!("\n🏷️ Preferring partners interested in: {}", tags.join(", "))
+            };
+            bot.send_message(
+                msg.chat.id,
+                format!("🔍 Looking for a chat partner... Please wait!{}", looking_for)
+            ).await?;
         }
+        MatchOutcome::NotSearching => {}
     }
     Ok(())
-} 
\ No newline at end of file
+}
@@ -1,21 +1,23 @@
-use teloxide::{
-    prelude::*,
-    types::{InputFile, MediaKind, MessageKind},
-};
+use teloxide::prelude::*;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use anyhow::Result;
+use chrono::Utc;
 use crate::{
-    models::AppState,
-    services::{redis_service, content_filter, chat_room},
+    models::{AppState, RecentMessage},
+    services::{
+        chat_room,
+        content_filter::Verdict,
+        inactivity::{self, INACTIVITY_TIMEOUT},
+        matchmaking,
+        rate_limit::{self, Traffic},
+        relay::{self, RelayContent},
+    },
 };
 
-const INACTIVITY_TIMEOUT: u64 = 1800; // 30 minutes in seconds
-
 pub async fn handle_message(
     bot: Bot,
     msg: Message,
-    state: Arc<Mutex<AppState>>,
+    state: Arc<AppState>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     if let Some(text) = msg.text() {
         if text.starts_with('/') {
@@ -24,136 +26,167 @@ pub async fn handle_message(
     }
 
     let chat_id = msg.chat.id.0;
-    let state_guard = state.lock().await;
-    let mut redis = state_guard.redis.get_async_connection().await.map_err(|e| anyhow::anyhow!(e))?;
-
-    if let Ok(Some(mut current_state)) = redis_service::get_user_state(&mut redis, chat_id).await {
-        // Update last activity
-        current_state.update_activity();
-        redis_service::set_user_state(&mut redis, &current_state).await.map_err(|e| anyhow::anyhow!(e))?;
-
-        // Check for inactivity
-        if current_state.is_inactive(INACTIVITY_TIMEOUT) {
-            if let Some(partner_id) = current_state.partner_id {
-                // Notify partner about disconnection
-                bot.send_message(
-                    ChatId(partner_id),
-                    "⏰ Your chat partner has been disconnected due to inactivity.\n\
-                    Use /find to start a new chat!"
-                ).await?;
-
-                // Clear partner's state
-                if let Ok(Some(mut partner_state)) = redis_service::get_user_state(&mut redis, partner_id).await {
-                    partner_state.partner_id = None;
-                    partner_state.is_searching = false;
-                    redis_service::set_user_state(&mut redis, &partner_state).await.map_err(|e| anyhow::anyhow!(e))?;
-                }
-            }
 
-            // Clear user's state
-            current_state.partner_id = None;
-            current_state.is_searching = false;
-            redis_service::set_user_state(&mut redis, &current_state).await.map_err(|e| anyhow::anyhow!(e))?;
+    if let Ok(Some(current_state)) = state.sessions.get_user_state(chat_id).await {
+        // Check for inactivity before this message counts as activity
+        if current_state.is_inactive(INACTIVITY_TIMEOUT)
+            && (current_state.partner_id.is_some() || current_state.current_room.is_some())
+        {
+            inactivity::disconnect_inactive_user(&bot, &state, current_state).await?;
+            return Ok(());
+        }
 
+        // Floods are cut off before they cost a write or a reply
+        let room_id = current_state.current_room.as_deref();
+        if !rate_limit::admit(&bot, &state, chat_id, Traffic::of(&msg), room_id).await? {
+            return Ok(());
+        }
+
+        // Update last activity, keeping anything a partner changed meanwhile
+        let Some(current_state) = state
+            .sessions
+            .update_user_state(chat_id, &|user_state| user_state.update_activity())
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?
+        else {
+            return Ok(());
+        };
+
+        if current_state.current_room.is_none() && current_state.partner_id.is_none() {
             bot.send_message(
                 msg.chat.id,
-                "⏰ You have been disconnected due to inactivity.\n\
-                Use /find to start a new chat!"
+                "❌ You're not connected to anyone!\n\
+                Use /find to start chatting or /listrooms to join a chat room."
             ).await?;
+            return Ok(());
+        }
+
+        let Some(content) = RelayContent::from_message(&msg) else {
+            bot.send_message(msg.chat.id, "❌ This type of message is not supported.").await?;
+            return Ok(());
+        };
 
+        let notice = content.rejection_message();
+        let Some(content) = moderate(&bot, &state, &msg, content, notice).await? else {
+            return Ok(());
+        };
+
+        // Contacts reveal a real name and phone number, so the first one is
+        // held back until the sender confirms by sending it again
+        if matches!(content, RelayContent::Contact { .. }) && !current_state.contact_warning_acknowledged {
+            state
+                .sessions
+                .update_user_state(chat_id, &|user_state| user_state.contact_warning_acknowledged = true)
+                .await
+                .map_err(|e| anyhow::anyhow!(e))?;
+            bot.send_message(
+                msg.chat.id,
+                "⚠️ Sharing a contact reveals a real name and phone number.\n\
+                Send the contact again if you still want to share it."
+            ).await?;
             return Ok(());
         }
 
         // Handle message based on context (private chat or room)
         if let Some(room_id) = &current_state.current_room {
-            // Handle room message
-            if let Some(text) = msg.text() {
-                if content_filter::contains_inappropriate_content(text) {
-                    bot.send_message(
-                        msg.chat.id,
-                        "⚠️ Your message contains inappropriate content and was not sent."
-                    ).await?;
-                    return Ok(());
-                }
-                
-                let filtered_text = content_filter::filter_message(text);
-                chat_room::broadcast_to_room(&bot, &mut redis, room_id, chat_id, &filtered_text).await?;
-            }
+            chat_room::broadcast_to_room(&bot, &state, room_id, &msg, &content).await?;
         } else if let Some(partner_id) = current_state.partner_id {
-            // Handle private chat message
-            match msg.kind {
-                MessageKind::Common(common) => {
-                    match common.media_kind {
-                        MediaKind::Text(text) => {
-                            if content_filter::contains_inappropriate_content(&text.text) {
-                                bot.send_message(
-                                    msg.chat.id,
-                                    "⚠️ Your message contains inappropriate content and was not sent."
-                                ).await?;
-                                return Ok(());
-                            }
-                            
-                            let filtered_text = content_filter::filter_message(&text.text);
-                            bot.send_message(ChatId(partner_id), filtered_text).await?;
-                        }
-                        MediaKind::Photo(photo) => {
-                            if let Some(largest_photo) = photo.photo.last() {
-                                let file = InputFile::file_id(&largest_photo.file.id);
-                                let caption = photo.caption.unwrap_or_default();
-                                if content_filter::contains_inappropriate_content(&caption) {
-                                    bot.send_message(
-                                        msg.chat.id,
-                                        "⚠️ Your photo caption contains inappropriate content and was not sent."
-                                    ).await?;
-                                    return Ok(());
-                                }
-                                
-                                let filtered_caption = content_filter::filter_message(&caption);
-                                bot.send_photo(ChatId(partner_id), file)
-                                    .caption(filtered_caption)
-                                    .await?;
-                            }
-                        }
-                        MediaKind::Sticker(sticker) => {
-                            bot.send_sticker(ChatId(partner_id), InputFile::file_id(&sticker.sticker.file.id))
-                                .await?;
-                        }
-                        MediaKind::Voice(voice) => {
-                            let caption = voice.caption.unwrap_or_default();
-                            if content_filter::contains_inappropriate_content(&caption) {
-                                bot.send_message(
-                                    msg.chat.id,
-                                    "⚠️ Your voice note caption contains inappropriate content and was not sent."
-                                ).await?;
-                                return Ok(());
-                            }
-                            
-                            let filtered_caption = content_filter::filter_message(&caption);
-                            bot.send_voice(
-                                ChatId(partner_id), 
-                                InputFile::file_id(&voice.voice.file.id)
-                            )
-                            .caption(filtered_caption)
-                            .await?;
-                        }
-                        _ => {
-                            bot.send_message(
-                                msg.chat.id,
-                                "❌ This type of message is not supported. You can send text, photos, stickers, or voice notes."
-                            ).await?;
-                        }
-                    }
+            if current_state.share_presence {
+                // Bots are not told when a user is typing, so the closest
+                // signal is an action shown while the message is relayed
+                if let Err(e) = bot.send_chat_action(ChatId(partner_id), content.chat_action()).await {
+                    log::warn!("⚠️ Failed to send chat action to {}: {}", partner_id, e);
                 }
-                _ => {}
             }
-        } else {
+            relay::relay_message(&bot, state.sessions.as_ref(), &msg, &[partner_id], &content).await?;
+            // Reacted to once the partner next uses the bot, if they share presence
+            state.sessions.set_unseen_message(chat_id, partner_id, msg.id.0).await?;
+        }
+
+        // Kept so a /report can show what was said
+        if let Some(conversation) = relay::conversation_id(&current_state) {
+            let recent = RecentMessage { sender_id: chat_id, content: content.summary(), sent_at: Utc::now() };
+            state.sessions.record_recent_message(&conversation, &recent).await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn handle_edited_message(
+    bot: Bot,
+    msg: Message,
+    state: Arc<AppState>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let chat_id = msg.chat.id.0;
+    let Ok(Some(current_state)) = state.sessions.get_user_state(chat_id).await else {
+        return Ok(());
+    };
+    // Edits are relayed like messages, so they share the same budget
+    let room_id = current_state.current_room.as_deref();
+    if !rate_limit::admit(&bot, &state, chat_id, Traffic::of(&msg), room_id).await? {
+        return Ok(());
+    }
+    let Some(content) = RelayContent::from_message(&msg) else {
+        return Ok(());
+    };
+
+    let notice = "⚠️ Your edit contains inappropriate content and was not applied.".to_string();
+    let Some(mut content) = moderate(&bot, &state, &msg, content, notice).await? else {
+        return Ok(());
+    };
+    if current_state.current_room.is_some() {
+        content = content.labelled(chat_room::ROOM_MESSAGE_LABEL);
+    }
+    let recipients = relay::recipients(&state, &current_state).await?;
+    relay::relay_edit(&bot, state.sessions.as_ref(), &msg, &recipients, &content).await?;
+
+    Ok(())
+}
+
+/// Runs the content through the filter in the sender's language. When it may
+/// not be relayed, sends them `notice` (or says which info was withheld),
+/// flags them if the filter asks for it, and returns `None`.
+async fn moderate(
+    bot: &Bot,
+    state: &AppState,
+    msg: &Message,
+    content: RelayContent,
+    notice: String,
+) -> Result<Option<RelayContent>, Box<dyn std::error::Error + Send + Sync>> {
+    let chat_id = msg.chat.id.0;
+    let language = matchmaking::effective_language(state, msg).await;
+
+    match content.moderate(&state.content_filter, language.as_deref()) {
+        Verdict::Allow(content) => return Ok(Some(content)),
+        Verdict::Warn { content, kind } => {
             bot.send_message(
                 msg.chat.id,
-                "❌ You're not connected to anyone!\n\
-                Use /find to start chatting or /listrooms to join a chat room."
+                format!(
+                    "⚠️ Your message contains {}. Be careful what you share with strangers.",
+                    kind.describe()
+                ),
             ).await?;
+            return Ok(Some(content));
+        }
+        Verdict::Withhold { kind } => {
+            bot.send_message(
+                msg.chat.id,
+                format!("🔒 Messages containing {} are not allowed here, to keep chats safe and anonymous.", kind.describe()),
+            ).await?;
+            return Ok(None);
+        }
+        Verdict::Block => {}
+        Verdict::Flag { word } => {
+            state.profiles
+                .flag_user(chat_id, &format!("Used filtered word \"{}\"", word))
+                .await
+                .map_err(|e| anyhow::anyhow!(e))?;
+            let flags = state.profiles.get_flag_count(chat_id).await.unwrap_or_default();
+            log::warn!("🚩 Flagged user {} for a filtered word ({} flags in total)", chat_id, flags);
         }
     }
 
-    Ok(())
-} 
\ No newline at end of file
+    bot.send_message(msg.chat.id, notice).await?;
+    Ok(None)
+}
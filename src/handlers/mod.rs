@@ -1,2 +1,83 @@
 pub mod command_handler;
-pub mod message_handler; 
\ No newline at end of file
+pub mod message_handler;
+
+use std::error::Error;
+use std::sync::Arc;
+use teloxide::{dispatching::UpdateHandler, prelude::*, types::UpdateKind};
+use crate::{
+    commands::Command,
+    models::AppState,
+    services::{moderation::{self, Enforcement}, presence, rate_limit},
+};
+
+/// The update handler tree run by the dispatcher.
+pub fn schema(state: Arc<AppState>) -> UpdateHandler<Box<dyn Error + Send + Sync>> {
+    let message_state = state.clone();
+    let edit_state = state.clone();
+    let sanction_state = state.clone();
+    let presence_state = state.clone();
+
+    let messages = Update::filter_message()
+        .branch(
+            dptree::entry()
+                .filter_command::<Command>()
+                .endpoint(move |bot: Bot, msg: Message, cmd: Command| {
+                    command_handler::handle_command(bot, msg, cmd, state.clone())
+                }),
+        )
+        .branch(
+            dptree::filter(|msg: Message| !msg.text().map(|text| text.starts_with('/')).unwrap_or(false))
+                .endpoint(move |bot: Bot, msg: Message| {
+                    message_handler::handle_message(bot, msg, message_state.clone())
+                }),
+        );
+
+    let edits = Update::filter_edited_message().endpoint(move |bot: Bot, msg: Message| {
+        message_handler::handle_edited_message(bot, msg, edit_state.clone())
+    });
+
+    dptree::entry()
+        .filter_async(move |bot: Bot, update: Update| {
+            let state = sanction_state.clone();
+            async move { passes_sanctions(&bot, &state, &update).await }
+        })
+        .inspect_async(move |bot: Bot, update: Update| {
+            let state = presence_state.clone();
+            async move {
+                // Any update means the user has the chat open
+                let Some(chat) = update.chat() else { return };
+                if let Err(e) = presence::mark_seen(&bot, &state, chat.id.0).await {
+                    log::warn!("⚠️ Failed to mark messages seen for {}: {}", chat.id, e);
+                }
+            }
+        })
+        .branch(messages)
+        .branch(edits)
+}
+
+/// Applies bans, mutes and shadow bans before any handler sees the update.
+async fn passes_sanctions(bot: &Bot, state: &AppState, update: &Update) -> bool {
+    let (msg, is_edit) = match &update.kind {
+        UpdateKind::Message(msg) => (msg, false),
+        UpdateKind::EditedMessage(msg) => (msg, true),
+        _ => return true,
+    };
+    let chat_id = msg.chat.id.0;
+    let is_command = !is_edit && msg.text().is_some_and(|text| text.starts_with('/'));
+
+    match moderation::enforce(state, chat_id, is_command).await {
+        Ok(Enforcement::Allow) => true,
+        Ok(Enforcement::Drop) => false,
+        Ok(Enforcement::Refuse(notice)) => {
+            if let Err(e) = rate_limit::notify(bot, state, chat_id, notice).await {
+                log::warn!("⚠️ Failed to send sanction notice to {}: {}", chat_id, e);
+            }
+            false
+        }
+        Err(e) => {
+            // Moderation storage being down should not take the bot down with it
+            log::error!("❌ Failed to check sanctions for user {}: {}", chat_id, e);
+            true
+        }
+    }
+}
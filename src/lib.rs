@@ -0,0 +1,4 @@
+pub mod commands;
+pub mod handlers;
+pub mod models;
+pub mod services;
@@ -1,18 +1,21 @@
-mod commands;
-mod handlers;
-mod models;
-mod services;
-
 use std::error::Error;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use teloxide::prelude::*;
 use anyhow::Result;
 
-use crate::models::AppState;
-use crate::commands::Command;
-use crate::handlers::{command_handler, message_handler};
-use crate::services::mongodb_service::MongoDB;
+use telegram_bot::handlers;
+use telegram_bot::models::AppState;
+use telegram_bot::services::{
+    content_filter::{self, ContentFilter},
+    info_detector::InfoPolicy,
+    inactivity,
+    matchmaking::{self, MatchSettings},
+    mongodb_service::MongoDB,
+    profile_service::PersistentProfileStore,
+    rate_limit::RateLimits,
+    redis_service::RedisStore,
+    roles,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -21,36 +24,46 @@ async fn main() -> Result<(), Box<dyn Error>> {
     
     dotenvy::dotenv().ok();
     
-    let redis_client = redis::Client::open(
-        std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string())
-    ).map_err(|e| anyhow::anyhow!(e))?;
+    let state = match std::env::var("STORAGE_BACKEND").as_deref() {
+        Ok("memory") => {
+            log::warn!("⚠️ Using in-memory storage, nothing will survive a restart");
+            let source = content_filter::source_from_env(None)?;
+            AppState {
+                content_filter: Arc::new(ContentFilter::load(source).await?.with_info_policy(InfoPolicy::from_env()?)),
+                ..AppState::in_memory()
+            }
+        }
+        _ => {
+            let redis_client = redis::Client::open(
+                std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string())
+            ).map_err(|e| anyhow::anyhow!(e))?;
+            // One multiplexed, auto-reconnecting connection shared by every handler
+            let redis = redis::aio::ConnectionManager::new(redis_client).await?;
+            let mongodb = MongoDB::new().await?;
 
-    let mongodb = MongoDB::new().await?;
-    
-    let state = Arc::new(Mutex::new(AppState {
-        redis: redis_client,
-        mongodb,
-    }));
+            let source = content_filter::source_from_env(Some(mongodb.clone()))?;
 
-    let bot = Bot::from_env();
-    let state_clone = state.clone();
+            let store = Arc::new(RedisStore::new(redis.clone()));
+            AppState {
+                sessions: store.clone(),
+                rooms: store,
+                profiles: Arc::new(PersistentProfileStore::new(mongodb.clone(), redis)),
+                moderation: Arc::new(mongodb),
+                content_filter: Arc::new(ContentFilter::load(source).await?.with_info_policy(InfoPolicy::from_env()?)),
+                rate_limits: RateLimits::default(),
+                matching: MatchSettings::from_env(),
+            }
+        }
+    };
+    roles::bootstrap(&state, &roles::owner_ids_from_env()?).await?;
+    let state = Arc::new(state);
 
-    let handler = Update::filter_message()
-        .branch(
-            dptree::entry()
-                .filter_command::<Command>()
-                .endpoint(move |bot: Bot, msg: Message, cmd: Command| {
-                    command_handler::handle_command(bot, msg, cmd, state.clone())
-                }),
-        )
-        .branch(
-            dptree::filter(|msg: Message| !msg.text().map(|text| text.starts_with('/')).unwrap_or(false))
-                .endpoint(move |bot: Bot, msg: Message| {
-                    message_handler::handle_message(bot, msg, state_clone.clone())
-                }),
-        );
+    let bot = Bot::from_env();
+    tokio::spawn(matchmaking::run_fallback_matcher(bot.clone(), state.clone()));
+    tokio::spawn(matchmaking::run_search_expiry(bot.clone(), state.clone()));
+    tokio::spawn(inactivity::run_inactivity_reaper(bot.clone(), state.clone()));
 
-    Dispatcher::builder(bot, handler)
+    Dispatcher::builder(bot, handlers::schema(state))
         .enable_ctrlc_handler()
         .build()
         .dispatch()
@@ -1,7 +1,15 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use chrono::{DateTime, Utc};
+use crate::services::{
+    content_filter::ContentFilter,
+    matchmaking::MatchSettings,
+    memory_store::MemoryStore,
+    rate_limit::RateLimits,
+    storage::{ModerationStore, ProfileStore, RoomStore, SessionStore},
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UserProfile {
@@ -23,12 +31,18 @@ pub struct MoodEntry {
 pub struct UserState {
     pub chat_id: i64,
     pub partner_id: Option<i64>,
+    #[serde(default)]
+    pub last_partner_id: Option<i64>,
     pub is_searching: bool,
     pub last_activity: u64,
     pub current_room: Option<String>,
     pub profile: Option<UserProfile>,
-    pub is_admin: bool,
     pub daily_mood: Option<MoodEntry>,
+    #[serde(default)]
+    pub contact_warning_acknowledged: bool,
+    /// Whether the partner sees typing indicators and read receipts from this user.
+    #[serde(default)]
+    pub share_presence: bool,
 }
 
 impl UserState {
@@ -36,6 +50,7 @@ impl UserState {
         Self {
             chat_id,
             partner_id: None,
+            last_partner_id: None,
             is_searching: false,
             last_activity: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
@@ -43,8 +58,9 @@ impl UserState {
                 .as_secs(),
             current_room: None,
             profile: None,
-            is_admin: false,
             daily_mood: None,
+            contact_warning_acknowledged: false,
+            share_presence: false,
         }
     }
 
@@ -63,6 +79,10 @@ impl UserState {
         current_time - self.last_activity > timeout_secs
     }
 
+    pub fn is_available_for_match(&self) -> bool {
+        self.is_searching && self.partner_id.is_none() && self.current_room.is_none()
+    }
+
     pub fn set_profile(&mut self, nickname: String, avatar_emoji: String, bio: String) {
         let now = Utc::now();
         self.profile = Some(UserProfile {
@@ -119,7 +139,183 @@ impl ChatRoom {
     }
 }
 
+/// A relayed message, kept for a while so reports can show what was said.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecentMessage {
+    pub sender_id: i64,
+    pub content: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStatus {
+    Open,
+    Resolved,
+}
+
+/// A user's report of abuse, with the messages leading up to it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Report {
+    pub report_id: String,
+    pub reporter_id: i64,
+    /// The partner in a private chat. Room reports name no one; the
+    /// messages show who said what.
+    pub reported_id: Option<i64>,
+    pub room_id: Option<String>,
+    pub reason: String,
+    /// Oldest first.
+    pub messages: Vec<RecentMessage>,
+    pub status: ReportStatus,
+    pub created_at: DateTime<Utc>,
+    pub resolved_by: Option<i64>,
+    pub resolution: Option<String>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SanctionKind {
+    /// Every update from the user is ignored.
+    Ban,
+    /// The user can use commands but not send messages.
+    Mute,
+    /// The user is only matched with other shadow-banned users, and their
+    /// room messages are silently dropped.
+    Shadowban,
+}
+
+/// A penalty an admin put on a user. Lasts until lifted when `expires_at`
+/// is `None`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Sanction {
+    pub chat_id: i64,
+    pub kind: SanctionKind,
+    pub issued_by: i64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Sanction {
+    pub fn is_active(&self) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > Utc::now())
+    }
+}
+
+/// Staff roles, lowest first. Each role can grant and revoke the roles
+/// below it; owners come from `ADMIN_CHAT_IDS`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Moderator,
+    Admin,
+    Owner,
+}
+
+/// What staff commands need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// List, review and resolve reports.
+    ReviewReports,
+    /// Ban, mute, shadow-ban and unban users.
+    Sanction,
+    ReloadFilter,
+    Broadcast,
+    /// Promote and demote moderators.
+    ManageRoles,
+    /// Promote and demote admins.
+    ManageAdmins,
+}
+
+impl Role {
+    pub fn permissions(self) -> &'static [Permission] {
+        match self {
+            Role::Moderator => &[Permission::ReviewReports, Permission::Sanction],
+            Role::Admin => &[
+                Permission::ReviewReports,
+                Permission::Sanction,
+                Permission::ReloadFilter,
+                Permission::Broadcast,
+                Permission::ManageRoles,
+            ],
+            Role::Owner => &[
+                Permission::ReviewReports,
+                Permission::Sanction,
+                Permission::ReloadFilter,
+                Permission::Broadcast,
+                Permission::ManageRoles,
+                Permission::ManageAdmins,
+            ],
+        }
+    }
+
+    pub fn can(self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+
+    /// Name for messages, e.g. "an admin".
+    pub fn describe(self) -> &'static str {
+        match self {
+            Role::Moderator => "a moderator",
+            Role::Admin => "an admin",
+            Role::Owner => "an owner",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "moderator" | "mod" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            "owner" => Ok(Role::Owner),
+            other => Err(anyhow::anyhow!("Unknown role: {}", other)),
+        }
+    }
+}
+
+/// A user's staff role and who granted it. Owners from `ADMIN_CHAT_IDS`
+/// have no granter.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StaffMember {
+    pub chat_id: i64,
+    pub role: Role,
+    pub granted_by: Option<i64>,
+    pub granted_at: DateTime<Utc>,
+}
+
 pub struct AppState {
-    pub redis: redis::Client,
-    pub mongodb: crate::services::mongodb_service::MongoDB,
+    pub sessions: Arc<dyn SessionStore>,
+    pub rooms: Arc<dyn RoomStore>,
+    pub profiles: Arc<dyn ProfileStore>,
+    pub moderation: Arc<dyn ModerationStore>,
+    pub content_filter: Arc<ContentFilter>,
+    pub rate_limits: RateLimits,
+    pub matching: MatchSettings,
+}
+
+impl AppState {
+    /// State backed entirely by process memory, for tests and local development.
+    pub fn in_memory() -> Self {
+        let store = Arc::new(MemoryStore::new());
+        Self {
+            sessions: store.clone(),
+            rooms: store.clone(),
+            profiles: store.clone(),
+            moderation: store,
+            content_filter: Arc::new(ContentFilter::builtin()),
+            rate_limits: RateLimits::default(),
+            matching: MatchSettings::from_env(),
+        }
+    }
 } 
\ No newline at end of file
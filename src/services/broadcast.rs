@@ -0,0 +1,190 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::{ApiError, RequestError};
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use uuid::Uuid;
+use crate::models::AppState;
+use super::moderation;
+
+/// Telegram allows bots about 30 messages a second across all chats.
+pub const MESSAGES_PER_SECOND: u64 = 30;
+// Tries per recipient, waiting out flood control in between
+const SEND_ATTEMPTS: usize = 3;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A message for every user, sent by a background job.
+#[derive(Debug, Clone)]
+pub struct Broadcast {
+    pub broadcast_id: String,
+    pub admin_id: i64,
+    pub message: String,
+    /// `None` sends right away.
+    pub send_at: Option<DateTime<Utc>>,
+}
+
+impl Broadcast {
+    /// Reads `/broadcast` arguments: the message, optionally after
+    /// `in <duration>` or `at <YYYY-MM-DD HH:MM>` (UTC). Text that only looks
+    /// like a schedule is all message.
+    pub fn parse(admin_id: i64, args: &str) -> Self {
+        let args = args.trim();
+        let (keyword, rest) = split_word(args);
+        let schedule = match keyword {
+            "in" => {
+                let (duration, message) = split_word(rest);
+                moderation::parse_duration(duration)
+                    .and_then(|duration| Utc::now().checked_add_signed(duration))
+                    .map(|send_at| (send_at, message))
+            }
+            "at" => {
+                let (date, rest) = split_word(rest);
+                let (time, message) = split_word(rest);
+                NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H:%M")
+                    .ok()
+                    .map(|send_at| (send_at.and_utc(), message))
+            }
+            _ => None,
+        };
+        let (send_at, message) = match schedule {
+            Some((send_at, message)) => (Some(send_at), message),
+            None => (None, args),
+        };
+        Self {
+            broadcast_id: Uuid::new_v4().simple().to_string()[..8].to_string(),
+            admin_id,
+            message: message.to_string(),
+            send_at,
+        }
+    }
+}
+
+/// Splits off the first word at any whitespace, so a schedule can be
+/// followed by a line break. The rest keeps its own line breaks.
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// How a broadcast went.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BroadcastSummary {
+    pub total: usize,
+    pub delivered: usize,
+    pub failed: usize,
+    /// Recipients who blocked the bot or deleted their account, now left out
+    /// of future broadcasts.
+    pub unreachable: usize,
+}
+
+enum Delivery {
+    Delivered,
+    Failed,
+    Unreachable,
+}
+
+/// Runs the broadcast in the background, at its send time, reporting
+/// progress and a summary to the admin who sent it.
+pub fn start(bot: Bot, state: Arc<AppState>, broadcast: Broadcast) {
+    tokio::spawn(async move {
+        if let Some(send_at) = broadcast.send_at {
+            tokio::time::sleep((send_at - Utc::now()).to_std().unwrap_or_default()).await;
+        }
+
+        let text = match run(&bot, &state, &broadcast).await {
+            Ok(summary) => {
+                log::info!("📢 Broadcast {} finished: {:?}", broadcast.broadcast_id, summary);
+                format!(
+                    "✅ Broadcast #{} finished.\n\n\
+                    📬 Delivered: {}\n\
+                    ❌ Failed: {}\n\
+                    🚫 Blocked the bot: {}",
+                    broadcast.broadcast_id, summary.delivered, summary.failed, summary.unreachable
+                )
+            }
+            Err(e) => {
+                log::error!("❌ Broadcast {} failed: {}", broadcast.broadcast_id, e);
+                format!("❌ Broadcast #{} stopped: {}", broadcast.broadcast_id, e)
+            }
+        };
+        if let Err(e) = bot.send_message(ChatId(broadcast.admin_id), text).await {
+            log::warn!("⚠️ Failed to report broadcast {} to admin {}: {}", broadcast.broadcast_id, broadcast.admin_id, e);
+        }
+    });
+}
+
+async fn run(bot: &Bot, state: &AppState, broadcast: &Broadcast) -> Result<BroadcastSummary> {
+    let recipients: Vec<i64> = state
+        .sessions
+        .reachable_user_ids()
+        .await?
+        .into_iter()
+        .filter(|chat_id| *chat_id != broadcast.admin_id)
+        .collect();
+    let mut summary = BroadcastSummary { total: recipients.len(), ..Default::default() };
+    log::info!("📢 Broadcast {} from admin {} to {} users", broadcast.broadcast_id, broadcast.admin_id, summary.total);
+
+    let admin = ChatId(broadcast.admin_id);
+    let progress = bot
+        .send_message(admin, format!("📢 Broadcast #{}: sending to {} users…", broadcast.broadcast_id, summary.total))
+        .await?;
+    let text = format!("📢 Broadcast Message:\n\n{}", broadcast.message);
+
+    let mut throttle = tokio::time::interval(Duration::from_millis(1000 / MESSAGES_PER_SECOND));
+    throttle.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_progress = Instant::now();
+    for (sent, &chat_id) in recipients.iter().enumerate() {
+        match deliver(bot, &mut throttle, chat_id, &text).await {
+            Delivery::Delivered => summary.delivered += 1,
+            Delivery::Failed => summary.failed += 1,
+            Delivery::Unreachable => match state.sessions.mark_unreachable(chat_id).await {
+                Ok(()) => summary.unreachable += 1,
+                // Counted as failed, since later broadcasts will try them again
+                Err(e) => {
+                    log::warn!("⚠️ Failed to mark user {} unreachable: {}", chat_id, e);
+                    summary.failed += 1;
+                }
+            },
+        }
+
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            let update = format!(
+                "📢 Broadcast #{}: {}/{} sent, {} failed…",
+                broadcast.broadcast_id, sent + 1, summary.total, summary.failed + summary.unreachable
+            );
+            // Progress is best effort; the summary follows either way
+            if let Err(e) = bot.edit_message_text(admin, progress.id, update).await {
+                log::warn!("⚠️ Failed to update broadcast {} progress: {}", broadcast.broadcast_id, e);
+            }
+        }
+    }
+    Ok(summary)
+}
+
+// Sends one copy at the throttled pace, waiting out flood control
+async fn deliver(bot: &Bot, throttle: &mut Interval, chat_id: i64, text: &str) -> Delivery {
+    for _ in 0..SEND_ATTEMPTS {
+        throttle.tick().await;
+        match bot.send_message(ChatId(chat_id), text).await {
+            Ok(_) => return Delivery::Delivered,
+            Err(RequestError::RetryAfter(wait)) => {
+                log::warn!("🐢 Flood control during broadcast, waiting {:?}", wait);
+                tokio::time::sleep(wait).await;
+            }
+            Err(RequestError::Api(
+                ApiError::BotBlocked | ApiError::UserDeactivated | ApiError::CantInitiateConversation,
+            )) => return Delivery::Unreachable,
+            Err(e) => {
+                log::warn!("⚠️ Failed to broadcast to user {}: {}", chat_id, e);
+                return Delivery::Failed;
+            }
+        }
+    }
+    log::warn!("⚠️ Gave up broadcasting to user {} after {} attempts", chat_id, SEND_ATTEMPTS);
+    Delivery::Failed
+}
@@ -1,14 +1,18 @@
 use redis::AsyncCommands;
-use crate::models::{ChatRoom, UserState};
-use anyhow::Result;
+use crate::models::{AppState, ChatRoom, UserState};
+use super::{redis_service::{self, RedisStore}, relay::{self, RelayContent}, storage::RoomStore};
+use async_trait::async_trait;
+use anyhow::{Result, anyhow};
 use uuid::Uuid;
-use teloxide::{prelude::*, types::ChatId};
+use teloxide::prelude::*;
 
 const ROOM_PREFIX: &str = "room:";
 const ROOM_LIST_KEY: &str = "rooms";
+/// Shown in front of every text and caption relayed to a room.
+pub const ROOM_MESSAGE_LABEL: &str = "👤 Anonymous: ";
 
 pub async fn create_room(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
     name: String,
     max_members: usize,
 ) -> Result<ChatRoom> {
@@ -16,31 +20,22 @@ pub async fn create_room(
     let room = ChatRoom::new(room_id.clone(), name, max_members);
     
     let data = serde_json::to_string(&room)?;
-    redis.set(format!("{}{}", ROOM_PREFIX, room_id), data).await?;
-    redis.sadd(ROOM_LIST_KEY, room_id).await?;
+    let _: () = redis.set(format!("{}{}", ROOM_PREFIX, room_id), data).await?;
+    let _: () = redis.sadd(ROOM_LIST_KEY, room_id).await?;
     
     Ok(room)
 }
 
 pub async fn get_room(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
     room_id: &str,
 ) -> Result<Option<ChatRoom>> {
     let data: Option<String> = redis.get(format!("{}{}", ROOM_PREFIX, room_id)).await?;
     Ok(data.map(|d| serde_json::from_str(&d).unwrap()))
 }
 
-pub async fn update_room(
-    redis: &mut redis::aio::Connection,
-    room: &ChatRoom,
-) -> Result<()> {
-    let data = serde_json::to_string(room)?;
-    redis.set(format!("{}{}", ROOM_PREFIX, room.room_id), data).await?;
-    Ok(())
-}
-
 pub async fn list_rooms(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
 ) -> Result<Vec<ChatRoom>> {
     let room_ids: Vec<String> = redis.smembers(ROOM_LIST_KEY).await?;
     let mut rooms = Vec::new();
@@ -55,56 +50,113 @@ pub async fn list_rooms(
 }
 
 pub async fn join_room(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
     room_id: &str,
     user_state: &mut UserState,
 ) -> Result<bool> {
-    if let Some(mut room) = get_room(redis, room_id).await? {
-        if room.add_member(user_state.chat_id) {
+    let key = format!("{}{}", ROOM_PREFIX, room_id);
+
+    // Retry if another member joined or left between our read and write
+    for _ in 0..redis_service::MAX_UPDATE_ATTEMPTS {
+        let raw: Option<String> = redis.get(&key).await?;
+        let Some(raw) = raw else {
+            return Ok(false);
+        };
+        let mut room: ChatRoom = serde_json::from_str(&raw)?;
+
+        // Refuse silently so nobody learns they were blocked
+        for &member_id in &room.members {
+            if redis_service::is_blocked_between(redis, user_state.chat_id, member_id).await? {
+                return Ok(false);
+            }
+        }
+
+        if !room.add_member(user_state.chat_id) {
+            return Ok(false);
+        }
+
+        let data = serde_json::to_string(&room)?;
+        if redis_service::compare_and_set(redis, &key, Some(&raw), Some(&data)).await? {
             user_state.current_room = Some(room_id.to_string());
-            update_room(redis, &room).await?;
+            user_state.update_activity();
             return Ok(true);
         }
     }
-    Ok(false)
+
+    Err(anyhow!("Could not join room {} after {} attempts", room_id, redis_service::MAX_UPDATE_ATTEMPTS))
 }
 
 pub async fn leave_room(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
     room_id: &str,
     user_state: &mut UserState,
 ) -> Result<()> {
-    if let Some(mut room) = get_room(redis, room_id).await? {
+    let key = format!("{}{}", ROOM_PREFIX, room_id);
+    user_state.current_room = None;
+
+    for _ in 0..redis_service::MAX_UPDATE_ATTEMPTS {
+        let raw: Option<String> = redis.get(&key).await?;
+        let Some(raw) = raw else {
+            return Ok(());
+        };
+        let mut room: ChatRoom = serde_json::from_str(&raw)?;
         room.remove_member(user_state.chat_id);
-        user_state.current_room = None;
-        
+
         if room.members.is_empty() {
             // Delete empty room
-            redis.del(format!("{}{}", ROOM_PREFIX, room_id)).await?;
-            redis.srem(ROOM_LIST_KEY, room_id).await?;
+            if redis_service::compare_and_set(redis, &key, Some(&raw), None).await? {
+                let _: () = redis.srem(ROOM_LIST_KEY, room_id).await?;
+                return Ok(());
+            }
         } else {
-            update_room(redis, &room).await?;
+            let data = serde_json::to_string(&room)?;
+            if redis_service::compare_and_set(redis, &key, Some(&raw), Some(&data)).await? {
+                return Ok(());
+            }
         }
     }
-    Ok(())
+
+    Err(anyhow!("Could not leave room {} after {} attempts", room_id, redis_service::MAX_UPDATE_ATTEMPTS))
 }
 
 pub async fn broadcast_to_room(
     bot: &Bot,
-    redis: &mut redis::aio::Connection,
+    state: &AppState,
     room_id: &str,
-    sender_id: i64,
-    message: &str,
+    msg: &Message,
+    content: &RelayContent,
 ) -> Result<()> {
-    if let Some(room) = get_room(redis, room_id).await? {
-        for &member_id in &room.members {
-            if member_id != sender_id {
-                bot.send_message(
-                    ChatId(member_id),
-                    format!("👤 Anonymous: {}", message)
-                ).await?;
-            }
-        }
+    if let Some(room) = state.rooms.get_room(room_id).await? {
+        let recipients: Vec<i64> = room
+            .members
+            .into_iter()
+            .filter(|&member_id| member_id != msg.chat.id.0)
+            .collect();
+        let content = content.clone().labelled(ROOM_MESSAGE_LABEL);
+        relay::relay_message(bot, state.sessions.as_ref(), msg, &recipients, &content).await?;
     }
     Ok(())
-} 
\ No newline at end of file
+}
+
+#[async_trait]
+impl RoomStore for RedisStore {
+    async fn create_room(&self, name: String, max_members: usize) -> Result<ChatRoom> {
+        create_room(&mut self.connection(), name, max_members).await
+    }
+
+    async fn get_room(&self, room_id: &str) -> Result<Option<ChatRoom>> {
+        get_room(&mut self.connection(), room_id).await
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<ChatRoom>> {
+        list_rooms(&mut self.connection()).await
+    }
+
+    async fn join_room(&self, room_id: &str, user_state: &mut UserState) -> Result<bool> {
+        join_room(&mut self.connection(), room_id, user_state).await
+    }
+
+    async fn leave_room(&self, room_id: &str, user_state: &mut UserState) -> Result<()> {
+        leave_room(&mut self.connection(), room_id, user_state).await
+    }
+}
@@ -1,36 +1,300 @@
-use std::collections::HashSet;
-use once_cell::sync::Lazy;
-
-static INAPPROPRIATE_WORDS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
-    let mut set = HashSet::new();
-    // Common inappropriate words (this is a basic list, expand as needed)
-    set.insert("anjing");
-    set.insert("babi");
-    set.insert("bangsat");
-    set.insert("kontol");
-    set.insert("memek");
-    set.insert("ngentot");
-    set.insert("jancok");
-    set.insert("fuck");
-    set.insert("shit");
-    set.insert("dick");
-    set.insert("bitch");
-    set.insert("bastard");
-    set.insert("asshole");
-    // Add more words as needed
-    set
-});
-
-pub fn contains_inappropriate_content(text: &str) -> bool {
-    let text_lower = text.to_lowercase();
-    INAPPROPRIATE_WORDS.iter().any(|&word| text_lower.contains(word))
-}
-
-pub fn filter_message(text: &str) -> String {
-    let mut filtered_text = text.to_string();
-    for word in INAPPROPRIATE_WORDS.iter() {
-        let replacement = "*".repeat(word.len());
-        filtered_text = filtered_text.replace(word, &replacement);
-    }
-    filtered_text
-} 
\ No newline at end of file
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use super::mongodb_service::MongoDB;
+use super::info_detector::{InfoAction, InfoKind, InfoPolicy};
+use super::word_matcher::WordMatcher;
+
+/// Words listed under this language apply to messages in every language.
+pub const ANY_LANGUAGE: &str = "*";
+
+/// What happens to a message containing a listed word, mildest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The word is masked and the message goes through.
+    Censor,
+    /// The message is not sent.
+    Block,
+    /// The message is not sent and the sender is flagged for moderators.
+    Flag,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterWord {
+    pub word: String,
+    pub language: String,
+    pub severity: Severity,
+}
+
+/// Outcome of running user text through the filter, mildest first.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict<T> {
+    /// Send this, with censored words and masked info hidden.
+    Allow(T),
+    /// Send this, and warn the sender that it contains `kind`.
+    Warn { content: T, kind: InfoKind },
+    /// Do not send it, because it contains `kind`.
+    Withhold { kind: InfoKind },
+    Block,
+    /// Block, and flag the sender for using `word`.
+    Flag { word: String },
+}
+
+/// Where the word lists come from.
+#[async_trait]
+pub trait WordListSource: Send + Sync {
+    fn describe(&self) -> String;
+    async fn load(&self) -> Result<Vec<FilterWord>>;
+}
+
+/// The word list that ships with the bot.
+pub struct BuiltinWords;
+
+const BUILTIN_WORDS: &[&str] = &[
+    "anjing", "babi", "bangsat", "kontol*", "memek*", "ngentot*", "jancok*",
+    "*fuck*", "shit*", "*shit", "dick", "bitch*", "bastard*", "asshole*",
+];
+
+#[async_trait]
+impl WordListSource for BuiltinWords {
+    fn describe(&self) -> String {
+        "built-in list".to_string()
+    }
+
+    async fn load(&self) -> Result<Vec<FilterWord>> {
+        Ok(builtin_words())
+    }
+}
+
+/// The words of the built-in list, all blocked in every language.
+pub fn builtin_words() -> Vec<FilterWord> {
+    BUILTIN_WORDS
+        .iter()
+        .map(|word| FilterWord {
+            word: word.to_string(),
+            language: ANY_LANGUAGE.to_string(),
+            severity: Severity::Block,
+        })
+        .collect()
+}
+
+/// A fixed word list, handy for tests.
+pub struct StaticWords(pub Vec<FilterWord>);
+
+#[async_trait]
+impl WordListSource for StaticWords {
+    fn describe(&self) -> String {
+        "static list".to_string()
+    }
+
+    async fn load(&self) -> Result<Vec<FilterWord>> {
+        Ok(self.0.clone())
+    }
+}
+
+/// A JSON file mapping language to word to severity, e.g.
+/// `{"*": {"fuck": "block"}, "id": {"anjing": "flag"}, "en": {"damn": "censor"}}`.
+/// Re-read on every reload.
+pub struct WordListFile {
+    path: String,
+}
+
+impl WordListFile {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl WordListSource for WordListFile {
+    fn describe(&self) -> String {
+        format!("file {}", self.path)
+    }
+
+    async fn load(&self) -> Result<Vec<FilterWord>> {
+        let data = tokio::fs::read_to_string(&self.path).await?;
+        let lists: HashMap<String, HashMap<String, Severity>> = serde_json::from_str(&data)?;
+        Ok(lists
+            .into_iter()
+            .flat_map(|(language, words)| {
+                words.into_iter().map(move |(word, severity)| FilterWord {
+                    word,
+                    language: language.clone(),
+                    severity,
+                })
+            })
+            .collect())
+    }
+}
+
+/// Picks the word list source named by `CONTENT_FILTER_SOURCE`: `builtin`
+/// (the default), `file` (reads `CONTENT_FILTER_FILE`) or `mongodb`.
+pub fn source_from_env(mongodb: Option<MongoDB>) -> Result<Box<dyn WordListSource>> {
+    match std::env::var("CONTENT_FILTER_SOURCE").as_deref() {
+        Err(_) | Ok("builtin") => Ok(Box::new(BuiltinWords)),
+        Ok("file") => {
+            let path = std::env::var("CONTENT_FILTER_FILE")
+                .map_err(|_| anyhow!("CONTENT_FILTER_SOURCE=file needs CONTENT_FILTER_FILE"))?;
+            Ok(Box::new(WordListFile::new(path)))
+        }
+        Ok("mongodb") => match mongodb {
+            Some(mongodb) => Ok(Box::new(mongodb)),
+            None => Err(anyhow!("CONTENT_FILTER_SOURCE=mongodb needs the MongoDB storage backend")),
+        },
+        Ok(other) => Err(anyhow!("Unknown CONTENT_FILTER_SOURCE: {}", other)),
+    }
+}
+
+struct ListedWord {
+    word: String,
+    language: String,
+    severity: Severity,
+}
+
+struct WordLists {
+    matcher: WordMatcher<ListedWord>,
+}
+
+impl WordLists {
+    fn new(words: Vec<FilterWord>) -> Self {
+        let matcher = WordMatcher::new(words.into_iter().map(|entry| {
+            let word = entry.word.trim().to_lowercase();
+            let listed = ListedWord {
+                word: word.clone(),
+                language: entry.language.trim().to_lowercase(),
+                severity: entry.severity,
+            };
+            (word, listed)
+        }));
+        Self { matcher }
+    }
+}
+
+impl Default for WordLists {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+/// Word and personal info filter for user text. The word lists can be
+/// reloaded from their source while the bot runs.
+pub struct ContentFilter {
+    source: Box<dyn WordListSource>,
+    lists: RwLock<Arc<WordLists>>,
+    info: InfoPolicy,
+}
+
+impl ContentFilter {
+    pub async fn load(source: Box<dyn WordListSource>) -> Result<Self> {
+        let filter = Self {
+            source,
+            lists: RwLock::new(Arc::default()),
+            info: InfoPolicy::default(),
+        };
+        let count = filter.reload().await?;
+        log::info!("🛡️ Loaded {} filtered words from {}", count, filter.source.describe());
+        Ok(filter)
+    }
+
+    /// A filter over the built-in word list.
+    pub fn builtin() -> Self {
+        Self {
+            source: Box::new(BuiltinWords),
+            lists: RwLock::new(Arc::new(WordLists::new(builtin_words()))),
+            info: InfoPolicy::default(),
+        }
+    }
+
+    /// A filter over a fixed word list.
+    pub fn with_words(words: Vec<FilterWord>) -> Self {
+        Self {
+            lists: RwLock::new(Arc::new(WordLists::new(words.clone()))),
+            source: Box::new(StaticWords(words)),
+            info: InfoPolicy::default(),
+        }
+    }
+
+    /// Replaces what is done with links and personal info.
+    pub fn with_info_policy(mut self, info: InfoPolicy) -> Self {
+        self.info = info;
+        self
+    }
+
+    pub fn source(&self) -> String {
+        self.source.describe()
+    }
+
+    /// Re-reads the word lists, keeping the current ones if that fails.
+    /// Returns the number of words loaded.
+    pub async fn reload(&self) -> Result<usize> {
+        let words = self.source.load().await?;
+        let count = words.len();
+        let lists = Arc::new(WordLists::new(words));
+        *self.lists.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = lists;
+        Ok(count)
+    }
+
+    /// Number of filtered words per language.
+    pub fn word_counts(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for listed in self.current().matcher.values() {
+            *counts.entry(listed.language.clone()).or_default() += 1;
+        }
+        counts
+    }
+
+    fn current(&self) -> Arc<WordLists> {
+        self.lists.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// Checks `text` against the words for `language` and for every language;
+    /// all words when the language is unknown.
+    pub fn check(&self, text: &str, language: Option<&str>) -> Verdict<String> {
+        let lists = self.current();
+        let mut masked: Vec<char> = text.chars().collect();
+        let mut worst: Option<&ListedWord> = None;
+        for found in lists.matcher.find(text) {
+            let listed = found.value;
+            let applies = match language {
+                Some(language) => listed.language == ANY_LANGUAGE || listed.language == language,
+                None => true,
+            };
+            if !applies {
+                continue;
+            }
+            masked[found.chars].fill('*');
+            if worst.is_none_or(|worst| listed.severity > worst.severity) {
+                worst = Some(listed);
+            }
+        }
+
+        match worst {
+            Some(ListedWord { severity: Severity::Flag, word, .. }) => Verdict::Flag { word: word.clone() },
+            Some(ListedWord { severity: Severity::Block, .. }) => Verdict::Block,
+            _ => self.check_info(masked.into_iter().collect()),
+        }
+    }
+
+    fn check_info(&self, mut text: String) -> Verdict<String> {
+        let mut warned = None;
+        // Back to front, so masking keeps earlier ranges valid
+        for (kind, range) in self.info.detect(&text).into_iter().rev() {
+            match self.info.action(kind) {
+                InfoAction::Allow => {}
+                InfoAction::Mask => {
+                    let mask = "*".repeat(text[range.clone()].chars().count());
+                    text.replace_range(range, &mask);
+                }
+                InfoAction::Warn => warned = Some(kind),
+                InfoAction::Block => return Verdict::Withhold { kind },
+            }
+        }
+        match warned {
+            Some(kind) => Verdict::Warn { content: text, kind },
+            None => Verdict::Allow(text),
+        }
+    }
+}
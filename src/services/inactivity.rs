@@ -0,0 +1,74 @@
+use anyhow::Result;
+use std::time::Duration;
+use teloxide::prelude::*;
+use std::sync::Arc;
+use crate::models::{AppState, UserState};
+
+pub const INACTIVITY_TIMEOUT: u64 = 1800; // 30 minutes in seconds
+const REAPER_INTERVAL_SECS: u64 = 60;
+
+/// Removes an idle user from their private chat or room and notifies everyone involved.
+pub async fn disconnect_inactive_user(
+    bot: &Bot,
+    app_state: &AppState,
+    mut state: UserState,
+) -> Result<()> {
+    let chat_id = state.chat_id;
+
+    if let Some(partner_id) = state.partner_id {
+        app_state.sessions.end_private_chat(chat_id, partner_id).await?;
+        bot.send_message(
+            ChatId(partner_id),
+            "⏰ Your chat partner has been disconnected due to inactivity.\n\
+            Use /find to start a new chat!"
+        ).await?;
+    } else if let Some(room_id) = state.current_room.clone() {
+        app_state.rooms.leave_room(&room_id, &mut state).await?;
+        app_state.sessions.update_user_state(chat_id, &|user_state| user_state.current_room = None).await?;
+        if let Some(room) = app_state.rooms.get_room(&room_id).await? {
+            for &member_id in &room.members {
+                bot.send_message(
+                    ChatId(member_id),
+                    "⏰ A user has left the chat room due to inactivity."
+                ).await?;
+            }
+        }
+    } else {
+        return Ok(());
+    }
+
+    log::info!("⏰ Disconnected user {} due to inactivity", chat_id);
+    bot.send_message(
+        ChatId(chat_id),
+        "⏰ You have been disconnected due to inactivity.\n\
+        Use /find to start a new chat!"
+    ).await?;
+    Ok(())
+}
+
+/// Periodically disconnects users idle past `INACTIVITY_TIMEOUT`, so their
+/// partner or room isn't left waiting until they send another message.
+pub async fn run_inactivity_reaper(bot: Bot, app_state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(REAPER_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        if let Err(e) = reap_inactive_users(&bot, &app_state).await {
+            log::error!("❌ Inactivity reaper failed: {}", e);
+        }
+    }
+}
+
+/// One sweep of the inactivity reaper.
+pub async fn reap_inactive_users(bot: &Bot, app_state: &AppState) -> Result<()> {
+    for chat_id in app_state.sessions.get_idle_users(INACTIVITY_TIMEOUT).await? {
+        // The idle list may lag behind the user's state; trust the state
+        if let Some(state) = app_state.sessions.get_user_state(chat_id).await? {
+            if state.is_inactive(INACTIVITY_TIMEOUT) {
+                if let Err(e) = disconnect_inactive_user(bot, app_state, state).await {
+                    log::error!("❌ Failed to disconnect inactive user {}: {}", chat_id, e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Links and personal info that can be spotted in user text, in the order
+/// they are looked for: an email address is not also a link and a username.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InfoKind {
+    /// t.me links to groups, channels and invites.
+    Invite,
+    Email,
+    Link,
+    /// Telegram @usernames.
+    Username,
+    NationalId,
+    Phone,
+}
+
+impl InfoKind {
+    pub const ALL: [InfoKind; 6] = [
+        InfoKind::Invite,
+        InfoKind::Email,
+        InfoKind::Link,
+        InfoKind::Username,
+        InfoKind::NationalId,
+        InfoKind::Phone,
+    ];
+
+    /// Name for user-facing notices, e.g. "a phone number".
+    pub fn describe(self) -> &'static str {
+        match self {
+            InfoKind::Invite => "a Telegram invite",
+            InfoKind::Email => "an email address",
+            InfoKind::Link => "a link",
+            InfoKind::Username => "a username",
+            InfoKind::NationalId => "an ID number",
+            InfoKind::Phone => "a phone number",
+        }
+    }
+
+    fn env_var(self) -> &'static str {
+        match self {
+            InfoKind::Invite => "FILTER_INVITES",
+            InfoKind::Email => "FILTER_EMAILS",
+            InfoKind::Link => "FILTER_LINKS",
+            InfoKind::Username => "FILTER_USERNAMES",
+            InfoKind::NationalId => "FILTER_NATIONAL_IDS",
+            InfoKind::Phone => "FILTER_PHONES",
+        }
+    }
+
+    fn default_action(self) -> InfoAction {
+        match self {
+            InfoKind::Invite | InfoKind::NationalId => InfoAction::Block,
+            InfoKind::Email | InfoKind::Phone => InfoAction::Mask,
+            InfoKind::Link | InfoKind::Username => InfoAction::Warn,
+        }
+    }
+
+    fn pattern(self) -> &'static Regex {
+        static INVITE: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"(?i)\b(?:https?://)?(?:t|telegram)\.(?:me|dog)/[\w+/-]+").unwrap()
+        });
+        static EMAIL: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"(?i)[\w.+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}").unwrap()
+        });
+        static LINK: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(
+                r"(?i)\b(?:https?://|www\.)\S+|\b[a-z0-9-]+(?:\.[a-z0-9-]+)*\.(?:com|net|org|io|me|co|ly|gg|id|ru|xyz|info|biz|app|dev|link|site|online|club|shop)\b(?:/\S*)?",
+            )
+            .unwrap()
+        });
+        static USERNAME: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"@[A-Za-z][A-Za-z0-9_]{4,31}\b").unwrap());
+        // Indonesian NIK and US SSN
+        static NATIONAL_ID: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"\b(?:\d{16}|\d{3}-\d{2}-\d{4})\b").unwrap());
+        static PHONE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\+?\d[\d ().-]{7,}\d").unwrap());
+
+        match self {
+            InfoKind::Invite => &INVITE,
+            InfoKind::Email => &EMAIL,
+            InfoKind::Link => &LINK,
+            InfoKind::Username => &USERNAME,
+            InfoKind::NationalId => &NATIONAL_ID,
+            InfoKind::Phone => &PHONE,
+        }
+    }
+
+    fn accepts(self, found: &str) -> bool {
+        match self {
+            // Long enough to dial, short enough not to be an ID
+            InfoKind::Phone => (9..=15).contains(&found.chars().filter(char::is_ascii_digit).count()),
+            _ => true,
+        }
+    }
+}
+
+/// What to do with a message containing a kind of info, mildest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InfoAction {
+    Allow,
+    /// Masked before the message is relayed.
+    Mask,
+    /// Relayed as is, with a warning to the sender.
+    Warn,
+    /// Not relayed.
+    Block,
+}
+
+impl std::str::FromStr for InfoAction {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "allow" => Ok(InfoAction::Allow),
+            "mask" => Ok(InfoAction::Mask),
+            "warn" => Ok(InfoAction::Warn),
+            "block" => Ok(InfoAction::Block),
+            other => Err(anyhow!("Unknown info action: {}", other)),
+        }
+    }
+}
+
+/// Which action applies to each kind of info.
+#[derive(Debug, Clone)]
+pub struct InfoPolicy {
+    actions: BTreeMap<InfoKind, InfoAction>,
+}
+
+impl Default for InfoPolicy {
+    fn default() -> Self {
+        Self {
+            actions: InfoKind::ALL.iter().map(|kind| (*kind, kind.default_action())).collect(),
+        }
+    }
+}
+
+impl InfoPolicy {
+    /// The default policy with overrides from `FILTER_LINKS`, `FILTER_USERNAMES`,
+    /// `FILTER_INVITES`, `FILTER_PHONES`, `FILTER_EMAILS` and
+    /// `FILTER_NATIONAL_IDS`, each one of `allow`, `mask`, `warn` or `block`.
+    pub fn from_env() -> Result<Self> {
+        let mut policy = Self::default();
+        for kind in InfoKind::ALL {
+            if let Ok(value) = std::env::var(kind.env_var()) {
+                let action = value
+                    .parse()
+                    .map_err(|e| anyhow!("{}: {}", kind.env_var(), e))?;
+                policy.actions.insert(kind, action);
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Every kind of info allowed.
+    pub fn allow_all() -> Self {
+        Self {
+            actions: InfoKind::ALL.iter().map(|kind| (*kind, InfoAction::Allow)).collect(),
+        }
+    }
+
+    pub fn with(mut self, kind: InfoKind, action: InfoAction) -> Self {
+        self.actions.insert(kind, action);
+        self
+    }
+
+    pub fn action(&self, kind: InfoKind) -> InfoAction {
+        self.actions.get(&kind).copied().unwrap_or(InfoAction::Allow)
+    }
+
+    /// Info in `text` that the policy does not simply allow, as byte ranges
+    /// in text order. Overlaps go to the kind looked for first.
+    pub fn detect(&self, text: &str) -> Vec<(InfoKind, Range<usize>)> {
+        let mut found: Vec<(InfoKind, Range<usize>)> = Vec::new();
+        for kind in InfoKind::ALL {
+            for candidate in kind.pattern().find_iter(text) {
+                let range = candidate.range();
+                let overlaps = found
+                    .iter()
+                    .any(|(_, taken)| range.start < taken.end && taken.start < range.end);
+                if !overlaps && kind.accepts(candidate.as_str()) {
+                    found.push((kind, range));
+                }
+            }
+        }
+        found.retain(|(kind, _)| self.action(*kind) != InfoAction::Allow);
+        found.sort_by_key(|(_, range)| range.start);
+        found
+    }
+}
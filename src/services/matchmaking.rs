@@ -0,0 +1,228 @@
+use anyhow::Result;
+use std::time::Duration;
+use teloxide::prelude::*;
+use std::sync::Arc;
+use crate::{models::AppState, services::storage::SessionStore};
+
+const MAX_INTEREST_TAGS: usize = 5;
+const FALLBACK_SWEEP_INTERVAL_SECS: u64 = 15;
+const SEARCH_EXPIRY_INTERVAL_SECS: u64 = 60;
+/// How long a user's /language choice is cached in the session store.
+pub const LANGUAGE_CACHE_SECS: u64 = 60 * 60;
+
+/// Sent to the partner of a user who left a private chat, whatever the reason.
+pub const PARTNER_LEFT_MESSAGE: &str = "👋 Your chat partner has left the chat.\n\
+    Use /find to start a new chat!";
+
+/// How long searches wait before matchmaking relaxes, and before they expire.
+#[derive(Debug, Clone)]
+pub struct MatchSettings {
+    /// How long a user waits for a shared-interest partner before anyone will do.
+    pub fallback_secs: u64,
+    /// How long a user waits for a partner speaking their language before any language will do.
+    pub language_fallback_secs: u64,
+    /// How long a user may stay idle in the search queue before their search expires.
+    pub search_timeout_secs: u64,
+}
+
+impl MatchSettings {
+    /// Reads `MATCH_FALLBACK_SECS`, `MATCH_LANGUAGE_FALLBACK_SECS` and
+    /// `SEARCH_TIMEOUT_SECS`, defaulting to 60, 120 and 600 seconds.
+    pub fn from_env() -> Self {
+        let secs = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            fallback_secs: secs("MATCH_FALLBACK_SECS", 60),
+            language_fallback_secs: secs("MATCH_LANGUAGE_FALLBACK_SECS", 120),
+            search_timeout_secs: secs("SEARCH_TIMEOUT_SECS", 600),
+        }
+    }
+}
+
+pub enum MatchOutcome {
+    Matched { partner_id: i64, shared_tags: Vec<String> },
+    Waiting,
+    NotSearching,
+}
+
+/// Normalizes `/find` arguments into lowercase tags, e.g. "#Music gaming" -> ["music", "gaming"].
+pub fn parse_interest_tags(input: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for word in input.split_whitespace() {
+        let tag = word.trim_start_matches('#').to_lowercase();
+        if !tag.is_empty() && tag.chars().all(char::is_alphanumeric) && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags.truncate(MAX_INTEREST_TAGS);
+    tags
+}
+
+/// Reduces a Telegram language code such as "en-US" to its primary language, e.g. "en".
+pub fn normalize_language(code: &str) -> Option<String> {
+    let primary = code.split(['-', '_']).next()?.to_lowercase();
+    if (2..=3).contains(&primary.len()) && primary.chars().all(|c| c.is_ascii_lowercase()) {
+        Some(primary)
+    } else {
+        None
+    }
+}
+
+/// The language the user chats in: their /language choice, or else their
+/// Telegram client's.
+pub async fn effective_language(state: &AppState, msg: &Message) -> Option<String> {
+    let chat_id = msg.chat.id.0;
+    let saved_language = saved_language(state, chat_id).await.unwrap_or_else(|e| {
+        log::error!("❌ Failed to load language for user {}: {}", chat_id, e);
+        None
+    });
+    saved_language.or_else(|| {
+        msg.from()
+            .and_then(|user| user.language_code.as_deref())
+            .and_then(normalize_language)
+    })
+}
+
+/// The user's /language choice, from the session cache when it is there so
+/// relaying a message does not cost a profile lookup.
+async fn saved_language(state: &AppState, chat_id: i64) -> Result<Option<String>> {
+    if let Some(cached) = state.sessions.get_cached_language(chat_id).await? {
+        return Ok(cached);
+    }
+    let language = state.profiles.get_language(chat_id).await?;
+    state.sessions.cache_language(chat_id, language.as_deref(), LANGUAGE_CACHE_SECS).await?;
+    Ok(language)
+}
+
+/// Pairs a searching user with the best waiting partner, or leaves them in the queue.
+pub async fn find_match(
+    sessions: &dyn SessionStore,
+    settings: &MatchSettings,
+    chat_id: i64,
+) -> Result<MatchOutcome> {
+    loop {
+        match sessions.get_user_state(chat_id).await? {
+            Some(state) if state.is_available_for_match() => {}
+            _ => {
+                sessions.remove_from_search_queue(chat_id).await?;
+                return Ok(MatchOutcome::NotSearching);
+            }
+        }
+
+        let Some((partner_id, partner_since)) = sessions.find_random_partner(
+            chat_id,
+            settings.fallback_secs,
+            settings.language_fallback_secs,
+        ).await? else {
+            return Ok(MatchOutcome::Waiting);
+        };
+
+        if sessions.connect_users(chat_id, partner_id).await? {
+            sessions.remove_from_search_queue(chat_id).await?;
+            let shared_tags = sessions.get_shared_tags(chat_id, partner_id).await?;
+            return Ok(MatchOutcome::Matched { partner_id, shared_tags });
+        }
+
+        // The partner was popped from the queue; put them back, keeping
+        // their place, if only our side was taken
+        if let Some(partner_state) = sessions.get_user_state(partner_id).await? {
+            if partner_state.is_available_for_match() {
+                sessions.add_to_search_queue(partner_id, partner_since).await?;
+            }
+        }
+    }
+}
+
+pub async fn announce_match(
+    bot: &Bot,
+    user1_id: i64,
+    user2_id: i64,
+    shared_tags: &[String],
+) -> Result<()> {
+    let shared = if shared_tags.is_empty() {
+        String::new()
+    } else {
+        format!("🏷️ Shared interests: {}\n\n", shared_tags.join(", "))
+    };
+    let match_message = format!("🎉 Chat partner found! Say hi! 👋\n\
+        {}You can send:\n\
+        • Text messages 💬\n\
+        • Photos, videos & GIFs 📸\n\
+        • Stickers 🎯\n\
+        • Voice & video notes 🎤\n\
+        • Files & music 📎\n\
+        • Locations, polls & dice 📍\n\n\
+        Use /leave when you want to end the chat.", shared);
+
+    bot.send_message(ChatId(user1_id), &match_message).await?;
+    bot.send_message(ChatId(user2_id), &match_message).await?;
+    Ok(())
+}
+
+/// Periodically retries users who have waited past a fallback time, so two
+/// queued users without shared interests or a shared language still get
+/// paired with each other.
+pub async fn run_fallback_matcher(bot: Bot, state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(FALLBACK_SWEEP_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        if let Err(e) = match_waiting_users(&bot, &state).await {
+            log::error!("❌ Fallback matchmaking failed: {}", e);
+        }
+    }
+}
+
+/// One sweep of the fallback matcher.
+pub async fn match_waiting_users(bot: &Bot, state: &AppState) -> Result<()> {
+    let settings = &state.matching;
+    let min_wait_secs = settings.fallback_secs.min(settings.language_fallback_secs);
+    for chat_id in state.sessions.get_waiting_users(min_wait_secs).await? {
+        // One user who blocked the bot must not hold up everyone else
+        if let Err(e) = match_waiting_user(bot, state, chat_id).await {
+            log::error!("❌ Failed to match waiting user {}: {}", chat_id, e);
+        }
+    }
+    Ok(())
+}
+
+async fn match_waiting_user(bot: &Bot, state: &AppState, chat_id: i64) -> Result<()> {
+    if let MatchOutcome::Matched { partner_id, shared_tags } = find_match(state.sessions.as_ref(), &state.matching, chat_id).await? {
+        log::info!("🔀 Fallback matched user {} with {}", chat_id, partner_id);
+        announce_match(bot, chat_id, partner_id, &shared_tags).await?;
+    }
+    Ok(())
+}
+
+/// Periodically ends searches that have been idle past the search timeout,
+/// so users who went offline don't linger in the queue.
+pub async fn run_search_expiry(bot: Bot, state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(SEARCH_EXPIRY_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        if let Err(e) = expire_stale_searches(&bot, &state).await {
+            log::error!("❌ Search expiry failed: {}", e);
+        }
+    }
+}
+
+/// One sweep of the search expiry.
+pub async fn expire_stale_searches(bot: &Bot, state: &AppState) -> Result<()> {
+    let timeout_secs = state.matching.search_timeout_secs;
+    for chat_id in state.sessions.get_waiting_users(timeout_secs).await? {
+        if state.sessions.expire_search(chat_id, timeout_secs).await? {
+            log::info!("⌛ Search expired for user {}", chat_id);
+            let notice = bot.send_message(
+                ChatId(chat_id),
+                "⌛ No partner found this time. Use /find to try again!"
+            ).await;
+            if let Err(e) = notice {
+                log::warn!("⚠️ Failed to tell user {} their search expired: {}", chat_id, e);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,698 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+use chrono::Utc;
+use crate::models::{
+    ChatRoom, MoodEntry, RecentMessage, Report, ReportStatus, Role, Sanction, SanctionKind, StaffMember, UserProfile,
+    UserState,
+};
+use super::{
+    redis_service::{MATCH_CANDIDATE_WINDOW, MESSAGE_LINK_TTL_SECS, RECENT_MESSAGE_LIMIT},
+    storage::{ModerationStore, ProfileStore, RoomStore, SessionStore, StateUpdate},
+};
+
+const MOOD_HISTORY_LIMIT: usize = 30;
+
+/// In-process storage for tests and local development (`STORAGE_BACKEND=memory`).
+/// Mirrors the Redis/MongoDB behaviour, including matchmaking rules; nothing
+/// survives a restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    inner: Mutex<Inner>,
+}
+
+struct MessageLink {
+    copies: Arc<HashMap<i64, i32>>,
+    expires_at: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    users: HashMap<i64, UserState>,
+    // (chat_id, waiting since), longest-waiting first
+    search_queue: Vec<(i64, u64)>,
+    search_tags: HashMap<i64, HashSet<String>>,
+    search_languages: HashMap<i64, String>,
+    blocked: HashMap<i64, HashSet<i64>>,
+    // (chat_id, message_id) -> every copy of that message, by chat_id
+    message_links: HashMap<(i64, i32), MessageLink>,
+    // (sender_id, recipient_id) -> message_id
+    unseen_messages: HashMap<(i64, i64), i32>,
+    rooms: HashMap<String, ChatRoom>,
+    profiles: HashMap<i64, UserProfile>,
+    interests: HashMap<i64, Vec<String>>,
+    languages: HashMap<i64, String>,
+    // Newest first
+    mood_history: HashMap<i64, Vec<MoodEntry>>,
+    mood_stats: HashMap<String, i32>,
+    // chat_id -> reasons, oldest first
+    flags: HashMap<i64, Vec<String>>,
+    // key -> (tokens, last refill in ms)
+    rate_buckets: HashMap<String, (f64, u64)>,
+    // chat_id -> (strikes, window end)
+    rate_strikes: HashMap<i64, (u32, u64)>,
+    // chat_id -> muted until
+    flood_mutes: HashMap<i64, u64>,
+    // conversation -> messages, oldest first
+    recent_messages: HashMap<String, Vec<RecentMessage>>,
+    // Filing order
+    reports: Vec<Report>,
+    // chat_id -> shadow-banned until
+    shadowbans: HashMap<i64, u64>,
+    sanctions: Vec<Sanction>,
+    // chat_id -> (sanctions, cached until)
+    sanction_cache: HashMap<i64, (Vec<Sanction>, u64)>,
+    // chat_id -> (language, cached until)
+    language_cache: HashMap<i64, (Option<String>, u64)>,
+    staff: HashMap<i64, StaffMember>,
+    unreachable: HashSet<i64>,
+    // chat_id -> pseudonym
+    pseudonyms: HashMap<i64, String>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Inner {
+    fn is_blocked_between(&self, user1_id: i64, user2_id: i64) -> bool {
+        let blocked_by = |user_id: i64, other_id: i64| {
+            self.blocked.get(&user_id).is_some_and(|blocked| blocked.contains(&other_id))
+        };
+        blocked_by(user1_id, user2_id) || blocked_by(user2_id, user1_id)
+    }
+
+    fn remove_from_queue(&mut self, chat_id: i64) -> Option<u64> {
+        let position = self.search_queue.iter().position(|&(id, _)| id == chat_id)?;
+        Some(self.search_queue.remove(position).1)
+    }
+
+    fn enqueue(&mut self, chat_id: i64, since: u64) {
+        self.remove_from_queue(chat_id);
+        let position = self.search_queue.partition_point(|&(_, queued_since)| queued_since <= since);
+        self.search_queue.insert(position, (chat_id, since));
+    }
+
+    // Same rules as the Redis matchmaking script; tests/matching_rules.rs
+    // holds both to them
+    fn is_shadowbanned(&self, chat_id: i64, now: u64) -> bool {
+        self.shadowbans.get(&chat_id).is_some_and(|until| *until > now)
+    }
+
+    fn pick_partner(&mut self, chat_id: i64, now: u64, fallback: u64, lang_fallback: u64) -> Option<(i64, u64)> {
+        let me_shadowbanned = self.is_shadowbanned(chat_id, now);
+        let my_since = match self.search_queue.iter().find(|&&(id, _)| id == chat_id) {
+            Some(&(_, since)) => since,
+            None => {
+                self.enqueue(chat_id, now);
+                now
+            }
+        };
+        let no_tags = HashSet::new();
+        let my_tags = self.search_tags.get(&chat_id).unwrap_or(&no_tags);
+        let me_flexible = my_tags.is_empty() || now - my_since >= fallback;
+        let my_lang = self.search_languages.get(&chat_id);
+        let me_any_lang = now - my_since >= lang_fallback;
+
+        let mut best: Option<(i64, u64, usize)> = None;
+        for &(candidate, since) in self.search_queue.iter().take(MATCH_CANDIDATE_WINDOW) {
+            if candidate == chat_id {
+                continue;
+            }
+            let their_tags = self.search_tags.get(&candidate).unwrap_or(&no_tags);
+            let shared = my_tags.intersection(their_tags).count();
+            let tags_ok = shared > 0
+                || (me_flexible && (their_tags.is_empty() || now - since >= fallback));
+
+            let their_lang = self.search_languages.get(&candidate);
+            let same_lang = my_lang.is_some() && my_lang == their_lang;
+            let lang_ok = same_lang
+                || my_lang.is_none()
+                || their_lang.is_none()
+                || (me_any_lang && now - since >= lang_fallback);
+
+            let score = shared + if same_lang { 1000 } else { 0 };
+            if tags_ok && lang_ok && !self.is_blocked_between(chat_id, candidate)
+                && self.is_shadowbanned(candidate, now) == me_shadowbanned
+                && best.is_none_or(|(_, _, best_score)| score > best_score)
+            {
+                best = Some((candidate, since, score));
+            }
+        }
+
+        let (partner_id, since, _) = best?;
+        self.remove_from_queue(partner_id);
+        Some((partner_id, since))
+    }
+
+    fn stop_searching_if(&mut self, chat_id: i64, should_stop: impl Fn(&UserState) -> bool) -> bool {
+        let stopped = match self.users.get_mut(&chat_id) {
+            Some(state) if state.is_available_for_match() => {
+                if !should_stop(state) {
+                    return false;
+                }
+                state.is_searching = false;
+                true
+            }
+            _ => false,
+        };
+        self.remove_from_queue(chat_id);
+        stopped
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn get_user_state(&self, chat_id: i64) -> Result<Option<UserState>> {
+        Ok(self.lock().users.get(&chat_id).cloned())
+    }
+
+    async fn set_user_state(&self, state: &UserState) -> Result<()> {
+        self.lock().users.insert(state.chat_id, state.clone());
+        Ok(())
+    }
+
+    async fn update_user_state(
+        &self,
+        chat_id: i64,
+        update: &StateUpdate<'_>,
+    ) -> Result<Option<UserState>> {
+        Ok(self.lock().users.get_mut(&chat_id).map(|state| {
+            update(state);
+            state.clone()
+        }))
+    }
+
+    async fn cleanup_user_state(&self, chat_id: i64) -> Result<()> {
+        let mut inner = self.lock();
+        inner.users.remove(&chat_id);
+        inner.search_tags.remove(&chat_id);
+        inner.search_languages.remove(&chat_id);
+        inner.remove_from_queue(chat_id);
+        inner.unreachable.remove(&chat_id);
+        Ok(())
+    }
+
+    async fn reachable_user_ids(&self) -> Result<Vec<i64>> {
+        let inner = self.lock();
+        Ok(inner.users.keys().copied().filter(|chat_id| !inner.unreachable.contains(chat_id)).collect())
+    }
+
+    async fn mark_unreachable(&self, chat_id: i64) -> Result<()> {
+        self.lock().unreachable.insert(chat_id);
+        Ok(())
+    }
+
+    async fn find_random_partner(
+        &self,
+        chat_id: i64,
+        fallback_wait_secs: u64,
+        language_fallback_secs: u64,
+    ) -> Result<Option<(i64, u64)>> {
+        let mut inner = self.lock();
+        loop {
+            let Some((partner_id, since)) = inner.pick_partner(chat_id, now_secs(), fallback_wait_secs, language_fallback_secs) else {
+                return Ok(None);
+            };
+            if inner.users.get(&partner_id).is_some_and(UserState::is_available_for_match) {
+                return Ok(Some((partner_id, since)));
+            }
+        }
+    }
+
+    async fn add_to_search_queue(&self, chat_id: i64, since: u64) -> Result<()> {
+        self.lock().enqueue(chat_id, since);
+        Ok(())
+    }
+
+    async fn remove_from_search_queue(&self, chat_id: i64) -> Result<()> {
+        self.lock().remove_from_queue(chat_id);
+        Ok(())
+    }
+
+    async fn get_waiting_users(&self, min_wait_secs: u64) -> Result<Vec<i64>> {
+        let cutoff = now_secs().saturating_sub(min_wait_secs);
+        Ok(self.lock()
+            .search_queue
+            .iter()
+            .filter(|&&(_, since)| since <= cutoff)
+            .map(|&(chat_id, _)| chat_id)
+            .collect())
+    }
+
+    async fn set_search_tags(&self, chat_id: i64, tags: &[String]) -> Result<()> {
+        self.lock().search_tags.insert(chat_id, tags.iter().cloned().collect());
+        Ok(())
+    }
+
+    async fn get_shared_tags(&self, user1_id: i64, user2_id: i64) -> Result<Vec<String>> {
+        let inner = self.lock();
+        let (Some(user1_tags), Some(user2_tags)) = (inner.search_tags.get(&user1_id), inner.search_tags.get(&user2_id)) else {
+            return Ok(Vec::new());
+        };
+        let mut shared: Vec<String> = user1_tags.intersection(user2_tags).cloned().collect();
+        shared.sort();
+        Ok(shared)
+    }
+
+    async fn set_search_language(&self, chat_id: i64, language: Option<&str>) -> Result<()> {
+        let mut inner = self.lock();
+        match language {
+            Some(language) => inner.search_languages.insert(chat_id, language.to_string()),
+            None => inner.search_languages.remove(&chat_id),
+        };
+        Ok(())
+    }
+
+    async fn connect_users(&self, user1_id: i64, user2_id: i64) -> Result<bool> {
+        let mut inner = self.lock();
+        let available = |id| inner.users.get(&id).is_some_and(UserState::is_available_for_match);
+        if !available(user1_id) || !available(user2_id) {
+            return Ok(false);
+        }
+
+        for (user_id, partner_id) in [(user1_id, user2_id), (user2_id, user1_id)] {
+            if let Some(state) = inner.users.get_mut(&user_id) {
+                state.partner_id = Some(partner_id);
+                state.is_searching = false;
+                state.update_activity();
+            }
+        }
+        Ok(true)
+    }
+
+    async fn end_private_chat(&self, chat_id: i64, partner_id: i64) -> Result<()> {
+        let mut inner = self.lock();
+        for (user_id, other_id) in [(chat_id, partner_id), (partner_id, chat_id)] {
+            if let Some(state) = inner.users.get_mut(&user_id) {
+                // A user who already moved on keeps their new chat or search
+                if state.partner_id == Some(other_id) {
+                    state.partner_id = None;
+                    state.last_partner_id = Some(other_id);
+                    state.is_searching = false;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn cancel_search(&self, chat_id: i64) -> Result<bool> {
+        Ok(self.lock().stop_searching_if(chat_id, |_| true))
+    }
+
+    async fn expire_search(&self, chat_id: i64, timeout_secs: u64) -> Result<bool> {
+        Ok(self.lock().stop_searching_if(chat_id, |state| state.is_inactive(timeout_secs)))
+    }
+
+    async fn block_user(&self, chat_id: i64, blocked_id: i64) -> Result<()> {
+        self.lock().blocked.entry(chat_id).or_default().insert(blocked_id);
+        Ok(())
+    }
+
+    async fn link_messages(&self, copies: &[(i64, i32)]) -> Result<()> {
+        let now = now_secs();
+        let group = Arc::new(copies.iter().copied().collect::<HashMap<_, _>>());
+        let mut inner = self.lock();
+        inner.message_links.retain(|_, link| link.expires_at > now);
+        for &copy in copies {
+            let link = MessageLink { copies: group.clone(), expires_at: now + MESSAGE_LINK_TTL_SECS };
+            inner.message_links.insert(copy, link);
+        }
+        Ok(())
+    }
+
+    async fn get_linked_message(&self, chat_id: i64, message_id: i32, target_chat_id: i64) -> Result<Option<i32>> {
+        let now = now_secs();
+        Ok(self.lock()
+            .message_links
+            .get(&(chat_id, message_id))
+            .filter(|link| link.expires_at > now)
+            .and_then(|link| link.copies.get(&target_chat_id).copied()))
+    }
+
+    async fn get_linked_messages(&self, chat_id: i64, message_id: i32) -> Result<Vec<(i64, i32)>> {
+        let now = now_secs();
+        Ok(self.lock()
+            .message_links
+            .get(&(chat_id, message_id))
+            .filter(|link| link.expires_at > now)
+            .map(|link| link.copies.iter().map(|(&chat_id, &message_id)| (chat_id, message_id)).collect())
+            .unwrap_or_default())
+    }
+
+    async fn set_unseen_message(&self, sender_id: i64, recipient_id: i64, message_id: i32) -> Result<()> {
+        self.lock().unseen_messages.insert((sender_id, recipient_id), message_id);
+        Ok(())
+    }
+
+    async fn take_unseen_message(&self, sender_id: i64, recipient_id: i64) -> Result<Option<i32>> {
+        Ok(self.lock().unseen_messages.remove(&(sender_id, recipient_id)))
+    }
+
+    async fn get_idle_users(&self, timeout_secs: u64) -> Result<Vec<i64>> {
+        Ok(self.lock()
+            .users
+            .values()
+            .filter(|state| state.partner_id.is_some() || state.current_room.is_some())
+            .filter(|state| state.is_inactive(timeout_secs))
+            .map(|state| state.chat_id)
+            .collect())
+    }
+
+    async fn take_rate_token(&self, key: &str, burst: u32, refill_ms: u64) -> Result<bool> {
+        let now = now_millis();
+        let mut inner = self.lock();
+        let (tokens, at) = inner.rate_buckets.entry(key.to_string()).or_insert((burst as f64, now));
+        *tokens = (*tokens + now.saturating_sub(*at) as f64 / refill_ms.max(1) as f64).min(burst as f64);
+        *at = now;
+        if *tokens < 1.0 {
+            return Ok(false);
+        }
+        *tokens -= 1.0;
+        Ok(true)
+    }
+
+    async fn add_rate_strike(&self, chat_id: i64, window_secs: u64) -> Result<u32> {
+        let now = now_secs();
+        let mut inner = self.lock();
+        let (strikes, window_end) = inner.rate_strikes.entry(chat_id).or_insert((0, 0));
+        if *window_end <= now {
+            *strikes = 0;
+            *window_end = now + window_secs;
+        }
+        *strikes += 1;
+        Ok(*strikes)
+    }
+
+    async fn set_flood_mute(&self, chat_id: i64, duration_secs: u64) -> Result<()> {
+        self.lock().flood_mutes.insert(chat_id, now_secs() + duration_secs);
+        Ok(())
+    }
+
+    async fn get_flood_mute(&self, chat_id: i64) -> Result<Option<u64>> {
+        let now = now_secs();
+        Ok(self.lock()
+            .flood_mutes
+            .get(&chat_id)
+            .filter(|until| **until > now)
+            .map(|until| until - now))
+    }
+
+    async fn record_recent_message(&self, conversation: &str, message: &RecentMessage) -> Result<()> {
+        let mut inner = self.lock();
+        let messages = inner.recent_messages.entry(conversation.to_string()).or_default();
+        messages.push(message.clone());
+        if messages.len() > RECENT_MESSAGE_LIMIT {
+            messages.drain(..messages.len() - RECENT_MESSAGE_LIMIT);
+        }
+        Ok(())
+    }
+
+    async fn get_recent_messages(&self, conversation: &str) -> Result<Vec<RecentMessage>> {
+        Ok(self.lock().recent_messages.get(conversation).cloned().unwrap_or_default())
+    }
+
+    async fn set_shadowban(&self, chat_id: i64, until_secs: u64) -> Result<()> {
+        self.lock().shadowbans.insert(chat_id, until_secs);
+        Ok(())
+    }
+
+    async fn clear_shadowban(&self, chat_id: i64) -> Result<()> {
+        self.lock().shadowbans.remove(&chat_id);
+        Ok(())
+    }
+
+    async fn cache_sanctions(&self, chat_id: i64, sanctions: &[Sanction], ttl_secs: u64) -> Result<()> {
+        self.lock().sanction_cache.insert(chat_id, (sanctions.to_vec(), now_secs() + ttl_secs));
+        Ok(())
+    }
+
+    async fn get_cached_sanctions(&self, chat_id: i64) -> Result<Option<Vec<Sanction>>> {
+        let now = now_secs();
+        Ok(self.lock()
+            .sanction_cache
+            .get(&chat_id)
+            .filter(|(_, until)| *until > now)
+            .map(|(sanctions, _)| sanctions.clone()))
+    }
+
+    async fn forget_cached_sanctions(&self, chat_id: i64) -> Result<()> {
+        self.lock().sanction_cache.remove(&chat_id);
+        Ok(())
+    }
+
+    async fn cache_language(&self, chat_id: i64, language: Option<&str>, ttl_secs: u64) -> Result<()> {
+        self.lock().language_cache.insert(chat_id, (language.map(str::to_string), now_secs() + ttl_secs));
+        Ok(())
+    }
+
+    async fn get_cached_language(&self, chat_id: i64) -> Result<Option<Option<String>>> {
+        let now = now_secs();
+        Ok(self.lock()
+            .language_cache
+            .get(&chat_id)
+            .filter(|(_, until)| *until > now)
+            .map(|(language, _)| language.clone()))
+    }
+}
+
+#[async_trait]
+impl RoomStore for MemoryStore {
+    async fn create_room(&self, name: String, max_members: usize) -> Result<ChatRoom> {
+        let room = ChatRoom::new(Uuid::new_v4().to_string(), name, max_members);
+        self.lock().rooms.insert(room.room_id.clone(), room.clone());
+        Ok(room)
+    }
+
+    async fn get_room(&self, room_id: &str) -> Result<Option<ChatRoom>> {
+        Ok(self.lock().rooms.get(room_id).cloned())
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<ChatRoom>> {
+        Ok(self.lock().rooms.values().cloned().collect())
+    }
+
+    async fn join_room(&self, room_id: &str, user_state: &mut UserState) -> Result<bool> {
+        let mut inner = self.lock();
+        let Some(room) = inner.rooms.get(room_id) else {
+            return Ok(false);
+        };
+        if room.members.iter().any(|&member_id| inner.is_blocked_between(user_state.chat_id, member_id)) {
+            return Ok(false);
+        }
+
+        let joined = inner.rooms
+            .get_mut(room_id)
+            .is_some_and(|room| room.add_member(user_state.chat_id));
+        if joined {
+            user_state.current_room = Some(room_id.to_string());
+            user_state.update_activity();
+        }
+        Ok(joined)
+    }
+
+    async fn leave_room(&self, room_id: &str, user_state: &mut UserState) -> Result<()> {
+        let mut inner = self.lock();
+        user_state.current_room = None;
+        if let Some(room) = inner.rooms.get_mut(room_id) {
+            room.remove_member(user_state.chat_id);
+            if room.members.is_empty() {
+                inner.rooms.remove(room_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ProfileStore for MemoryStore {
+    async fn save_profile(&self, chat_id: i64, profile: UserProfile) -> Result<()> {
+        self.lock().profiles.insert(chat_id, profile);
+        Ok(())
+    }
+
+    async fn get_profile(&self, chat_id: i64) -> Result<Option<UserProfile>> {
+        Ok(self.lock().profiles.get(&chat_id).cloned())
+    }
+
+    async fn save_interests(&self, chat_id: i64, interests: &[String]) -> Result<()> {
+        self.lock().interests.insert(chat_id, interests.to_vec());
+        Ok(())
+    }
+
+    async fn get_interests(&self, chat_id: i64) -> Result<Vec<String>> {
+        Ok(self.lock().interests.get(&chat_id).cloned().unwrap_or_default())
+    }
+
+    async fn save_language(&self, chat_id: i64, language: Option<&str>) -> Result<()> {
+        let mut inner = self.lock();
+        match language {
+            Some(language) => inner.languages.insert(chat_id, language.to_string()),
+            None => inner.languages.remove(&chat_id),
+        };
+        Ok(())
+    }
+
+    async fn get_language(&self, chat_id: i64) -> Result<Option<String>> {
+        Ok(self.lock().languages.get(&chat_id).cloned())
+    }
+
+    async fn save_mood_history(&self, chat_id: i64, mood: &MoodEntry) -> Result<()> {
+        let mut inner = self.lock();
+        let history = inner.mood_history.entry(chat_id).or_default();
+        history.insert(0, mood.clone());
+        history.truncate(MOOD_HISTORY_LIMIT);
+        *inner.mood_stats.entry(mood.mood.clone()).or_insert(0) += 1;
+        Ok(())
+    }
+
+    async fn get_mood_history(&self, chat_id: i64) -> Result<Vec<MoodEntry>> {
+        Ok(self.lock().mood_history.get(&chat_id).cloned().unwrap_or_default())
+    }
+
+    async fn get_mood_stats(&self) -> Result<HashMap<String, i32>> {
+        Ok(self.lock().mood_stats.clone())
+    }
+
+    async fn flag_user(&self, chat_id: i64, reason: &str) -> Result<()> {
+        self.lock().flags.entry(chat_id).or_default().push(reason.to_string());
+        Ok(())
+    }
+
+    async fn get_flag_count(&self, chat_id: i64) -> Result<u64> {
+        Ok(self.lock().flags.get(&chat_id).map_or(0, |flags| flags.len() as u64))
+    }
+}
+
+#[async_trait]
+impl ModerationStore for MemoryStore {
+    async fn file_report(&self, report: &Report) -> Result<()> {
+        self.lock().reports.push(report.clone());
+        Ok(())
+    }
+
+    async fn get_report(&self, report_id: &str) -> Result<Option<Report>> {
+        Ok(self.lock().reports.iter().find(|report| report.report_id == report_id).cloned())
+    }
+
+    async fn list_open_reports(&self, limit: usize) -> Result<Vec<Report>> {
+        Ok(self.lock()
+            .reports
+            .iter()
+            .filter(|report| report.status == ReportStatus::Open)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn has_open_reports_about(&self, chat_id: i64) -> Result<bool> {
+        Ok(self.lock().reports.iter().any(|report| {
+            report.status == ReportStatus::Open
+                && report.reporter_id != chat_id
+                && (report.reported_id == Some(chat_id)
+                    || report.messages.iter().any(|message| message.sender_id == chat_id))
+        }))
+    }
+
+    async fn resolve_report(&self, report_id: &str, admin_id: i64, resolution: &str) -> Result<bool> {
+        let mut inner = self.lock();
+        let Some(report) = inner
+            .reports
+            .iter_mut()
+            .find(|report| report.report_id == report_id && report.status == ReportStatus::Open)
+        else {
+            return Ok(false);
+        };
+        report.status = ReportStatus::Resolved;
+        report.resolved_by = Some(admin_id);
+        report.resolution = Some(resolution.to_string());
+        report.resolved_at = Some(Utc::now());
+        Ok(true)
+    }
+
+    async fn add_sanction(&self, sanction: &Sanction) -> Result<()> {
+        let mut inner = self.lock();
+        inner.sanctions.retain(|other| other.chat_id != sanction.chat_id || other.kind != sanction.kind);
+        inner.sanctions.push(sanction.clone());
+        Ok(())
+    }
+
+    async fn get_sanctions(&self, chat_id: i64) -> Result<Vec<Sanction>> {
+        Ok(self.lock()
+            .sanctions
+            .iter()
+            .filter(|sanction| sanction.chat_id == chat_id && sanction.is_active())
+            .cloned()
+            .collect())
+    }
+
+    async fn lift_sanctions(&self, chat_id: i64, kind: Option<SanctionKind>) -> Result<u64> {
+        let mut inner = self.lock();
+        let before = inner.sanctions.len();
+        inner.sanctions.retain(|sanction| {
+            sanction.chat_id != chat_id || kind.is_some_and(|kind| sanction.kind != kind)
+        });
+        Ok((before - inner.sanctions.len()) as u64)
+    }
+
+    async fn set_role(&self, member: &StaffMember) -> Result<()> {
+        self.lock().staff.insert(member.chat_id, member.clone());
+        Ok(())
+    }
+
+    async fn get_role(&self, chat_id: i64) -> Result<Option<StaffMember>> {
+        Ok(self.lock().staff.get(&chat_id).cloned())
+    }
+
+    async fn list_staff(&self, role: Role) -> Result<Vec<StaffMember>> {
+        Ok(self.lock().staff.values().filter(|member| member.role == role).cloned().collect())
+    }
+
+    async fn remove_role(&self, chat_id: i64) -> Result<bool> {
+        Ok(self.lock().staff.remove(&chat_id).is_some())
+    }
+
+    async fn get_pseudonym(&self, chat_id: i64) -> Result<Option<String>> {
+        Ok(self.lock().pseudonyms.get(&chat_id).cloned())
+    }
+
+    async fn claim_pseudonym(&self, chat_id: i64, pseudonym: &str) -> Result<bool> {
+        let mut inner = self.lock();
+        if inner.pseudonyms.iter().any(|(owner, taken)| *owner != chat_id && taken == pseudonym) {
+            return Ok(false);
+        }
+        inner.pseudonyms.insert(chat_id, pseudonym.to_string());
+        Ok(true)
+    }
+
+    async fn resolve_pseudonym(&self, pseudonym: &str) -> Result<Option<i64>> {
+        Ok(self.lock()
+            .pseudonyms
+            .iter()
+            .find(|(_, taken)| *taken == pseudonym)
+            .map(|(chat_id, _)| *chat_id))
+    }
+}
@@ -1,5 +1,19 @@
 pub mod redis_service;
 pub mod chat_room;
+pub mod relay;
 pub mod content_filter;
+pub mod word_matcher;
+pub mod info_detector;
 pub mod profile_service;
-pub mod mongodb_service; 
\ No newline at end of file
+pub mod mongodb_service;
+pub mod matchmaking;
+pub mod inactivity;
+pub mod rate_limit;
+pub mod reports;
+pub mod moderation;
+pub mod roles;
+pub mod pseudonyms;
+pub mod broadcast;
+pub mod presence;
+pub mod storage;
+pub mod memory_store;
@@ -0,0 +1,197 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use teloxide::prelude::*;
+use crate::models::{AppState, Sanction, SanctionKind};
+use super::matchmaking::PARTNER_LEFT_MESSAGE;
+
+// Stands in for "until lifted" in the shadow-ban set, which needs an end time
+const PERMANENT_UNTIL_SECS: u64 = 253_402_300_799; // 9999-12-31
+// How long `enforce` trusts its cached copy of a user's sanctions
+const SANCTION_CACHE_SECS: u64 = 60;
+
+/// What the dispatcher does with an update from the user.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Enforcement {
+    Allow,
+    /// Ignore it without telling the user.
+    Drop,
+    /// Ignore it and tell the user why.
+    Refuse(String),
+}
+
+/// Parses durations like `90s`, `30m`, `12h`, `7d` or `2w`. Durations too
+/// long to end at a representable time are rejected.
+pub fn parse_duration(text: &str) -> Option<TimeDelta> {
+    let text = text.trim().to_lowercase();
+    let unit = text.chars().last()?;
+    let amount: i64 = text[..text.len() - unit.len_utf8()].parse().ok().filter(|amount| *amount > 0)?;
+    let duration = match unit {
+        's' => TimeDelta::try_seconds(amount),
+        'm' => TimeDelta::try_minutes(amount),
+        'h' => TimeDelta::try_hours(amount),
+        'd' => TimeDelta::try_days(amount),
+        'w' => TimeDelta::try_weeks(amount),
+        _ => None,
+    }?;
+    Utc::now().checked_add_signed(duration).map(|_| duration)
+}
+
+/// "until 2024-05-01 12:00 UTC", or "until further notice".
+pub fn describe_until(expires_at: Option<DateTime<Utc>>) -> String {
+    match expires_at {
+        Some(expires_at) => format!("until {}", expires_at.format("%Y-%m-%d %H:%M UTC")),
+        None => "until further notice".to_string(),
+    }
+}
+
+/// Puts a sanction on the user, lasting `duration` or until lifted. Banned
+/// users are taken out of their chat, room and search; everyone but
+/// shadow-banned users is told.
+pub async fn impose(
+    bot: &Bot,
+    state: &AppState,
+    chat_id: i64,
+    kind: SanctionKind,
+    duration: Option<TimeDelta>,
+    admin_id: i64,
+) -> Result<Sanction> {
+    let now = Utc::now();
+    let expires_at = match duration {
+        Some(duration) => Some(now.checked_add_signed(duration).ok_or_else(|| anyhow!("Sanction duration {} is out of range", duration))?),
+        None => None,
+    };
+    let sanction = Sanction {
+        chat_id,
+        kind,
+        issued_by: admin_id,
+        created_at: now,
+        expires_at,
+    };
+    state.moderation.add_sanction(&sanction).await?;
+    state.sessions.forget_cached_sanctions(chat_id).await?;
+    log::warn!("⚖️ Admin {} put a {:?} on user {} {}", admin_id, kind, chat_id, describe_until(sanction.expires_at));
+
+    let until = describe_until(sanction.expires_at);
+    match kind {
+        SanctionKind::Ban => {
+            disconnect(bot, state, chat_id).await?;
+            notify(bot, chat_id, format!("🚫 You have been banned {}.", until)).await;
+        }
+        SanctionKind::Mute => {
+            notify(bot, chat_id, format!("🔇 You have been muted {}. You can still use commands.", until)).await;
+        }
+        SanctionKind::Shadowban => {
+            state.sessions.set_shadowban(chat_id, shadowban_until(&sanction)).await?;
+            end_chat_outside_shadowban(bot, state, chat_id).await?;
+        }
+    }
+    Ok(sanction)
+}
+
+/// Lifts every sanction on the user, telling them unless they were only
+/// shadow-banned. Returns how many there were.
+pub async fn lift(bot: &Bot, state: &AppState, chat_id: i64) -> Result<u64> {
+    let active = state.moderation.get_sanctions(chat_id).await?;
+    let lifted = state.moderation.lift_sanctions(chat_id, None).await?;
+    state.sessions.forget_cached_sanctions(chat_id).await?;
+    state.sessions.clear_shadowban(chat_id).await?;
+    if lifted > 0 {
+        log::info!("⚖️ Lifted {} sanctions from user {}", lifted, chat_id);
+    }
+    if active.iter().any(|sanction| sanction.kind != SanctionKind::Shadowban) {
+        notify(bot, chat_id, "✅ Your restrictions have been lifted.".to_string()).await;
+    }
+    Ok(lifted)
+}
+
+/// Decides what happens to an update from the user, before any handler runs.
+/// Banned users are refused everything, muted users everything but commands,
+/// and shadow-banned users' room messages are dropped.
+pub async fn enforce(state: &AppState, chat_id: i64, is_command: bool) -> Result<Enforcement> {
+    let sanctions = active_sanctions(state, chat_id).await?;
+    let find = |kind| sanctions.iter().find(|sanction| sanction.kind == kind);
+
+    if let Some(ban) = find(SanctionKind::Ban) {
+        return Ok(Enforcement::Refuse(format!("🚫 You're banned {}.", describe_until(ban.expires_at))));
+    }
+    if is_command {
+        return Ok(Enforcement::Allow);
+    }
+    if let Some(mute) = find(SanctionKind::Mute) {
+        return Ok(Enforcement::Refuse(format!(
+            "🔇 You're muted {}. You can still use commands.",
+            describe_until(mute.expires_at)
+        )));
+    }
+    if find(SanctionKind::Shadowban).is_some() {
+        let in_room = state.sessions.get_user_state(chat_id).await?.is_some_and(|user_state| user_state.current_room.is_some());
+        if in_room {
+            return Ok(Enforcement::Drop);
+        }
+    }
+    Ok(Enforcement::Allow)
+}
+
+// Every update is enforced, so the sanctions are read from the session
+// store's short-lived copy rather than MongoDB each time
+async fn active_sanctions(state: &AppState, chat_id: i64) -> Result<Vec<Sanction>> {
+    if let Some(cached) = state.sessions.get_cached_sanctions(chat_id).await? {
+        return Ok(cached.into_iter().filter(Sanction::is_active).collect());
+    }
+
+    let sanctions = state.moderation.get_sanctions(chat_id).await?;
+    if let Some(shadowban) = sanctions.iter().find(|sanction| sanction.kind == SanctionKind::Shadowban) {
+        // Matchmaking reads shadow bans from the session store; keep it in
+        // step in case it lost them
+        state.sessions.set_shadowban(chat_id, shadowban_until(shadowban)).await?;
+    }
+    state.sessions.cache_sanctions(chat_id, &sanctions, SANCTION_CACHE_SECS).await?;
+    Ok(sanctions)
+}
+
+fn shadowban_until(sanction: &Sanction) -> u64 {
+    sanction.expires_at.map_or(PERMANENT_UNTIL_SECS, |expires_at| expires_at.timestamp() as u64)
+}
+
+// Shadow-banned users only talk among themselves, so a private chat with
+// anyone else ends. Each side is told the other left, so the user never
+// learns of the ban.
+async fn end_chat_outside_shadowban(bot: &Bot, state: &AppState, chat_id: i64) -> Result<()> {
+    let Some(partner_id) = state.sessions.get_user_state(chat_id).await?.and_then(|user_state| user_state.partner_id) else {
+        return Ok(());
+    };
+    let partner_sanctions = active_sanctions(state, partner_id).await?;
+    if partner_sanctions.iter().any(|sanction| sanction.kind == SanctionKind::Shadowban) {
+        return Ok(());
+    }
+    state.sessions.end_private_chat(chat_id, partner_id).await?;
+    for user_id in [chat_id, partner_id] {
+        notify(bot, user_id, PARTNER_LEFT_MESSAGE.to_string()).await;
+    }
+    Ok(())
+}
+
+// Ends the user's private chat or leaves their room as if they left, and
+// stops any search
+async fn disconnect(bot: &Bot, state: &AppState, chat_id: i64) -> Result<()> {
+    let Some(mut user_state) = state.sessions.get_user_state(chat_id).await? else {
+        return Ok(());
+    };
+    if let Some(partner_id) = user_state.partner_id {
+        state.sessions.end_private_chat(chat_id, partner_id).await?;
+        notify(bot, partner_id, PARTNER_LEFT_MESSAGE.to_string()).await;
+    } else if let Some(room_id) = user_state.current_room.clone() {
+        state.rooms.leave_room(&room_id, &mut user_state).await?;
+        state.sessions.update_user_state(chat_id, &|user_state| user_state.current_room = None).await?;
+    } else if user_state.is_searching {
+        state.sessions.cancel_search(chat_id).await?;
+    }
+    Ok(())
+}
+
+// The user may have blocked the bot, which must not stop the sanction
+async fn notify(bot: &Bot, chat_id: i64, text: String) {
+    if let Err(e) = bot.send_message(ChatId(chat_id), text).await {
+        log::warn!("⚠️ Failed to notify user {}: {}", chat_id, e);
+    }
+}
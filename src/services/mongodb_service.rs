@@ -1,22 +1,54 @@
 use mongodb::{Client, Collection, Database, IndexModel, options::IndexOptions};
-use crate::models::{UserProfile, MoodEntry};
+use crate::models::{UserProfile, MoodEntry, Report, Role, Sanction, SanctionKind, StaffMember};
+use super::content_filter::{FilterWord, WordListSource};
+use super::storage::ModerationStore;
 use anyhow::Result;
+use async_trait::async_trait;
 use futures::StreamExt;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 const DB_NAME: &str = "telegram_anonymous_chat";
+// MongoDB's error code for a write that broke a unique index
+const DUPLICATE_KEY_CODE: i32 = 11000;
 const USERS_COLLECTION: &str = "users";
+const FILTER_WORDS_COLLECTION: &str = "filter_words";
+const USER_FLAGS_COLLECTION: &str = "user_flags";
+const REPORTS_COLLECTION: &str = "reports";
+const SANCTIONS_COLLECTION: &str = "sanctions";
+const STAFF_COLLECTION: &str = "staff";
+const PSEUDONYMS_COLLECTION: &str = "pseudonyms";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserDocument {
     pub chat_id: i64,
     pub profile: Option<UserProfile>,
     pub moods: Vec<MoodEntry>,
+    #[serde(default)]
+    pub interests: Vec<String>,
+    #[serde(default)]
+    pub language: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// The pseudonym staff know a user by.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PseudonymDocument {
+    pub chat_id: i64,
+    pub pseudonym: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A moderation flag raised against a user, e.g. by the content filter.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FlagDocument {
+    pub chat_id: i64,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
 pub struct MongoDB {
     db: Database,
 }
@@ -41,6 +73,31 @@ impl MongoDB {
             
         users.create_index(index, None).await?;
 
+        // Sanctions are looked up for every update
+        let sanctions = db.collection::<Sanction>(SANCTIONS_COLLECTION);
+        let index = IndexModel::builder()
+            .keys(mongodb::bson::doc! { "chat_id": 1, "kind": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        sanctions.create_index(index, None).await?;
+
+        let staff = db.collection::<StaffMember>(STAFF_COLLECTION);
+        let index = IndexModel::builder()
+            .keys(mongodb::bson::doc! { "chat_id": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        staff.create_index(index, None).await?;
+
+        // Looked up both ways, and each must stay unique
+        let pseudonyms = db.collection::<PseudonymDocument>(PSEUDONYMS_COLLECTION);
+        for key in ["chat_id", "pseudonym"] {
+            let index = IndexModel::builder()
+                .keys(mongodb::bson::doc! { key: 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build();
+            pseudonyms.create_index(index, None).await?;
+        }
+
         log::info!("✅ Successfully connected to MongoDB database: {}", DB_NAME);
         
         Ok(Self { db })
@@ -52,40 +109,24 @@ impl MongoDB {
 
     pub async fn save_profile(&self, chat_id: i64, profile: UserProfile) -> Result<()> {
         let users = self.users_collection();
-        let now = Utc::now();
 
         log::info!("🔄 Attempting to save profile for user {}", chat_id);
 
-        // Get existing document if any
-        let existing = users.find_one(mongodb::bson::doc! { "chat_id": chat_id }, None).await
-            .map_err(|e| {
-                log::error!("❌ Failed to query existing profile for user {}: {}", chat_id, e);
-                anyhow::anyhow!("MongoDB query error: {}", e)
-            })?;
-
-        let doc = match existing {
-            Some(mut doc) => {
-                log::info!("📝 Updating existing profile for user {}", chat_id);
-                doc.profile = Some(profile);
-                doc.updated_at = now;
-                doc
-            },
-            None => {
-                log::info!("📝 Creating new profile document for user {}", chat_id);
-                UserDocument {
-                    chat_id,
-                    profile: Some(profile),
-                    moods: Vec::new(),
-                    created_at: now,
-                    updated_at: now,
-                }
-            }
-        };
-
-        users.replace_one(
+        // Only the profile is written, so interests and language saved in
+        // the meantime are left alone
+        let profile = mongodb::bson::to_bson(&profile)?;
+        let now = mongodb::bson::to_bson(&Utc::now())?;
+        users.update_one(
             mongodb::bson::doc! { "chat_id": chat_id },
-            doc,
-            mongodb::options::ReplaceOptions::builder().upsert(true).build(),
+            mongodb::bson::doc! {
+                "$set": { "profile": profile, "updated_at": now.clone() },
+                "$setOnInsert": {
+                    "chat_id": chat_id,
+                    "moods": [],
+                    "created_at": now
+                }
+            },
+            mongodb::options::UpdateOptions::builder().upsert(true).build(),
         ).await.map_err(|e| {
             log::error!("❌ Failed to save profile for user {}: {}", chat_id, e);
             anyhow::anyhow!("MongoDB save error: {}", e)
@@ -118,6 +159,67 @@ impl MongoDB {
         }
     }
 
+    pub async fn save_interests(&self, chat_id: i64, interests: &[String]) -> Result<()> {
+        let users = self.users_collection();
+        // Stored through serde so timestamps match the shape `UserDocument` expects
+        let now = mongodb::bson::to_bson(&Utc::now())?;
+
+        users.update_one(
+            mongodb::bson::doc! { "chat_id": chat_id },
+            mongodb::bson::doc! {
+                "$set": { "interests": interests, "updated_at": now.clone() },
+                "$setOnInsert": {
+                    "chat_id": chat_id,
+                    "moods": [],
+                    "created_at": now
+                }
+            },
+            mongodb::options::UpdateOptions::builder().upsert(true).build(),
+        ).await?;
+
+        log::info!("✅ Saved {} interest tags for user {}", interests.len(), chat_id);
+        Ok(())
+    }
+
+    pub async fn get_interests(&self, chat_id: i64) -> Result<Vec<String>> {
+        let users = self.users_collection();
+
+        match users.find_one(mongodb::bson::doc! { "chat_id": chat_id }, None).await? {
+            Some(user) => Ok(user.interests),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub async fn save_language(&self, chat_id: i64, language: Option<&str>) -> Result<()> {
+        let users = self.users_collection();
+        let now = mongodb::bson::to_bson(&Utc::now())?;
+
+        users.update_one(
+            mongodb::bson::doc! { "chat_id": chat_id },
+            mongodb::bson::doc! {
+                "$set": { "language": language, "updated_at": now.clone() },
+                "$setOnInsert": {
+                    "chat_id": chat_id,
+                    "moods": [],
+                    "created_at": now
+                }
+            },
+            mongodb::options::UpdateOptions::builder().upsert(true).build(),
+        ).await?;
+
+        log::info!("✅ Saved language preference {:?} for user {}", language, chat_id);
+        Ok(())
+    }
+
+    pub async fn get_language(&self, chat_id: i64) -> Result<Option<String>> {
+        let users = self.users_collection();
+
+        match users.find_one(mongodb::bson::doc! { "chat_id": chat_id }, None).await? {
+            Some(user) => Ok(user.language),
+            None => Ok(None),
+        }
+    }
+
     pub async fn save_mood(&self, chat_id: i64, mood: MoodEntry) -> Result<()> {
         let users = self.users_collection();
         let now = Utc::now();
@@ -167,4 +269,212 @@ impl MongoDB {
         log::info!("📊 Retrieved mood statistics with {} different moods", stats.len());
         Ok(stats)
     }
-} 
\ No newline at end of file
+
+    pub async fn flag_user(&self, chat_id: i64, reason: &str) -> Result<()> {
+        let flags = self.db.collection::<FlagDocument>(USER_FLAGS_COLLECTION);
+        flags.insert_one(
+            FlagDocument {
+                chat_id,
+                reason: reason.to_string(),
+                created_at: Utc::now(),
+            },
+            None,
+        ).await?;
+        Ok(())
+    }
+
+    pub async fn get_flag_count(&self, chat_id: i64) -> Result<u64> {
+        let flags = self.db.collection::<FlagDocument>(USER_FLAGS_COLLECTION);
+        Ok(flags.count_documents(mongodb::bson::doc! { "chat_id": chat_id }, None).await?)
+    }
+}
+
+/// Filtered words live in the `filter_words` collection, one document per
+/// word: `{ word, language, severity }`.
+#[async_trait]
+impl WordListSource for MongoDB {
+    fn describe(&self) -> String {
+        format!("MongoDB collection {}", FILTER_WORDS_COLLECTION)
+    }
+
+    async fn load(&self) -> Result<Vec<FilterWord>> {
+        let words = self.db.collection::<FilterWord>(FILTER_WORDS_COLLECTION);
+        let mut cursor = words.find(None, None).await?;
+        let mut loaded = Vec::new();
+        while let Some(word) = cursor.next().await {
+            loaded.push(word?);
+        }
+        Ok(loaded)
+    }
+}
+
+#[async_trait]
+impl ModerationStore for MongoDB {
+    async fn file_report(&self, report: &Report) -> Result<()> {
+        let reports = self.db.collection::<Report>(REPORTS_COLLECTION);
+        reports.insert_one(report, None).await?;
+        log::info!("🚨 Filed report {} from user {}", report.report_id, report.reporter_id);
+        Ok(())
+    }
+
+    async fn get_report(&self, report_id: &str) -> Result<Option<Report>> {
+        let reports = self.db.collection::<Report>(REPORTS_COLLECTION);
+        Ok(reports.find_one(mongodb::bson::doc! { "report_id": report_id }, None).await?)
+    }
+
+    async fn list_open_reports(&self, limit: usize) -> Result<Vec<Report>> {
+        let reports = self.db.collection::<Report>(REPORTS_COLLECTION);
+        let options = mongodb::options::FindOptions::builder()
+            .sort(mongodb::bson::doc! { "created_at": 1 })
+            .limit(limit as i64)
+            .build();
+        let mut cursor = reports.find(mongodb::bson::doc! { "status": "open" }, options).await?;
+        let mut open = Vec::new();
+        while let Some(report) = cursor.next().await {
+            open.push(report?);
+        }
+        Ok(open)
+    }
+
+    async fn has_open_reports_about(&self, chat_id: i64) -> Result<bool> {
+        let reports = self.db.collection::<Report>(REPORTS_COLLECTION);
+        let filter = mongodb::bson::doc! {
+            "status": "open",
+            "reporter_id": { "$ne": chat_id },
+            "$or": [{ "reported_id": chat_id }, { "messages.sender_id": chat_id }],
+        };
+        let options = mongodb::options::CountOptions::builder().limit(1).build();
+        Ok(reports.count_documents(filter, options).await? > 0)
+    }
+
+    async fn resolve_report(&self, report_id: &str, admin_id: i64, resolution: &str) -> Result<bool> {
+        let reports = self.db.collection::<Report>(REPORTS_COLLECTION);
+        let now = mongodb::bson::to_bson(&Utc::now())?;
+        let result = reports.update_one(
+            mongodb::bson::doc! { "report_id": report_id, "status": "open" },
+            mongodb::bson::doc! {
+                "$set": {
+                    "status": "resolved",
+                    "resolved_by": admin_id,
+                    "resolution": resolution,
+                    "resolved_at": now,
+                }
+            },
+            None,
+        ).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    async fn add_sanction(&self, sanction: &Sanction) -> Result<()> {
+        let sanctions = self.db.collection::<Sanction>(SANCTIONS_COLLECTION);
+        sanctions.replace_one(
+            mongodb::bson::doc! {
+                "chat_id": sanction.chat_id,
+                "kind": mongodb::bson::to_bson(&sanction.kind)?,
+            },
+            sanction,
+            mongodb::options::ReplaceOptions::builder().upsert(true).build(),
+        ).await?;
+        log::info!("⚖️ Saved {:?} sanction for user {}", sanction.kind, sanction.chat_id);
+        Ok(())
+    }
+
+    async fn get_sanctions(&self, chat_id: i64) -> Result<Vec<Sanction>> {
+        let sanctions = self.db.collection::<Sanction>(SANCTIONS_COLLECTION);
+        let mut cursor = sanctions.find(mongodb::bson::doc! { "chat_id": chat_id }, None).await?;
+        let mut active = Vec::new();
+        while let Some(sanction) = cursor.next().await {
+            let sanction = sanction?;
+            // Expired sanctions are left for the record
+            if sanction.is_active() {
+                active.push(sanction);
+            }
+        }
+        Ok(active)
+    }
+
+    async fn lift_sanctions(&self, chat_id: i64, kind: Option<SanctionKind>) -> Result<u64> {
+        let sanctions = self.db.collection::<Sanction>(SANCTIONS_COLLECTION);
+        let mut filter = mongodb::bson::doc! { "chat_id": chat_id };
+        if let Some(kind) = kind {
+            filter.insert("kind", mongodb::bson::to_bson(&kind)?);
+        }
+        Ok(sanctions.delete_many(filter, None).await?.deleted_count)
+    }
+
+    async fn set_role(&self, member: &StaffMember) -> Result<()> {
+        let staff = self.db.collection::<StaffMember>(STAFF_COLLECTION);
+        staff.replace_one(
+            mongodb::bson::doc! { "chat_id": member.chat_id },
+            member,
+            mongodb::options::ReplaceOptions::builder().upsert(true).build(),
+        ).await?;
+        log::info!("🛡️ User {} is now {}", member.chat_id, member.role.name());
+        Ok(())
+    }
+
+    async fn get_role(&self, chat_id: i64) -> Result<Option<StaffMember>> {
+        let staff = self.db.collection::<StaffMember>(STAFF_COLLECTION);
+        Ok(staff.find_one(mongodb::bson::doc! { "chat_id": chat_id }, None).await?)
+    }
+
+    async fn list_staff(&self, role: Role) -> Result<Vec<StaffMember>> {
+        let staff = self.db.collection::<StaffMember>(STAFF_COLLECTION);
+        let mut cursor = staff.find(mongodb::bson::doc! { "role": mongodb::bson::to_bson(&role)? }, None).await?;
+        let mut members = Vec::new();
+        while let Some(member) = cursor.next().await {
+            members.push(member?);
+        }
+        Ok(members)
+    }
+
+    async fn remove_role(&self, chat_id: i64) -> Result<bool> {
+        let staff = self.db.collection::<StaffMember>(STAFF_COLLECTION);
+        let result = staff.delete_one(mongodb::bson::doc! { "chat_id": chat_id }, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn get_pseudonym(&self, chat_id: i64) -> Result<Option<String>> {
+        let pseudonyms = self.db.collection::<PseudonymDocument>(PSEUDONYMS_COLLECTION);
+        let document = pseudonyms.find_one(mongodb::bson::doc! { "chat_id": chat_id }, None).await?;
+        Ok(document.map(|document| document.pseudonym))
+    }
+
+    async fn claim_pseudonym(&self, chat_id: i64, pseudonym: &str) -> Result<bool> {
+        let pseudonyms = self.db.collection::<PseudonymDocument>(PSEUDONYMS_COLLECTION);
+        let taken = pseudonyms.find_one(mongodb::bson::doc! { "pseudonym": pseudonym }, None).await?;
+        if taken.is_some_and(|document| document.chat_id != chat_id) {
+            return Ok(false);
+        }
+        // The unique index still catches a race for the same pseudonym
+        let claimed = pseudonyms.replace_one(
+            mongodb::bson::doc! { "chat_id": chat_id },
+            PseudonymDocument {
+                chat_id,
+                pseudonym: pseudonym.to_string(),
+                created_at: Utc::now(),
+            },
+            mongodb::options::ReplaceOptions::builder().upsert(true).build(),
+        ).await;
+        match claimed {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn resolve_pseudonym(&self, pseudonym: &str) -> Result<Option<i64>> {
+        let pseudonyms = self.db.collection::<PseudonymDocument>(PSEUDONYMS_COLLECTION);
+        let document = pseudonyms.find_one(mongodb::bson::doc! { "pseudonym": pseudonym }, None).await?;
+        Ok(document.map(|document| document.chat_id))
+    }
+}
+
+// Whether the write lost a race on a unique index
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(write_error))
+            if write_error.code == DUPLICATE_KEY_CODE
+    )
+}
@@ -0,0 +1,54 @@
+use anyhow::Result;
+use serde::Serialize;
+use teloxide::{
+    prelude::*,
+    requests::{JsonRequest, Payload},
+    types::True,
+};
+use crate::models::AppState;
+
+const SEEN_REACTION: &str = "👀";
+
+/// `setMessageReaction`, which teloxide does not wrap yet.
+#[derive(Debug, Clone, Serialize)]
+struct SetMessageReaction {
+    chat_id: i64,
+    message_id: i32,
+    reaction: Vec<ReactionTypeEmoji>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ReactionTypeEmoji {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    emoji: &'static str,
+}
+
+impl Payload for SetMessageReaction {
+    type Output = True;
+
+    const NAME: &'static str = "SetMessageReaction";
+}
+
+/// Marks the partner's last relayed message as seen once the user does
+/// anything in the bot, by reacting to it in the partner's chat. Only done
+/// for users who share their presence.
+pub async fn mark_seen(bot: &Bot, state: &AppState, chat_id: i64) -> Result<()> {
+    let Some(user_state) = state.sessions.get_user_state(chat_id).await? else {
+        return Ok(());
+    };
+    let Some(partner_id) = user_state.partner_id.filter(|_| user_state.share_presence) else {
+        return Ok(());
+    };
+    let Some(message_id) = state.sessions.take_unseen_message(partner_id, chat_id).await? else {
+        return Ok(());
+    };
+
+    let payload = SetMessageReaction {
+        chat_id: partner_id,
+        message_id,
+        reaction: vec![ReactionTypeEmoji { kind: "emoji", emoji: SEEN_REACTION }],
+    };
+    JsonRequest::new(bot.clone(), payload).await?;
+    Ok(())
+}
@@ -1,39 +1,44 @@
-use crate::models::MoodEntry;
-use anyhow::Result;
+use crate::models::{MoodEntry, UserProfile};
+use super::{mongodb_service::MongoDB, redis_service, storage::ProfileStore};
+use async_trait::async_trait;
+use anyhow::{Result, anyhow};
 use redis::AsyncCommands;
 use std::collections::HashMap;
-use teloxide::prelude::*;
 
 const MOOD_HISTORY_PREFIX: &str = "mood_history:";
 const MOOD_STATS_KEY: &str = "mood_stats";
 
 pub async fn save_mood_history(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
     chat_id: i64,
     mood: &MoodEntry,
 ) -> Result<()> {
     let key = format!("{}{}", MOOD_HISTORY_PREFIX, chat_id);
     let data = serde_json::to_string(mood)?;
-    redis.lpush(&key, data).await?;
+    let _: () = redis.lpush(&key, data).await?;
     // Keep only last 30 days
-    redis.ltrim(&key, 0, 29).await?;
+    let _: () = redis.ltrim(&key, 0, 29).await?;
     
-    // Update mood stats
-    let stats_key = MOOD_STATS_KEY;
-    let stats: HashMap<String, i32> = match redis.get::<_, String>(stats_key).await {
-        Ok(data) => serde_json::from_str(&data).unwrap_or_default(),
-        Err(_) => HashMap::new(),
-    };
-    
-    let mut new_stats = stats;
-    *new_stats.entry(mood.mood.clone()).or_insert(0) += 1;
-    redis.set(stats_key, serde_json::to_string(&new_stats)?).await?;
-    
-    Ok(())
+    // Update mood stats, retrying if another user's mood landed in between
+    for _ in 0..redis_service::MAX_UPDATE_ATTEMPTS {
+        let raw: Option<String> = redis.get(MOOD_STATS_KEY).await?;
+        let mut stats: HashMap<String, i32> = raw
+            .as_deref()
+            .and_then(|data| serde_json::from_str(data).ok())
+            .unwrap_or_default();
+        *stats.entry(mood.mood.clone()).or_insert(0) += 1;
+
+        let data = serde_json::to_string(&stats)?;
+        if redis_service::compare_and_set(redis, MOOD_STATS_KEY, raw.as_deref(), Some(&data)).await? {
+            return Ok(());
+        }
+    }
+
+    Err(anyhow!("Could not update mood stats after {} attempts", redis_service::MAX_UPDATE_ATTEMPTS))
 }
 
 pub async fn get_mood_history(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
     chat_id: i64,
 ) -> Result<Vec<MoodEntry>> {
     let key = format!("{}{}", MOOD_HISTORY_PREFIX, chat_id);
@@ -48,7 +53,7 @@ pub async fn get_mood_history(
 }
 
 pub async fn get_mood_stats(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
 ) -> Result<HashMap<String, i32>> {
     let stats: Option<String> = redis.get(MOOD_STATS_KEY).await?;
     Ok(stats
@@ -56,32 +61,61 @@ pub async fn get_mood_stats(
         .unwrap_or_default())
 }
 
-pub async fn broadcast_message(
-    bot: &Bot,
-    redis: &mut redis::aio::Connection,
-    message: &str,
-    sender_id: i64,
-) -> Result<usize> {
-    let pattern = "user:*";
-    let keys: Vec<String> = redis.keys(pattern).await?;
-    let mut sent_count = 0;
-    
-    for key in keys {
-        if let Ok(Some(state)) = super::redis_service::get_user_state(redis, key.split(':').nth(1).unwrap().parse()?).await {
-            if state.chat_id != sender_id {
-                if bot
-                    .send_message(
-                        ChatId(state.chat_id),
-                        format!("📢 Broadcast Message:\n\n{}", message),
-                    )
-                    .await
-                    .is_ok()
-                {
-                    sent_count += 1;
-                }
-            }
-        }
+/// Profiles and preferences live in MongoDB; mood history and stats in Redis.
+pub struct PersistentProfileStore {
+    mongodb: MongoDB,
+    redis: redis::aio::ConnectionManager,
+}
+
+impl PersistentProfileStore {
+    pub fn new(mongodb: MongoDB, redis: redis::aio::ConnectionManager) -> Self {
+        Self { mongodb, redis }
     }
-    
-    Ok(sent_count)
-} 
\ No newline at end of file
+}
+
+#[async_trait]
+impl ProfileStore for PersistentProfileStore {
+    async fn save_profile(&self, chat_id: i64, profile: UserProfile) -> Result<()> {
+        self.mongodb.save_profile(chat_id, profile).await
+    }
+
+    async fn get_profile(&self, chat_id: i64) -> Result<Option<UserProfile>> {
+        self.mongodb.get_profile(chat_id).await
+    }
+
+    async fn save_interests(&self, chat_id: i64, interests: &[String]) -> Result<()> {
+        self.mongodb.save_interests(chat_id, interests).await
+    }
+
+    async fn get_interests(&self, chat_id: i64) -> Result<Vec<String>> {
+        self.mongodb.get_interests(chat_id).await
+    }
+
+    async fn save_language(&self, chat_id: i64, language: Option<&str>) -> Result<()> {
+        self.mongodb.save_language(chat_id, language).await
+    }
+
+    async fn get_language(&self, chat_id: i64) -> Result<Option<String>> {
+        self.mongodb.get_language(chat_id).await
+    }
+
+    async fn save_mood_history(&self, chat_id: i64, mood: &MoodEntry) -> Result<()> {
+        save_mood_history(&mut self.redis.clone(), chat_id, mood).await
+    }
+
+    async fn get_mood_history(&self, chat_id: i64) -> Result<Vec<MoodEntry>> {
+        get_mood_history(&mut self.redis.clone(), chat_id).await
+    }
+
+    async fn get_mood_stats(&self) -> Result<HashMap<String, i32>> {
+        get_mood_stats(&mut self.redis.clone()).await
+    }
+
+    async fn flag_user(&self, chat_id: i64, reason: &str) -> Result<()> {
+        self.mongodb.flag_user(chat_id, reason).await
+    }
+
+    async fn get_flag_count(&self, chat_id: i64) -> Result<u64> {
+        self.mongodb.get_flag_count(chat_id).await
+    }
+}
@@ -0,0 +1,77 @@
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+use crate::models::AppState;
+use super::rate_limit::Budget;
+
+const PREFIX: &str = "U-";
+// Hex digits after the prefix: 16.7 million pseudonyms
+const DIGITS: usize = 6;
+// Collisions are rare; give up rather than loop forever if the store misbehaves
+const MAX_ATTEMPTS: usize = 10;
+// Users may get a new pseudonym once a day
+const ROTATION_BUDGET: Budget = Budget::new(1, 24 * 60 * 60 * 1000);
+
+/// The outcome of `/myid new`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rotation {
+    Done(String),
+    /// An open report is about the user, so staff must still be able to
+    /// find them by their current pseudonym.
+    UnderReview,
+    /// The user already got a new pseudonym recently.
+    TooSoon,
+}
+
+/// The user's pseudonym, such as `U-7F3A2C`, given one on first use. Staff
+/// only ever see users by their pseudonym.
+pub async fn pseudonym_of(state: &AppState, chat_id: i64) -> Result<String> {
+    if let Some(pseudonym) = state.moderation.get_pseudonym(chat_id).await? {
+        return Ok(pseudonym);
+    }
+    assign(state, chat_id).await
+}
+
+/// Gives the user a new pseudonym, unless a report about them is open or
+/// they rotated it recently. The old one stops resolving.
+pub async fn rotate(state: &AppState, chat_id: i64) -> Result<Rotation> {
+    if state.moderation.has_open_reports_about(chat_id).await? {
+        return Ok(Rotation::UnderReview);
+    }
+    let key = format!("myid:{}", chat_id);
+    if !state.sessions.take_rate_token(&key, ROTATION_BUDGET.burst, ROTATION_BUDGET.refill_ms).await? {
+        return Ok(Rotation::TooSoon);
+    }
+    let pseudonym = assign(state, chat_id).await?;
+    log::info!("🪪 User {} rotated their pseudonym", chat_id);
+    Ok(Rotation::Done(pseudonym))
+}
+
+/// The chat id behind a pseudonym, written with or without the `U-` prefix
+/// and in any case.
+pub async fn resolve(state: &AppState, reference: &str) -> Result<Option<i64>> {
+    let pseudonym = normalize(reference);
+    if pseudonym.len() != PREFIX.len() + DIGITS || !pseudonym[PREFIX.len()..].chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    state.moderation.resolve_pseudonym(&pseudonym).await
+}
+
+/// How a pseudonym is stored and shown: `u-7f3a2c` becomes `U-7F3A2C`.
+pub fn normalize(reference: &str) -> String {
+    let reference = reference.trim().to_uppercase();
+    match reference.strip_prefix(PREFIX) {
+        Some(_) => reference,
+        None => format!("{}{}", PREFIX, reference),
+    }
+}
+
+async fn assign(state: &AppState, chat_id: i64) -> Result<String> {
+    for _ in 0..MAX_ATTEMPTS {
+        let digits = Uuid::new_v4().simple().to_string()[..DIGITS].to_uppercase();
+        let pseudonym = format!("{}{}", PREFIX, digits);
+        if state.moderation.claim_pseudonym(chat_id, &pseudonym).await? {
+            return Ok(pseudonym);
+        }
+    }
+    Err(anyhow!("No free pseudonym for user {} after {} attempts", chat_id, MAX_ATTEMPTS))
+}
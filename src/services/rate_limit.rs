@@ -0,0 +1,132 @@
+use anyhow::Result;
+use teloxide::prelude::*;
+use crate::models::AppState;
+
+/// A token bucket: up to `burst` updates at once, then one every `refill_ms`.
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    pub burst: u32,
+    pub refill_ms: u64,
+}
+
+impl Budget {
+    pub const fn new(burst: u32, refill_ms: u64) -> Self {
+        Self { burst, refill_ms }
+    }
+}
+
+/// How much each user and each room may send, and how floods are punished.
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    pub messages: Budget,
+    pub media: Budget,
+    pub commands: Budget,
+    /// Shared by everyone in a room, since each message fans out to every member.
+    pub room: Budget,
+    /// Over-budget updates within this window count towards a mute.
+    pub strike_window_secs: u64,
+    pub strikes_before_mute: u32,
+    pub mute_secs: u64,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            messages: Budget::new(10, 1_000),
+            media: Budget::new(5, 4_000),
+            commands: Budget::new(10, 2_000),
+            room: Budget::new(20, 1_000),
+            strike_window_secs: 60,
+            strikes_before_mute: 10,
+            mute_secs: 300,
+        }
+    }
+}
+
+// At most one cooldown notice per user this often, so the notices do not
+// become a flood of their own
+const NOTICE_BUDGET: Budget = Budget::new(1, 10_000);
+
+/// What a user is sending, each with its own budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Traffic {
+    Message,
+    Media,
+    Command,
+}
+
+impl Traffic {
+    /// Text is a message; anything else a user sends counts as media.
+    pub fn of(msg: &Message) -> Self {
+        if msg.text().is_some() { Traffic::Message } else { Traffic::Media }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Traffic::Message => "messages",
+            Traffic::Media => "media",
+            Traffic::Command => "commands",
+        }
+    }
+}
+
+/// Takes `traffic` from the user's budget and, for room messages, from the
+/// room's. When either is spent, tells the user to slow down, mutes them
+/// after repeated floods, and returns `false`.
+pub async fn admit(
+    bot: &Bot,
+    state: &AppState,
+    chat_id: i64,
+    traffic: Traffic,
+    room_id: Option<&str>,
+) -> Result<bool> {
+    let limits = &state.rate_limits;
+
+    if let Some(remaining) = state.sessions.get_flood_mute(chat_id).await? {
+        notify(bot, state, chat_id, format!(
+            "🔇 You're muted for flooding. Try again in {} seconds.", remaining
+        )).await?;
+        return Ok(false);
+    }
+
+    let budget = match traffic {
+        Traffic::Message => limits.messages,
+        Traffic::Media => limits.media,
+        Traffic::Command => limits.commands,
+    };
+    let key = format!("{}:{}", traffic.name(), chat_id);
+    if !state.sessions.take_rate_token(&key, budget.burst, budget.refill_ms).await? {
+        let strikes = state.sessions.add_rate_strike(chat_id, limits.strike_window_secs).await?;
+        if strikes == limits.strikes_before_mute {
+            state.sessions.set_flood_mute(chat_id, limits.mute_secs).await?;
+            log::warn!("🔇 Muted user {} for {} seconds for flooding", chat_id, limits.mute_secs);
+            bot.send_message(ChatId(chat_id), format!(
+                "🔇 You've been muted for {} minutes for flooding.", limits.mute_secs / 60
+            )).await?;
+        } else {
+            notify(bot, state, chat_id, "🐢 Slow down! You're sending too fast, \
+                please wait a few seconds.".to_string()).await?;
+        }
+        return Ok(false);
+    }
+
+    if let Some(room_id) = room_id {
+        let key = format!("room:{}", room_id);
+        if !state.sessions.take_rate_token(&key, limits.room.burst, limits.room.refill_ms).await? {
+            notify(bot, state, chat_id, "🐢 This room is busy right now, \
+                please wait a few seconds.".to_string()).await?;
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Sends the user a notice unless they got one in the last few seconds.
+pub async fn notify(bot: &Bot, state: &AppState, chat_id: i64, text: String) -> Result<()> {
+    let key = format!("notice:{}", chat_id);
+    if state.sessions.take_rate_token(&key, NOTICE_BUDGET.burst, NOTICE_BUDGET.refill_ms).await? {
+        bot.send_message(ChatId(chat_id), text).await?;
+    }
+    Ok(())
+}
@@ -1,10 +1,161 @@
 use redis::AsyncCommands;
-use crate::models::UserState;
+use crate::models::{RecentMessage, Sanction, UserState};
+use crate::services::storage::{SessionStore, StateUpdate};
+use async_trait::async_trait;
 use anyhow::{Result, anyhow};
-use rand::seq::SliceRandom;
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SEARCH_QUEUE_KEY: &str = "search_queue";
+
+const SEARCH_TAGS_PREFIX: &str = "search_tags:";
+
+const SEARCH_LANGUAGES_KEY: &str = "search_languages";
+const BLOCKED_PREFIX: &str = "blocked:";
+// Users in a private chat or room, scored by last activity, for the inactivity reaper
+const ACTIVE_USERS_KEY: &str = "active_users";
+// `msg_link:{chat}:{message}` names the `msg_group:` hash of chat -> message id
+// holding every copy of one relayed message
+const MESSAGE_LINK_PREFIX: &str = "msg_link:";
+const MESSAGE_GROUP_PREFIX: &str = "msg_group:";
+const UNSEEN_MESSAGE_PREFIX: &str = "unseen:";
+const RATE_BUCKET_PREFIX: &str = "ratelimit:bucket:";
+const RATE_STRIKES_PREFIX: &str = "ratelimit:strikes:";
+const FLOOD_MUTE_PREFIX: &str = "ratelimit:muted:";
+const RECENT_MESSAGES_PREFIX: &str = "recent:";
+// Sorted set of shadow-banned chat ids, scored by when the shadow ban ends
+const SHADOWBANNED_KEY: &str = "shadowbanned";
+// JSON copy of a user's sanctions, so enforcing them needn't query MongoDB
+const SANCTION_CACHE_PREFIX: &str = "sanctions:";
+const LANGUAGE_CACHE_PREFIX: &str = "language:";
+// Users who blocked the bot, left out of broadcasts
+const UNREACHABLE_USERS_KEY: &str = "unreachable_users";
+/// How long a relayed message can still be replied to with threading.
+pub const MESSAGE_LINK_TTL_SECS: u64 = 48 * 60 * 60;
+/// How many relayed messages per conversation are kept for reports, and for
+/// how long.
+pub const RECENT_MESSAGE_LIMIT: usize = 20;
+pub const RECENT_MESSAGES_TTL_SECS: u64 = 24 * 60 * 60;
+/// How many of the longest-waiting users a /find considers, so matching costs
+/// the same however long the queue gets.
+pub const MATCH_CANDIDATE_WINDOW: usize = 100;
+
+// Enqueues the caller unless they are already waiting, keeping the time they
+// first joined, then takes the best waiting partner off the queue and returns
+// it with the time that partner joined. The caller stays queued until paired.
+// Users sharing a language are preferred, then
+// users sharing the most interest tags. A cross-language pair is only allowed
+// once both sides have waited past the language fallback time, and a pair
+// without shared tags once both sides either have no tags or have waited past
+// the tag fallback time. Users without a known language match any language.
+// Only the longest-waiting `MATCH_CANDIDATE_WINDOW` users are considered.
+// Users who blocked each other, in either direction, are never paired, and
+// shadow-banned users are only paired with each other. Runs atomically, so
+// two concurrent /find calls can never claim the same partner.
+const MATCH_OR_ENQUEUE_SCRIPT: &str = r#"
+local queue, languages, shadowbanned = KEYS[1], KEYS[2], KEYS[3]
+local me, now, fallback, prefix, lang_fallback, blocked = ARGV[1], tonumber(ARGV[2]), tonumber(ARGV[3]), ARGV[4], tonumber(ARGV[5]), ARGV[6]
+local window = tonumber(ARGV[7])
+redis.call('ZADD', queue, 'NX', now, me)
+local my_since = tonumber(redis.call('ZSCORE', queue, me))
+
+local function is_shadowbanned(user)
+    local until_secs = tonumber(redis.call('ZSCORE', shadowbanned, user))
+    return until_secs ~= nil and until_secs > now
+end
+local me_shadowbanned = is_shadowbanned(me)
+
+local my_tags = prefix .. me
+local me_flexible = redis.call('SCARD', my_tags) == 0 or now - my_since >= fallback
+local my_lang = redis.call('HGET', languages, me)
+local me_any_lang = now - my_since >= lang_fallback
+
+local waiting = redis.call('ZRANGE', queue, 0, window - 1, 'WITHSCORES')
+local best, best_score, best_since = nil, -1, nil
+for i = 1, #waiting, 2 do
+    local candidate, since = waiting[i], tonumber(waiting[i + 1])
+    if candidate ~= me then
+        local their_tags = prefix .. candidate
+        local shared = #redis.call('SINTER', my_tags, their_tags)
+        local tags_ok = shared > 0 or (me_flexible and
+            (redis.call('SCARD', their_tags) == 0 or now - since >= fallback))
+
+        local their_lang = redis.call('HGET', languages, candidate)
+        local same_lang = my_lang and their_lang and my_lang == their_lang
+        local lang_ok = same_lang or not my_lang or not their_lang or
+            (me_any_lang and now - since >= lang_fallback)
+
+        local not_blocked = redis.call('SISMEMBER', blocked .. me, candidate) == 0 and
+            redis.call('SISMEMBER', blocked .. candidate, me) == 0
+
+        local score = shared
+        if same_lang then
+            score = score + 1000
+        end
+        local same_standing = is_shadowbanned(candidate) == me_shadowbanned
+
+        if tags_ok and lang_ok and not_blocked and same_standing and score > best_score then
+            best, best_score, best_since = candidate, score, since
+        end
+    end
+end
+
+if best then
+    redis.call('ZREM', queue, best)
+    return {best, best_since}
+end
+return false
+"#;
+
+// Writes both paired states only if neither record changed since it was read,
+// so a user can never end up paired with two people at once.
+const PAIR_USERS_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) ~= ARGV[1] or redis.call('GET', KEYS[2]) ~= ARGV[2] then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[3])
+redis.call('SET', KEYS[2], ARGV[4])
+return 1
+"#;
+
+// Token bucket: refills the stored count for the time since it was last
+// touched, then takes a token if one is left. The caller passes the time so
+// every instance refills at the same rate.
+const TAKE_TOKEN_SCRIPT: &str = r#"
+local burst, refill_ms, now = tonumber(ARGV[1]), tonumber(ARGV[2]), tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'at')
+local tokens = tonumber(bucket[1]) or burst
+local at = tonumber(bucket[2]) or now
+tokens = math.min(burst, tokens + math.max(0, now - at) / refill_ms)
+local taken = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    taken = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'at', now)
+redis.call('PEXPIRE', KEYS[1], burst * refill_ms)
+return taken
+"#;
+
+// Single-record variant of the above. An empty string stands for a missing
+// key, both as the expected value and as the new value (which deletes it).
+const UPDATE_IF_UNCHANGED_SCRIPT: &str = r#"
+local current = redis.call('GET', KEYS[1]) or ''
+if current ~= ARGV[1] then
+    return 0
+end
+if ARGV[2] == '' then
+    redis.call('DEL', KEYS[1])
+else
+    redis.call('SET', KEYS[1], ARGV[2])
+end
+return 1
+"#;
+
+pub const MAX_UPDATE_ATTEMPTS: usize = 5;
 
 pub async fn get_user_state(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
     chat_id: i64,
 ) -> Result<Option<UserState>> {
     let key = format!("user:{}", chat_id);
@@ -27,69 +178,735 @@ pub async fn get_user_state(
 }
 
 pub async fn set_user_state(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
     state: &UserState,
 ) -> Result<()> {
     let key = format!("user:{}", state.chat_id);
     let data = serde_json::to_string(state).map_err(|e| anyhow!("Serialization error: {}", e))?;
-    redis.set(&key, data).await?;
+    let _: () = redis.set(&key, data).await?;
+    track_activity(redis, state).await?;
     Ok(())
 }
 
+/// Applies `update` to the user's state, retrying if someone else changed it
+/// in the meantime.
+pub async fn update_user_state(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    update: &StateUpdate<'_>,
+) -> Result<Option<UserState>> {
+    let key = format!("user:{}", chat_id);
+
+    for _ in 0..MAX_UPDATE_ATTEMPTS {
+        let raw: Option<String> = redis.get(&key).await?;
+        let Some(raw) = raw else {
+            return Ok(None);
+        };
+
+        let mut state: UserState = serde_json::from_str(&raw)?;
+        update(&mut state);
+        if compare_and_set(redis, &key, Some(&raw), Some(&serde_json::to_string(&state)?)).await? {
+            track_activity(redis, &state).await?;
+            return Ok(Some(state));
+        }
+    }
+
+    Err(anyhow!("Could not update state of {} after {} attempts", chat_id, MAX_UPDATE_ATTEMPTS))
+}
+
+async fn track_activity(
+    redis: &mut redis::aio::ConnectionManager,
+    state: &UserState,
+) -> Result<()> {
+    let _: () = if state.partner_id.is_some() || state.current_room.is_some() {
+        redis.zadd(ACTIVE_USERS_KEY, state.chat_id, state.last_activity).await?
+    } else {
+        redis.zrem(ACTIVE_USERS_KEY, state.chat_id).await?
+    };
+    Ok(())
+}
+
+/// Returns users in a private chat or room whose last activity is older than `timeout_secs`.
+pub async fn get_idle_users(
+    redis: &mut redis::aio::ConnectionManager,
+    timeout_secs: u64,
+) -> Result<Vec<i64>> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let idle: Vec<i64> = redis
+        .zrangebyscore(ACTIVE_USERS_KEY, "-inf", now.saturating_sub(timeout_secs))
+        .await?;
+    Ok(idle)
+}
+
 pub async fn find_random_partner(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
     chat_id: i64,
-) -> Result<Option<i64>> {
-    let pattern = "user:*";
-    let keys: Vec<String> = redis.keys(pattern).await?;
-    
-    let mut available_partners = Vec::new();
-    
-    for key in keys {
-        if let Ok(Some(state)) = get_user_state(redis, key.split(':').nth(1).unwrap().parse()?).await {
-            if state.chat_id != chat_id && state.is_searching && state.partner_id.is_none() {
-                available_partners.push(state.chat_id);
+    fallback_wait_secs: u64,
+    language_fallback_secs: u64,
+) -> Result<Option<(i64, u64)>> {
+    let script = redis::Script::new(MATCH_OR_ENQUEUE_SCRIPT);
+
+    loop {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let candidate: Option<(i64, u64)> = script
+            .key(SEARCH_QUEUE_KEY)
+            .key(SEARCH_LANGUAGES_KEY)
+            .key(SHADOWBANNED_KEY)
+            .arg(chat_id)
+            .arg(now)
+            .arg(fallback_wait_secs)
+            .arg(SEARCH_TAGS_PREFIX)
+            .arg(language_fallback_secs)
+            .arg(BLOCKED_PREFIX)
+            .arg(MATCH_CANDIDATE_WINDOW)
+            .invoke_async(redis)
+            .await?;
+
+        let Some((partner_id, since)) = candidate else {
+            return Ok(None);
+        };
+
+        // The queue may hold users who stopped searching since they were
+        // enqueued; drop them and keep looking.
+        match get_user_state(redis, partner_id).await? {
+            Some(state) if state.is_available_for_match() => {
+                return Ok(Some((partner_id, since)));
             }
+            _ => log::info!("🧹 Dropped stale search queue entry for user {}", partner_id),
         }
     }
-    
-    Ok(available_partners.choose(&mut rand::thread_rng()).copied())
 }
 
+/// Returns users who have been waiting in the search queue for at least
+/// `min_wait_secs`, longest-waiting first.
+pub async fn get_waiting_users(
+    redis: &mut redis::aio::ConnectionManager,
+    min_wait_secs: u64,
+) -> Result<Vec<i64>> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let waiting: Vec<i64> = redis
+        .zrangebyscore(SEARCH_QUEUE_KEY, "-inf", now.saturating_sub(min_wait_secs))
+        .await?;
+    Ok(waiting)
+}
+
+pub async fn set_search_tags(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    tags: &[String],
+) -> Result<()> {
+    let key = format!("{}{}", SEARCH_TAGS_PREFIX, chat_id);
+    let _: () = redis.del(&key).await?;
+    if !tags.is_empty() {
+        let _: () = redis.sadd(&key, tags).await?;
+    }
+    Ok(())
+}
+
+pub async fn set_search_language(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    language: Option<&str>,
+) -> Result<()> {
+    let _: () = match language {
+        Some(language) => redis.hset(SEARCH_LANGUAGES_KEY, chat_id, language).await?,
+        None => redis.hdel(SEARCH_LANGUAGES_KEY, chat_id).await?,
+    };
+    Ok(())
+}
+
+pub async fn block_user(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    blocked_id: i64,
+) -> Result<()> {
+    let _: () = redis.sadd(format!("{}{}", BLOCKED_PREFIX, chat_id), blocked_id).await?;
+    Ok(())
+}
+
+pub async fn link_messages(
+    redis: &mut redis::aio::ConnectionManager,
+    copies: &[(i64, i32)],
+) -> Result<()> {
+    let Some(&(origin_chat_id, origin_message_id)) = copies.first() else {
+        return Ok(());
+    };
+    let group_key = format!("{}{}:{}", MESSAGE_GROUP_PREFIX, origin_chat_id, origin_message_id);
+    let ttl = MESSAGE_LINK_TTL_SECS as usize;
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for &(chat_id, message_id) in copies {
+        pipe.hset(&group_key, chat_id, message_id).ignore();
+        pipe.set_ex(format!("{}{}:{}", MESSAGE_LINK_PREFIX, chat_id, message_id), &group_key, ttl)
+            .ignore();
+    }
+    pipe.expire(&group_key, ttl).ignore();
+    let _: () = pipe.query_async(redis).await?;
+    Ok(())
+}
+
+pub async fn get_linked_message(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    message_id: i32,
+    target_chat_id: i64,
+) -> Result<Option<i32>> {
+    let group_key: Option<String> = redis
+        .get(format!("{}{}:{}", MESSAGE_LINK_PREFIX, chat_id, message_id))
+        .await?;
+    let Some(group_key) = group_key else {
+        return Ok(None);
+    };
+    Ok(redis.hget(group_key, target_chat_id).await?)
+}
+
+pub async fn get_linked_messages(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    message_id: i32,
+) -> Result<Vec<(i64, i32)>> {
+    let group_key: Option<String> = redis
+        .get(format!("{}{}:{}", MESSAGE_LINK_PREFIX, chat_id, message_id))
+        .await?;
+    let Some(group_key) = group_key else {
+        return Ok(Vec::new());
+    };
+    let copies: HashMap<i64, i32> = redis.hgetall(group_key).await?;
+    Ok(copies.into_iter().collect())
+}
+
+pub async fn set_unseen_message(
+    redis: &mut redis::aio::ConnectionManager,
+    sender_id: i64,
+    recipient_id: i64,
+    message_id: i32,
+) -> Result<()> {
+    let key = format!("{}{}:{}", UNSEEN_MESSAGE_PREFIX, sender_id, recipient_id);
+    let _: () = redis.set_ex(key, message_id, MESSAGE_LINK_TTL_SECS as usize).await?;
+    Ok(())
+}
+
+pub async fn take_unseen_message(
+    redis: &mut redis::aio::ConnectionManager,
+    sender_id: i64,
+    recipient_id: i64,
+) -> Result<Option<i32>> {
+    let key = format!("{}{}:{}", UNSEEN_MESSAGE_PREFIX, sender_id, recipient_id);
+    Ok(redis::cmd("GETDEL").arg(key).query_async(redis).await?)
+}
+
+pub async fn take_rate_token(
+    redis: &mut redis::aio::ConnectionManager,
+    key: &str,
+    burst: u32,
+    refill_ms: u64,
+) -> Result<bool> {
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    let taken: i32 = redis::Script::new(TAKE_TOKEN_SCRIPT)
+        .key(format!("{}{}", RATE_BUCKET_PREFIX, key))
+        .arg(burst)
+        .arg(refill_ms.max(1))
+        .arg(now_ms)
+        .invoke_async(redis)
+        .await?;
+    Ok(taken == 1)
+}
+
+pub async fn add_rate_strike(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    window_secs: u64,
+) -> Result<u32> {
+    let key = format!("{}{}", RATE_STRIKES_PREFIX, chat_id);
+    let strikes: u32 = redis.incr(&key, 1).await?;
+    if strikes == 1 {
+        let _: () = redis.expire(&key, window_secs as usize).await?;
+    }
+    Ok(strikes)
+}
+
+pub async fn set_flood_mute(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    duration_secs: u64,
+) -> Result<()> {
+    let key = format!("{}{}", FLOOD_MUTE_PREFIX, chat_id);
+    let _: () = redis.set_ex(key, 1, duration_secs as usize).await?;
+    Ok(())
+}
+
+pub async fn get_flood_mute(redis: &mut redis::aio::ConnectionManager, chat_id: i64) -> Result<Option<u64>> {
+    let ttl: i64 = redis.ttl(format!("{}{}", FLOOD_MUTE_PREFIX, chat_id)).await?;
+    Ok((ttl > 0).then_some(ttl as u64))
+}
+
+pub async fn record_recent_message(
+    redis: &mut redis::aio::ConnectionManager,
+    conversation: &str,
+    message: &RecentMessage,
+) -> Result<()> {
+    let key = format!("{}{}", RECENT_MESSAGES_PREFIX, conversation);
+    let _: () = redis::pipe()
+        .atomic()
+        .rpush(&key, serde_json::to_string(message)?)
+        .ltrim(&key, -(RECENT_MESSAGE_LIMIT as isize), -1)
+        .expire(&key, RECENT_MESSAGES_TTL_SECS as usize)
+        .query_async(redis)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_recent_messages(
+    redis: &mut redis::aio::ConnectionManager,
+    conversation: &str,
+) -> Result<Vec<RecentMessage>> {
+    let raw: Vec<String> = redis.lrange(format!("{}{}", RECENT_MESSAGES_PREFIX, conversation), 0, -1).await?;
+    Ok(raw
+        .iter()
+        .filter_map(|message| serde_json::from_str(message).ok())
+        .collect())
+}
+
+pub async fn set_shadowban(redis: &mut redis::aio::ConnectionManager, chat_id: i64, until_secs: u64) -> Result<()> {
+    let _: () = redis.zadd(SHADOWBANNED_KEY, chat_id, until_secs).await?;
+    Ok(())
+}
+
+pub async fn clear_shadowban(redis: &mut redis::aio::ConnectionManager, chat_id: i64) -> Result<()> {
+    let _: () = redis.zrem(SHADOWBANNED_KEY, chat_id).await?;
+    Ok(())
+}
+
+pub async fn cache_sanctions(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    sanctions: &[Sanction],
+    ttl_secs: u64,
+) -> Result<()> {
+    let key = format!("{}{}", SANCTION_CACHE_PREFIX, chat_id);
+    let _: () = redis.set_ex(key, serde_json::to_string(sanctions)?, ttl_secs as usize).await?;
+    Ok(())
+}
+
+pub async fn get_cached_sanctions(redis: &mut redis::aio::ConnectionManager, chat_id: i64) -> Result<Option<Vec<Sanction>>> {
+    let raw: Option<String> = redis.get(format!("{}{}", SANCTION_CACHE_PREFIX, chat_id)).await?;
+    // A copy that no longer parses is treated as missing and reloaded
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+pub async fn forget_cached_sanctions(redis: &mut redis::aio::ConnectionManager, chat_id: i64) -> Result<()> {
+    let _: () = redis.del(format!("{}{}", SANCTION_CACHE_PREFIX, chat_id)).await?;
+    Ok(())
+}
+
+pub async fn cache_language(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    language: Option<&str>,
+    ttl_secs: u64,
+) -> Result<()> {
+    let key = format!("{}{}", LANGUAGE_CACHE_PREFIX, chat_id);
+    let _: () = redis.set_ex(key, serde_json::to_string(&language)?, ttl_secs as usize).await?;
+    Ok(())
+}
+
+pub async fn get_cached_language(redis: &mut redis::aio::ConnectionManager, chat_id: i64) -> Result<Option<Option<String>>> {
+    let raw: Option<String> = redis.get(format!("{}{}", LANGUAGE_CACHE_PREFIX, chat_id)).await?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// Whether either user has blocked the other.
+pub async fn is_blocked_between(
+    redis: &mut redis::aio::ConnectionManager,
+    user1_id: i64,
+    user2_id: i64,
+) -> Result<bool> {
+    let blocked_by_user1: bool = redis.sismember(format!("{}{}", BLOCKED_PREFIX, user1_id), user2_id).await?;
+    let blocked_by_user2: bool = redis.sismember(format!("{}{}", BLOCKED_PREFIX, user2_id), user1_id).await?;
+    Ok(blocked_by_user1 || blocked_by_user2)
+}
+
+pub async fn get_shared_tags(
+    redis: &mut redis::aio::ConnectionManager,
+    user1_id: i64,
+    user2_id: i64,
+) -> Result<Vec<String>> {
+    let mut shared: Vec<String> = redis
+        .sinter(&[
+            format!("{}{}", SEARCH_TAGS_PREFIX, user1_id),
+            format!("{}{}", SEARCH_TAGS_PREFIX, user2_id),
+        ])
+        .await?;
+    shared.sort();
+    Ok(shared)
+}
+
+pub async fn add_to_search_queue(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    since: u64,
+) -> Result<()> {
+    let _: () = redis.zadd(SEARCH_QUEUE_KEY, chat_id, since).await?;
+    Ok(())
+}
+
+pub async fn remove_from_search_queue(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+) -> Result<()> {
+    let _: () = redis.zrem(SEARCH_QUEUE_KEY, chat_id).await?;
+    Ok(())
+}
+
+/// Atomically pairs two searching users. Returns `false` when either side is
+/// no longer available, e.g. because a concurrent /find already took them.
 pub async fn connect_users(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
     user1_id: i64,
     user2_id: i64,
+) -> Result<bool> {
+    let script = redis::Script::new(PAIR_USERS_SCRIPT);
+    let user1_key = format!("user:{}", user1_id);
+    let user2_key = format!("user:{}", user2_id);
+
+    for _ in 0..MAX_UPDATE_ATTEMPTS {
+        let user1_raw: Option<String> = redis.get(&user1_key).await?;
+        let user2_raw: Option<String> = redis.get(&user2_key).await?;
+        let (Some(user1_raw), Some(user2_raw)) = (user1_raw, user2_raw) else {
+            return Ok(false);
+        };
+
+        let mut user1_state: UserState = serde_json::from_str(&user1_raw)?;
+        let mut user2_state: UserState = serde_json::from_str(&user2_raw)?;
+        if !user1_state.is_available_for_match() || !user2_state.is_available_for_match() {
+            return Ok(false);
+        }
+
+        user1_state.partner_id = Some(user2_id);
+        user1_state.is_searching = false;
+        user1_state.update_activity();
+        user2_state.partner_id = Some(user1_id);
+        user2_state.is_searching = false;
+        user2_state.update_activity();
+
+        let paired: i32 = script
+            .key(&user1_key)
+            .key(&user2_key)
+            .arg(&user1_raw)
+            .arg(&user2_raw)
+            .arg(serde_json::to_string(&user1_state)?)
+            .arg(serde_json::to_string(&user2_state)?)
+            .invoke_async(redis)
+            .await?;
+
+        if paired == 1 {
+            track_activity(redis, &user1_state).await?;
+            track_activity(redis, &user2_state).await?;
+            return Ok(true);
+        }
+        log::info!("🔁 Pairing {} with {} raced with another update, retrying", user1_id, user2_id);
+    }
+
+    Err(anyhow!("Could not pair {} with {} after {} attempts", user1_id, user2_id, MAX_UPDATE_ATTEMPTS))
+}
+
+/// Sets `key` to `new` (deleting it for `None`) only if it still holds
+/// `expected`. Returns `false` when another writer got there first.
+pub async fn compare_and_set(
+    redis: &mut redis::aio::ConnectionManager,
+    key: &str,
+    expected: Option<&str>,
+    new: Option<&str>,
+) -> Result<bool> {
+    let updated: i32 = redis::Script::new(UPDATE_IF_UNCHANGED_SCRIPT)
+        .key(key)
+        .arg(expected.unwrap_or_default())
+        .arg(new.unwrap_or_default())
+        .invoke_async(redis)
+        .await?;
+    Ok(updated == 1)
+}
+
+/// Stops the user's search. Returns `false` if they weren't searching.
+pub async fn cancel_search(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+) -> Result<bool> {
+    stop_searching_if(redis, chat_id, |_| true).await
+}
+
+/// Stops the user's search if they've been idle for longer than `timeout_secs`.
+pub async fn expire_search(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    timeout_secs: u64,
+) -> Result<bool> {
+    stop_searching_if(redis, chat_id, |state| state.is_inactive(timeout_secs)).await
+}
+
+async fn stop_searching_if(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    should_stop: impl Fn(&UserState) -> bool,
+) -> Result<bool> {
+    let key = format!("user:{}", chat_id);
+
+    for _ in 0..MAX_UPDATE_ATTEMPTS {
+        let raw: Option<String> = redis.get(&key).await?;
+        let Some(raw) = raw else {
+            remove_from_search_queue(redis, chat_id).await?;
+            return Ok(false);
+        };
+
+        let mut state: UserState = serde_json::from_str(&raw)?;
+        if !state.is_available_for_match() {
+            remove_from_search_queue(redis, chat_id).await?;
+            return Ok(false);
+        }
+        if !should_stop(&state) {
+            return Ok(false);
+        }
+
+        state.is_searching = false;
+        if compare_and_set(redis, &key, Some(&raw), Some(&serde_json::to_string(&state)?)).await? {
+            remove_from_search_queue(redis, chat_id).await?;
+            return Ok(true);
+        }
+    }
+
+    Err(anyhow!("Could not stop search for {} after {} attempts", chat_id, MAX_UPDATE_ATTEMPTS))
+}
+
+/// Ends a private chat on both sides. Each user keeps the other as
+/// `last_partner_id` so they can still /block them right after the chat.
+pub async fn end_private_chat(
+    redis: &mut redis::aio::ConnectionManager,
+    chat_id: i64,
+    partner_id: i64,
 ) -> Result<()> {
-    // Update user1 state
-    let mut user1_state = match get_user_state(redis, user1_id).await? {
-        Some(state) => state,
-        None => UserState::new(user1_id),
-    };
-    user1_state.partner_id = Some(user2_id);
-    user1_state.is_searching = false;
-    user1_state.update_activity();
-    set_user_state(redis, &user1_state).await?;
-    
-    // Update user2 state
-    let mut user2_state = match get_user_state(redis, user2_id).await? {
-        Some(state) => state,
-        None => UserState::new(user2_id),
-    };
-    user2_state.partner_id = Some(user1_id);
-    user2_state.is_searching = false;
-    user2_state.update_activity();
-    set_user_state(redis, &user2_state).await?;
-    
+    for (user_id, other_id) in [(chat_id, partner_id), (partner_id, chat_id)] {
+        update_user_state(redis, user_id, &|state| {
+            // A user who already moved on keeps their new chat or search
+            if state.partner_id == Some(other_id) {
+                state.partner_id = None;
+                state.last_partner_id = Some(other_id);
+                state.is_searching = false;
+            }
+        }).await?;
+    }
     Ok(())
 }
 
 // Helper function to clean up old data
 pub async fn cleanup_user_state(
-    redis: &mut redis::aio::Connection,
+    redis: &mut redis::aio::ConnectionManager,
     chat_id: i64,
 ) -> Result<()> {
     let key = format!("user:{}", chat_id);
-    redis.del(&key).await?;
+    let _: () = redis.del(&key).await?;
+    let _: () = redis.del(format!("{}{}", SEARCH_TAGS_PREFIX, chat_id)).await?;
+    let _: () = redis.hdel(SEARCH_LANGUAGES_KEY, chat_id).await?;
+    let _: () = redis.zrem(ACTIVE_USERS_KEY, chat_id).await?;
+    let _: () = redis.srem(UNREACHABLE_USERS_KEY, chat_id).await?;
+    remove_from_search_queue(redis, chat_id).await?;
     Ok(())
-} 
\ No newline at end of file
+} 
+/// Redis-backed session and room storage.
+#[derive(Clone)]
+pub struct RedisStore {
+    redis: redis::aio::ConnectionManager,
+}
+
+impl RedisStore {
+    pub fn new(redis: redis::aio::ConnectionManager) -> Self {
+        Self { redis }
+    }
+
+    // `ConnectionManager` is a cheap handle onto one multiplexed connection
+    pub(crate) fn connection(&self) -> redis::aio::ConnectionManager {
+        self.redis.clone()
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisStore {
+    async fn get_user_state(&self, chat_id: i64) -> Result<Option<UserState>> {
+        get_user_state(&mut self.connection(), chat_id).await
+    }
+
+    async fn set_user_state(&self, state: &UserState) -> Result<()> {
+        set_user_state(&mut self.connection(), state).await
+    }
+
+    async fn update_user_state(
+        &self,
+        chat_id: i64,
+        update: &StateUpdate<'_>,
+    ) -> Result<Option<UserState>> {
+        update_user_state(&mut self.connection(), chat_id, update).await
+    }
+
+    async fn cleanup_user_state(&self, chat_id: i64) -> Result<()> {
+        cleanup_user_state(&mut self.connection(), chat_id).await
+    }
+
+    async fn reachable_user_ids(&self) -> Result<Vec<i64>> {
+        let mut redis = self.connection();
+        let unreachable: HashSet<i64> = redis.smembers(UNREACHABLE_USERS_KEY).await?;
+        // SCAN rather than KEYS, which blocks Redis while it walks every key
+        let mut keys: redis::AsyncIter<String> = redis.scan_match("user:*").await?;
+        let mut chat_ids = Vec::new();
+        while let Some(key) = keys.next_item().await {
+            if let Some(chat_id) = key.split(':').nth(1).and_then(|id| id.parse().ok()) {
+                if !unreachable.contains(&chat_id) {
+                    chat_ids.push(chat_id);
+                }
+            }
+        }
+        Ok(chat_ids)
+    }
+
+    async fn mark_unreachable(&self, chat_id: i64) -> Result<()> {
+        let _: () = self.connection().sadd(UNREACHABLE_USERS_KEY, chat_id).await?;
+        Ok(())
+    }
+
+    async fn find_random_partner(
+        &self,
+        chat_id: i64,
+        fallback_wait_secs: u64,
+        language_fallback_secs: u64,
+    ) -> Result<Option<(i64, u64)>> {
+        find_random_partner(&mut self.connection(), chat_id, fallback_wait_secs, language_fallback_secs).await
+    }
+
+    async fn add_to_search_queue(&self, chat_id: i64, since: u64) -> Result<()> {
+        add_to_search_queue(&mut self.connection(), chat_id, since).await
+    }
+
+    async fn remove_from_search_queue(&self, chat_id: i64) -> Result<()> {
+        remove_from_search_queue(&mut self.connection(), chat_id).await
+    }
+
+    async fn get_waiting_users(&self, min_wait_secs: u64) -> Result<Vec<i64>> {
+        get_waiting_users(&mut self.connection(), min_wait_secs).await
+    }
+
+    async fn set_search_tags(&self, chat_id: i64, tags: &[String]) -> Result<()> {
+        set_search_tags(&mut self.connection(), chat_id, tags).await
+    }
+
+    async fn get_shared_tags(&self, user1_id: i64, user2_id: i64) -> Result<Vec<String>> {
+        get_shared_tags(&mut self.connection(), user1_id, user2_id).await
+    }
+
+    async fn set_search_language(&self, chat_id: i64, language: Option<&str>) -> Result<()> {
+        set_search_language(&mut self.connection(), chat_id, language).await
+    }
+
+    async fn connect_users(&self, user1_id: i64, user2_id: i64) -> Result<bool> {
+        connect_users(&mut self.connection(), user1_id, user2_id).await
+    }
+
+    async fn end_private_chat(&self, chat_id: i64, partner_id: i64) -> Result<()> {
+        end_private_chat(&mut self.connection(), chat_id, partner_id).await
+    }
+
+    async fn cancel_search(&self, chat_id: i64) -> Result<bool> {
+        cancel_search(&mut self.connection(), chat_id).await
+    }
+
+    async fn expire_search(&self, chat_id: i64, timeout_secs: u64) -> Result<bool> {
+        expire_search(&mut self.connection(), chat_id, timeout_secs).await
+    }
+
+    async fn block_user(&self, chat_id: i64, blocked_id: i64) -> Result<()> {
+        block_user(&mut self.connection(), chat_id, blocked_id).await
+    }
+
+    async fn link_messages(&self, copies: &[(i64, i32)]) -> Result<()> {
+        link_messages(&mut self.connection(), copies).await
+    }
+
+    async fn get_linked_message(&self, chat_id: i64, message_id: i32, target_chat_id: i64) -> Result<Option<i32>> {
+        get_linked_message(&mut self.connection(), chat_id, message_id, target_chat_id).await
+    }
+
+    async fn get_linked_messages(&self, chat_id: i64, message_id: i32) -> Result<Vec<(i64, i32)>> {
+        get_linked_messages(&mut self.connection(), chat_id, message_id).await
+    }
+
+    async fn set_unseen_message(&self, sender_id: i64, recipient_id: i64, message_id: i32) -> Result<()> {
+        set_unseen_message(&mut self.connection(), sender_id, recipient_id, message_id).await
+    }
+
+    async fn take_unseen_message(&self, sender_id: i64, recipient_id: i64) -> Result<Option<i32>> {
+        take_unseen_message(&mut self.connection(), sender_id, recipient_id).await
+    }
+
+    async fn get_idle_users(&self, timeout_secs: u64) -> Result<Vec<i64>> {
+        get_idle_users(&mut self.connection(), timeout_secs).await
+    }
+
+    async fn take_rate_token(&self, key: &str, burst: u32, refill_ms: u64) -> Result<bool> {
+        take_rate_token(&mut self.connection(), key, burst, refill_ms).await
+    }
+
+    async fn add_rate_strike(&self, chat_id: i64, window_secs: u64) -> Result<u32> {
+        add_rate_strike(&mut self.connection(), chat_id, window_secs).await
+    }
+
+    async fn set_flood_mute(&self, chat_id: i64, duration_secs: u64) -> Result<()> {
+        set_flood_mute(&mut self.connection(), chat_id, duration_secs).await
+    }
+
+    async fn get_flood_mute(&self, chat_id: i64) -> Result<Option<u64>> {
+        get_flood_mute(&mut self.connection(), chat_id).await
+    }
+
+    async fn record_recent_message(&self, conversation: &str, message: &RecentMessage) -> Result<()> {
+        record_recent_message(&mut self.connection(), conversation, message).await
+    }
+
+    async fn get_recent_messages(&self, conversation: &str) -> Result<Vec<RecentMessage>> {
+        get_recent_messages(&mut self.connection(), conversation).await
+    }
+
+    async fn set_shadowban(&self, chat_id: i64, until_secs: u64) -> Result<()> {
+        set_shadowban(&mut self.connection(), chat_id, until_secs).await
+    }
+
+    async fn clear_shadowban(&self, chat_id: i64) -> Result<()> {
+        clear_shadowban(&mut self.connection(), chat_id).await
+    }
+
+    async fn cache_sanctions(&self, chat_id: i64, sanctions: &[Sanction], ttl_secs: u64) -> Result<()> {
+        cache_sanctions(&mut self.connection(), chat_id, sanctions, ttl_secs).await
+    }
+
+    async fn get_cached_sanctions(&self, chat_id: i64) -> Result<Option<Vec<Sanction>>> {
+        get_cached_sanctions(&mut self.connection(), chat_id).await
+    }
+
+    async fn forget_cached_sanctions(&self, chat_id: i64) -> Result<()> {
+        forget_cached_sanctions(&mut self.connection(), chat_id).await
+    }
+
+    async fn cache_language(&self, chat_id: i64, language: Option<&str>, ttl_secs: u64) -> Result<()> {
+        cache_language(&mut self.connection(), chat_id, language, ttl_secs).await
+    }
+
+    async fn get_cached_language(&self, chat_id: i64) -> Result<Option<Option<String>>> {
+        get_cached_language(&mut self.connection(), chat_id).await
+    }
+}
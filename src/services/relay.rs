@@ -0,0 +1,450 @@
+use anyhow::Result;
+use teloxide::{
+    prelude::*,
+    types::{
+        ChatAction, DiceEmoji, InputFile, InputMedia, InputMediaAnimation, InputMediaAudio, InputMediaDocument,
+        InputMediaPhoto, InputMediaVideo, MediaKind, MessageId, MessageKind, PollType,
+    },
+};
+use crate::models::{AppState, UserState};
+use super::{content_filter::{ContentFilter, Verdict}, storage::SessionStore};
+
+/// Media that is re-sent by file_id with an optional caption.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionedMedia {
+    Photo,
+    Video,
+    Animation,
+    Document,
+    Audio,
+    Voice,
+}
+
+impl CaptionedMedia {
+    fn name(self) -> &'static str {
+        match self {
+            CaptionedMedia::Photo => "photo",
+            CaptionedMedia::Video => "video",
+            CaptionedMedia::Animation => "GIF",
+            CaptionedMedia::Document => "file",
+            CaptionedMedia::Audio => "audio",
+            CaptionedMedia::Voice => "voice note",
+        }
+    }
+}
+
+/// The part of a user's message that is relayed anonymously. Files are
+/// referenced by file_id, so nothing is downloaded or re-uploaded.
+#[derive(Debug, Clone)]
+pub enum RelayContent {
+    Text(String),
+    Media {
+        kind: CaptionedMedia,
+        file_id: String,
+        caption: String,
+    },
+    VideoNote(String),
+    Sticker(String),
+    Location {
+        latitude: f64,
+        longitude: f64,
+    },
+    Venue {
+        latitude: f64,
+        longitude: f64,
+        title: String,
+        address: String,
+    },
+    Contact {
+        phone_number: String,
+        first_name: String,
+        last_name: Option<String>,
+    },
+    Poll {
+        question: String,
+        options: Vec<String>,
+        allows_multiple_answers: bool,
+        correct_option_id: Option<u8>,
+    },
+    Dice(DiceEmoji),
+}
+
+impl RelayContent {
+    /// Extracts the relayable content of a message, or `None` for kinds the
+    /// bot does not relay.
+    pub fn from_message(msg: &Message) -> Option<Self> {
+        let common = match &msg.kind {
+            MessageKind::Common(common) => common,
+            MessageKind::Dice(dice) => return Some(RelayContent::Dice(dice.dice.emoji)),
+            _ => return None,
+        };
+
+        let media = |kind, file_id: &str, caption: &Option<String>| RelayContent::Media {
+            kind,
+            file_id: file_id.to_string(),
+            caption: caption.clone().unwrap_or_default(),
+        };
+
+        let content = match &common.media_kind {
+            MediaKind::Text(text) => RelayContent::Text(text.text.clone()),
+            MediaKind::Photo(photo) => {
+                let largest_photo = photo.photo.last()?;
+                media(CaptionedMedia::Photo, &largest_photo.file.id, &photo.caption)
+            }
+            MediaKind::Video(video) => media(CaptionedMedia::Video, &video.video.file.id, &video.caption),
+            MediaKind::Animation(animation) => {
+                media(CaptionedMedia::Animation, &animation.animation.file.id, &animation.caption)
+            }
+            MediaKind::Document(document) => {
+                media(CaptionedMedia::Document, &document.document.file.id, &document.caption)
+            }
+            MediaKind::Audio(audio) => media(CaptionedMedia::Audio, &audio.audio.file.id, &audio.caption),
+            MediaKind::Voice(voice) => media(CaptionedMedia::Voice, &voice.voice.file.id, &voice.caption),
+            MediaKind::VideoNote(video_note) => RelayContent::VideoNote(video_note.video_note.file.id.clone()),
+            MediaKind::Sticker(sticker) => RelayContent::Sticker(sticker.sticker.file.id.clone()),
+            MediaKind::Location(location) => RelayContent::Location {
+                latitude: location.location.latitude,
+                longitude: location.location.longitude,
+            },
+            MediaKind::Venue(venue) => RelayContent::Venue {
+                latitude: venue.venue.location.latitude,
+                longitude: venue.venue.location.longitude,
+                title: venue.venue.title.clone(),
+                address: venue.venue.address.clone(),
+            },
+            MediaKind::Contact(contact) => RelayContent::Contact {
+                phone_number: contact.contact.phone_number.clone(),
+                first_name: contact.contact.first_name.clone(),
+                last_name: contact.contact.last_name.clone(),
+            },
+            MediaKind::Poll(poll) => RelayContent::Poll {
+                question: poll.poll.question.clone(),
+                options: poll.poll.options.iter().map(|option| option.text.clone()).collect(),
+                allows_multiple_answers: poll.poll.allows_multiple_answers,
+                // The correct answer of a quiz is only visible to its sender's client
+                correct_option_id: match poll.poll.poll_type {
+                    PollType::Quiz => poll.poll.correct_option_id,
+                    PollType::Regular => None,
+                },
+            },
+            _ => return None,
+        };
+
+        Some(content)
+    }
+
+    /// The warning shown to the sender when the content is rejected.
+    pub fn rejection_message(&self) -> String {
+        let what = match self {
+            RelayContent::Media { kind, .. } => format!("{} caption", kind.name()),
+            RelayContent::Poll { .. } => "poll".to_string(),
+            _ => "message".to_string(),
+        };
+        format!("⚠️ Your {} contains inappropriate content and was not sent.", what)
+    }
+
+    /// The chat action shown to the recipient while this content is on its way.
+    pub fn chat_action(&self) -> ChatAction {
+        match self {
+            RelayContent::Media { kind, .. } => match kind {
+                CaptionedMedia::Photo => ChatAction::UploadPhoto,
+                CaptionedMedia::Video | CaptionedMedia::Animation => ChatAction::UploadVideo,
+                CaptionedMedia::Document | CaptionedMedia::Audio => ChatAction::UploadDocument,
+                CaptionedMedia::Voice => ChatAction::RecordVoice,
+            },
+            RelayContent::VideoNote(_) => ChatAction::RecordVideoNote,
+            RelayContent::Location { .. } | RelayContent::Venue { .. } => ChatAction::FindLocation,
+            _ => ChatAction::Typing,
+        }
+    }
+
+    /// A one-line description for moderators, e.g. "[photo] caption".
+    pub fn summary(&self) -> String {
+        let (what, text) = match self {
+            RelayContent::Text(text) => return text.clone(),
+            RelayContent::Media { kind, caption, .. } => (kind.name(), caption.as_str()),
+            RelayContent::VideoNote(_) => ("video note", ""),
+            RelayContent::Sticker(_) => ("sticker", ""),
+            RelayContent::Location { .. } => ("location", ""),
+            RelayContent::Venue { title, .. } => ("venue", title.as_str()),
+            RelayContent::Contact { .. } => ("contact", ""),
+            RelayContent::Poll { question, .. } => ("poll", question.as_str()),
+            RelayContent::Dice(_) => ("dice", ""),
+        };
+        format!("[{}] {}", what, text).trim_end().to_string()
+    }
+
+    /// Runs everything the user typed through the content filter, masking
+    /// censored words and masked info.
+    pub fn moderate(self, filter: &ContentFilter, language: Option<&str>) -> Verdict<Self> {
+        let mut blocked = false;
+        let mut flagged_word = None;
+        let mut withheld = None;
+        let mut warned = None;
+        let mut check = |text: String| match filter.check(&text, language) {
+            Verdict::Allow(text) => text,
+            Verdict::Warn { content, kind } => {
+                warned.get_or_insert(kind);
+                content
+            }
+            Verdict::Withhold { kind } => {
+                withheld.get_or_insert(kind);
+                text
+            }
+            Verdict::Block => {
+                blocked = true;
+                text
+            }
+            Verdict::Flag { word } => {
+                flagged_word.get_or_insert(word);
+                text
+            }
+        };
+
+        let content = match self {
+            RelayContent::Text(text) => RelayContent::Text(check(text)),
+            RelayContent::Media { kind, file_id, caption } => RelayContent::Media {
+                kind,
+                file_id,
+                caption: check(caption),
+            },
+            RelayContent::Poll { question, options, allows_multiple_answers, correct_option_id } => {
+                RelayContent::Poll {
+                    question: check(question),
+                    options: options.into_iter().map(&mut check).collect(),
+                    allows_multiple_answers,
+                    correct_option_id,
+                }
+            }
+            other => other,
+        };
+
+        match (flagged_word, withheld, warned) {
+            (Some(word), _, _) => Verdict::Flag { word },
+            _ if blocked => Verdict::Block,
+            (None, Some(kind), _) => Verdict::Withhold { kind },
+            (None, None, Some(kind)) => Verdict::Warn { content, kind },
+            (None, None, None) => Verdict::Allow(content),
+        }
+    }
+
+    /// Prefixes text and non-empty captions with `label`, as room messages are.
+    pub fn labelled(self, label: &str) -> Self {
+        match self {
+            RelayContent::Text(text) => RelayContent::Text(format!("{}{}", label, text)),
+            RelayContent::Media { kind, file_id, caption } if !caption.is_empty() => RelayContent::Media {
+                kind,
+                file_id,
+                caption: format!("{}{}", label, caption),
+            },
+            other => other,
+        }
+    }
+}
+
+/// Who receives what the user sends: their partner, or the other members of
+/// their room.
+pub async fn recipients(state: &AppState, user_state: &UserState) -> Result<Vec<i64>> {
+    if let Some(room_id) = &user_state.current_room {
+        return Ok(state
+            .rooms
+            .get_room(room_id)
+            .await?
+            .map(|room| room.members)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|&member_id| member_id != user_state.chat_id)
+            .collect());
+    }
+    Ok(user_state.partner_id.into_iter().collect())
+}
+
+/// Names the conversation the user is in, the same for everyone in it.
+pub fn conversation_id(user_state: &UserState) -> Option<String> {
+    if let Some(room_id) = &user_state.current_room {
+        return Some(format!("room:{}", room_id));
+    }
+    user_state
+        .partner_id
+        .map(|partner_id| private_conversation_id(user_state.chat_id, partner_id))
+}
+
+pub fn private_conversation_id(user1_id: i64, user2_id: i64) -> String {
+    format!("private:{}:{}", user1_id.min(user2_id), user1_id.max(user2_id))
+}
+
+/// Sends `content` to each recipient as a copy of `msg`. A reply is threaded
+/// onto the recipient's copy of the replied-to message, and the new copies are
+/// linked for later replies. Failed deliveries are logged and skipped.
+pub async fn relay_message(
+    bot: &Bot,
+    sessions: &dyn SessionStore,
+    msg: &Message,
+    recipients: &[i64],
+    content: &RelayContent,
+) -> Result<()> {
+    let sender_id = msg.chat.id.0;
+    let replied_to = msg.reply_to_message().map(|reply| reply.id.0);
+    let mut copies = vec![(sender_id, msg.id.0)];
+
+    for &recipient in recipients {
+        let reply_to = match replied_to {
+            Some(message_id) => sessions
+                .get_linked_message(sender_id, message_id, recipient)
+                .await?
+                .map(MessageId),
+            None => None,
+        };
+        match send(bot, ChatId(recipient), content, reply_to).await {
+            Ok(sent) => copies.push((recipient, sent.id.0)),
+            Err(e) => log::warn!("⚠️ Failed to relay message from {} to {}: {}", sender_id, recipient, e),
+        }
+    }
+
+    sessions.link_messages(&copies).await
+}
+
+/// Sends `content` to `chat_id` as a message from the bot, optionally as a
+/// reply to `reply_to` in that chat.
+pub async fn send(
+    bot: &Bot,
+    chat_id: ChatId,
+    content: &RelayContent,
+    reply_to: Option<MessageId>,
+) -> Result<Message> {
+    // Every send request has the same reply setters, but no shared trait for them
+    macro_rules! send {
+        ($request:expr) => {
+            send!($request, reply_to)
+        };
+        ($request:expr, $reply_to:expr) => {{
+            let request = $request;
+            match $reply_to {
+                Some(message_id) => {
+                    request
+                        .reply_to_message_id(message_id)
+                        .allow_sending_without_reply(true)
+                        .await?
+                }
+                None => request.await?,
+            }
+        }};
+    }
+
+    let message = match content.clone() {
+        RelayContent::Text(text) => send!(bot.send_message(chat_id, text)),
+        RelayContent::Media { kind, file_id, caption } => {
+            let file = InputFile::file_id(file_id);
+            match kind {
+                CaptionedMedia::Photo => send!(bot.send_photo(chat_id, file).caption(caption)),
+                CaptionedMedia::Video => send!(bot.send_video(chat_id, file).caption(caption)),
+                CaptionedMedia::Animation => send!(bot.send_animation(chat_id, file).caption(caption)),
+                CaptionedMedia::Document => send!(bot.send_document(chat_id, file).caption(caption)),
+                CaptionedMedia::Audio => send!(bot.send_audio(chat_id, file).caption(caption)),
+                CaptionedMedia::Voice => send!(bot.send_voice(chat_id, file).caption(caption)),
+            }
+        }
+        RelayContent::VideoNote(file_id) => send!(bot.send_video_note(chat_id, InputFile::file_id(file_id))),
+        // `SendSticker` still takes a bare message id
+        RelayContent::Sticker(file_id) => send!(
+            bot.send_sticker(chat_id, InputFile::file_id(file_id)),
+            reply_to.map(|message_id| message_id.0)
+        ),
+        RelayContent::Location { latitude, longitude } => {
+            send!(bot.send_location(chat_id, latitude, longitude))
+        }
+        RelayContent::Venue { latitude, longitude, title, address } => {
+            send!(bot.send_venue(chat_id, latitude, longitude, title, address))
+        }
+        RelayContent::Contact { phone_number, first_name, last_name } => {
+            let mut request = bot.send_contact(chat_id, phone_number, first_name);
+            if let Some(last_name) = last_name {
+                request = request.last_name(last_name);
+            }
+            send!(request)
+        }
+        RelayContent::Poll { question, options, allows_multiple_answers, correct_option_id } => {
+            let mut request = bot
+                .send_poll(chat_id, question, options)
+                .allows_multiple_answers(allows_multiple_answers);
+            if let Some(correct_option_id) = correct_option_id {
+                request = request.type_(PollType::Quiz).correct_option_id(correct_option_id);
+            }
+            send!(request)
+        }
+        RelayContent::Dice(emoji) => send!(bot.send_dice(chat_id).emoji(emoji)),
+    };
+
+    Ok(message)
+}
+
+/// Applies an edit of `msg` to each recipient's copy of it. Recipients without
+/// a copy, and content Telegram cannot edit, are skipped.
+pub async fn relay_edit(
+    bot: &Bot,
+    sessions: &dyn SessionStore,
+    msg: &Message,
+    recipients: &[i64],
+    content: &RelayContent,
+) -> Result<()> {
+    let sender_id = msg.chat.id.0;
+    for &recipient in recipients {
+        let Some(copy_id) = sessions.get_linked_message(sender_id, msg.id.0, recipient).await? else {
+            continue;
+        };
+        if let Err(e) = edit(bot, ChatId(recipient), MessageId(copy_id), content).await {
+            log::warn!("⚠️ Failed to relay edit from {} to {}: {}", sender_id, recipient, e);
+        }
+    }
+    Ok(())
+}
+
+async fn edit(bot: &Bot, chat_id: ChatId, message_id: MessageId, content: &RelayContent) -> Result<()> {
+    match content.clone() {
+        RelayContent::Text(text) => {
+            bot.edit_message_text(chat_id, message_id, text).await?;
+        }
+        RelayContent::Media { kind, file_id, caption } => {
+            let file = InputFile::file_id(file_id);
+            let media = match kind {
+                CaptionedMedia::Photo => InputMedia::Photo(InputMediaPhoto::new(file).caption(caption)),
+                CaptionedMedia::Video => InputMedia::Video(InputMediaVideo::new(file).caption(caption)),
+                CaptionedMedia::Animation => {
+                    InputMedia::Animation(InputMediaAnimation::new(file).caption(caption))
+                }
+                CaptionedMedia::Document => InputMedia::Document(InputMediaDocument::new(file).caption(caption)),
+                CaptionedMedia::Audio => InputMedia::Audio(InputMediaAudio::new(file).caption(caption)),
+                // Voice notes cannot be swapped out, only re-captioned
+                CaptionedMedia::Voice => {
+                    bot.edit_message_caption(chat_id, message_id).caption(caption).await?;
+                    return Ok(());
+                }
+            };
+            bot.edit_message_media(chat_id, message_id, media).await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Deletes every relayed copy of message `message_id` sent by `sender_id`,
+/// wherever the sender has moved on to since. Returns how many copies were
+/// deleted.
+pub async fn unsend(
+    bot: &Bot,
+    sessions: &dyn SessionStore,
+    sender_id: i64,
+    message_id: MessageId,
+) -> Result<usize> {
+    let mut deleted = 0;
+    for (recipient, copy_id) in sessions.get_linked_messages(sender_id, message_id.0).await? {
+        if recipient == sender_id {
+            continue;
+        }
+        match bot.delete_message(ChatId(recipient), MessageId(copy_id)).await {
+            Ok(_) => deleted += 1,
+            Err(e) => log::warn!("⚠️ Failed to unsend message from {} to {}: {}", sender_id, recipient, e),
+        }
+    }
+    Ok(deleted)
+}
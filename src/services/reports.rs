@@ -0,0 +1,145 @@
+use anyhow::Result;
+use chrono::Utc;
+use uuid::Uuid;
+use crate::models::{AppState, Report, ReportStatus, UserState};
+use super::{pseudonyms, relay};
+
+/// How many open reports `/reports` lists at once.
+pub const REPORT_LIST_LIMIT: usize = 20;
+// Long messages and reasons are cut short when a report is reviewed
+const REVIEW_MESSAGE_CHARS: usize = 200;
+const REVIEW_REASON_CHARS: usize = 500;
+// Short enough that a full `/reports` list fits in one message
+const SUMMARY_REASON_CHARS: usize = 80;
+// Telegram's limit on a message's length, in UTF-16 code units
+const TELEGRAM_TEXT_LIMIT: usize = 4096;
+
+/// Files a report from the user about their room, or their current or last
+/// private chat partner, with the messages relayed there recently. Returns
+/// `None` when there is nothing to report.
+pub async fn file_report(state: &AppState, user_state: &UserState, reason: &str) -> Result<Option<Report>> {
+    let last_partner_id = user_state.partner_id.or(user_state.last_partner_id);
+    let (reported_id, conversation) = match (&user_state.current_room, last_partner_id) {
+        (Some(_), _) => (None, relay::conversation_id(user_state)),
+        (None, Some(partner_id)) => {
+            (Some(partner_id), Some(relay::private_conversation_id(user_state.chat_id, partner_id)))
+        }
+        (None, None) => (None, None),
+    };
+    let Some(conversation) = conversation else {
+        return Ok(None);
+    };
+
+    let reason = reason.trim();
+    let report = Report {
+        report_id: Uuid::new_v4().simple().to_string()[..8].to_string(),
+        reporter_id: user_state.chat_id,
+        reported_id,
+        room_id: user_state.current_room.clone(),
+        reason: if reason.is_empty() { "No reason given".to_string() } else { reason.to_string() },
+        messages: state.sessions.get_recent_messages(&conversation).await?,
+        status: ReportStatus::Open,
+        created_at: Utc::now(),
+        resolved_by: None,
+        resolution: None,
+        resolved_at: None,
+    };
+    state.moderation.file_report(&report).await?;
+    log::warn!("🚨 User {} filed report {} ({} messages attached)", report.reporter_id, report.report_id, report.messages.len());
+    Ok(Some(report))
+}
+
+/// One line per report for the `/reports` list.
+pub fn summary_line(report: &Report) -> String {
+    format!(
+        "#{} • {} • {} • {}",
+        report.report_id,
+        report.created_at.format("%Y-%m-%d %H:%M"),
+        place(report),
+        shorten(&report.reason, SUMMARY_REASON_CHARS)
+    )
+}
+
+/// The whole report with its messages, for `/review`, split into as many
+/// messages as Telegram needs. Users appear only by their pseudonyms.
+pub async fn format_report(state: &AppState, report: &Report) -> Result<Vec<String>> {
+    let status = match report.status {
+        ReportStatus::Open => "open".to_string(),
+        ReportStatus::Resolved => format!(
+            "resolved: {}",
+            shorten(report.resolution.as_deref().unwrap_or_default(), REVIEW_REASON_CHARS)
+        ),
+    };
+    let reporter = pseudonyms::pseudonym_of(state, report.reporter_id).await?;
+    let reported = match report.reported_id {
+        Some(reported_id) => Some(pseudonyms::pseudonym_of(state, reported_id).await?),
+        None => None,
+    };
+
+    let mut messages = Vec::new();
+    for message in &report.messages {
+        let pseudonym = pseudonyms::pseudonym_of(state, message.sender_id).await?;
+        let sender = if message.sender_id == report.reporter_id {
+            format!("{} (reporter)", pseudonym)
+        } else if Some(message.sender_id) == report.reported_id {
+            format!("{} (reported)", pseudonym)
+        } else {
+            pseudonym
+        };
+        let content = shorten(&message.content, REVIEW_MESSAGE_CHARS);
+        messages.push(format!("[{}] {}: {}", message.sent_at.format("%H:%M"), sender, content));
+    }
+    if messages.is_empty() {
+        messages.push("(none)".to_string());
+    }
+
+    let header = format!(
+        "🚨 Report #{} ({})\n\n\
+        👤 Filed by: {}\n\
+        🎯 Reported: {}\n\
+        📍 Where: {}\n\
+        🕒 When: {}\n\
+        📝 Reason: {}\n\n\
+        💬 Last messages:",
+        report.report_id,
+        status,
+        reporter,
+        reported.as_deref().unwrap_or("—"),
+        place(report),
+        report.created_at.format("%Y-%m-%d %H:%M UTC"),
+        shorten(&report.reason, REVIEW_REASON_CHARS),
+    );
+
+    // Each line fits on its own, so lines only need packing into messages
+    let mut parts = vec![header];
+    for line in messages {
+        let current = parts.last_mut().expect("starts with the header");
+        if text_len(current) + 1 + text_len(&line) <= TELEGRAM_TEXT_LIMIT {
+            current.push('\n');
+            current.push_str(&line);
+        } else {
+            parts.push(line);
+        }
+    }
+    Ok(parts)
+}
+
+// Cuts `text` to at most `max_chars` characters, marking the cut
+fn shorten(text: &str, max_chars: usize) -> String {
+    let mut shortened: String = text.chars().take(max_chars).collect();
+    if text.chars().count() > max_chars {
+        shortened.push('…');
+    }
+    shortened
+}
+
+fn text_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+fn place(report: &Report) -> String {
+    match &report.room_id {
+        Some(room_id) => format!("room {}", room_id),
+        None => "private chat".to_string(),
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use crate::models::{AppState, Permission, Role, StaffMember};
+
+/// The outcome of `/promote` or `/demote`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoleChange {
+    Done,
+    /// The acting user's role is not above both the target's role and the
+    /// one being granted.
+    NotAllowed,
+    /// The target already had that role, or no role to take away.
+    Unchanged,
+}
+
+/// Owner chat ids from `ADMIN_CHAT_IDS`, separated by commas or spaces.
+pub fn owner_ids_from_env() -> Result<Vec<i64>> {
+    let Ok(value) = std::env::var("ADMIN_CHAT_IDS") else {
+        return Ok(Vec::new());
+    };
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|id| !id.is_empty())
+        .map(|id| id.parse().map_err(|e| anyhow!("ADMIN_CHAT_IDS: {:?}: {}", id, e)))
+        .collect()
+}
+
+/// Makes each user an owner, so a fresh deployment has someone to promote
+/// the rest of the staff. Owners no longer listed lose the role, which is
+/// the only way to revoke it.
+pub async fn bootstrap(state: &AppState, owner_ids: &[i64]) -> Result<()> {
+    if owner_ids.is_empty() {
+        log::warn!("⚠️ ADMIN_CHAT_IDS is not set, nobody can use staff commands");
+    }
+    for member in state.moderation.list_staff(Role::Owner).await? {
+        if member.granted_by.is_none() && !owner_ids.contains(&member.chat_id) {
+            state.moderation.remove_role(member.chat_id).await?;
+            log::warn!("🛡️ User {} is no longer an owner, as they left ADMIN_CHAT_IDS", member.chat_id);
+        }
+    }
+    for &chat_id in owner_ids {
+        if role_of(state, chat_id).await? == Some(Role::Owner) {
+            continue;
+        }
+        state.moderation.set_role(&StaffMember {
+            chat_id,
+            role: Role::Owner,
+            granted_by: None,
+            granted_at: Utc::now(),
+        }).await?;
+        log::info!("🛡️ User {} is an owner from ADMIN_CHAT_IDS", chat_id);
+    }
+    Ok(())
+}
+
+pub async fn role_of(state: &AppState, chat_id: i64) -> Result<Option<Role>> {
+    Ok(state.moderation.get_role(chat_id).await?.map(|member| member.role))
+}
+
+// Staff only ever act on users with no role or a lower one
+fn outranks(actor_role: Role, target_role: Option<Role>) -> bool {
+    target_role.is_none_or(|target_role| target_role < actor_role)
+}
+
+/// Whether `actor_id` ranks above `target_id`, so may sanction them.
+pub async fn may_act_on(state: &AppState, actor_id: i64, target_id: i64) -> Result<bool> {
+    let Some(actor_role) = role_of(state, actor_id).await? else {
+        return Ok(false);
+    };
+    Ok(outranks(actor_role, role_of(state, target_id).await?))
+}
+
+// Owners alone may hand out or take away the admin role
+fn permission_to_manage(role: Role) -> Permission {
+    if role >= Role::Admin { Permission::ManageAdmins } else { Permission::ManageRoles }
+}
+
+/// Gives `target_id` the role, or changes theirs. Staff can only hand out
+/// roles below their own, to users below them.
+pub async fn promote(state: &AppState, actor_id: i64, target_id: i64, role: Role) -> Result<RoleChange> {
+    let Some(actor_role) = role_of(state, actor_id).await? else {
+        return Ok(RoleChange::NotAllowed);
+    };
+    let current = role_of(state, target_id).await?;
+    let allowed = actor_role.can(permission_to_manage(role.max(current.unwrap_or(role))))
+        && role < actor_role
+        && outranks(actor_role, current);
+    if !allowed {
+        return Ok(RoleChange::NotAllowed);
+    }
+    if current == Some(role) {
+        return Ok(RoleChange::Unchanged);
+    }
+
+    state.moderation.set_role(&StaffMember {
+        chat_id: target_id,
+        role,
+        granted_by: Some(actor_id),
+        granted_at: Utc::now(),
+    }).await?;
+    log::warn!("🛡️ User {} made user {} {}", actor_id, target_id, role.name());
+    Ok(RoleChange::Done)
+}
+
+/// Takes `target_id`'s role away, if it is below the acting user's.
+pub async fn demote(state: &AppState, actor_id: i64, target_id: i64) -> Result<RoleChange> {
+    let Some(actor_role) = role_of(state, actor_id).await? else {
+        return Ok(RoleChange::NotAllowed);
+    };
+    let Some(current) = role_of(state, target_id).await? else {
+        return Ok(RoleChange::Unchanged);
+    };
+    if !actor_role.can(permission_to_manage(current)) || !outranks(actor_role, Some(current)) {
+        return Ok(RoleChange::NotAllowed);
+    }
+
+    state.moderation.remove_role(target_id).await?;
+    log::warn!("🛡️ User {} removed user {}'s {} role", actor_id, target_id, current.name());
+    Ok(RoleChange::Done)
+}
@@ -0,0 +1,177 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use crate::models::{
+    ChatRoom, MoodEntry, RecentMessage, Report, Role, Sanction, SanctionKind, StaffMember, UserProfile, UserState,
+};
+
+/// A change to a user's state, applied by `SessionStore::update_user_state`.
+/// It may run more than once, so it should only modify the state.
+pub type StateUpdate<'a> = dyn Fn(&mut UserState) + Send + Sync + 'a;
+
+/// Volatile per-user session data: chat state, the search queue and the
+/// matchmaking preferences it reads.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn get_user_state(&self, chat_id: i64) -> Result<Option<UserState>>;
+    async fn set_user_state(&self, state: &UserState) -> Result<()>;
+    /// Applies `update` to the user's current state without overwriting a
+    /// concurrent change, such as their partner leaving. Returns the updated
+    /// state, or `None` if the user has no session.
+    async fn update_user_state(
+        &self,
+        chat_id: i64,
+        update: &StateUpdate<'_>,
+    ) -> Result<Option<UserState>>;
+    /// Clears the user's session, and forgets that they were unreachable.
+    async fn cleanup_user_state(&self, chat_id: i64) -> Result<()>;
+    /// Every user with a session, except those marked unreachable.
+    async fn reachable_user_ids(&self) -> Result<Vec<i64>>;
+    /// Leaves out a user who blocked the bot or deleted their account until
+    /// their next `/start`.
+    async fn mark_unreachable(&self, chat_id: i64) -> Result<()>;
+
+    /// Enqueues `chat_id` unless they are already waiting, then claims the
+    /// best waiting partner for them, returned with the time (Unix seconds)
+    /// the partner joined the queue. The caller stays queued, keeping their
+    /// original time, until they are paired. Must be atomic across
+    /// concurrent callers.
+    async fn find_random_partner(
+        &self,
+        chat_id: i64,
+        fallback_wait_secs: u64,
+        language_fallback_secs: u64,
+    ) -> Result<Option<(i64, u64)>>;
+    /// Queues the user as having waited since `since` (Unix seconds).
+    async fn add_to_search_queue(&self, chat_id: i64, since: u64) -> Result<()>;
+    async fn remove_from_search_queue(&self, chat_id: i64) -> Result<()>;
+    async fn get_waiting_users(&self, min_wait_secs: u64) -> Result<Vec<i64>>;
+    async fn set_search_tags(&self, chat_id: i64, tags: &[String]) -> Result<()>;
+    async fn get_shared_tags(&self, user1_id: i64, user2_id: i64) -> Result<Vec<String>>;
+    async fn set_search_language(&self, chat_id: i64, language: Option<&str>) -> Result<()>;
+
+    /// Atomically pairs two searching users. Returns `false` when either side
+    /// is no longer available.
+    async fn connect_users(&self, user1_id: i64, user2_id: i64) -> Result<bool>;
+    async fn end_private_chat(&self, chat_id: i64, partner_id: i64) -> Result<()>;
+    async fn cancel_search(&self, chat_id: i64) -> Result<bool>;
+    async fn expire_search(&self, chat_id: i64, timeout_secs: u64) -> Result<bool>;
+
+    async fn block_user(&self, chat_id: i64, blocked_id: i64) -> Result<()>;
+
+    /// Records that `copies` (chat id, message id) are the same relayed
+    /// message, so a reply to any copy can be threaded on every other side.
+    /// Links expire after a while.
+    async fn link_messages(&self, copies: &[(i64, i32)]) -> Result<()>;
+    /// The copy in `target_chat_id` of message `message_id` in `chat_id`.
+    async fn get_linked_message(&self, chat_id: i64, message_id: i32, target_chat_id: i64) -> Result<Option<i32>>;
+    /// Every copy (chat id, message id) linked with message `message_id` in
+    /// `chat_id`, that one included.
+    async fn get_linked_messages(&self, chat_id: i64, message_id: i32) -> Result<Vec<(i64, i32)>>;
+
+    /// Remembers `message_id` as the latest message `sender_id` relayed to
+    /// `recipient_id` that has not been marked as seen.
+    async fn set_unseen_message(&self, sender_id: i64, recipient_id: i64, message_id: i32) -> Result<()>;
+    /// Takes that message, so it is only marked as seen once.
+    async fn take_unseen_message(&self, sender_id: i64, recipient_id: i64) -> Result<Option<i32>>;
+
+    async fn get_idle_users(&self, timeout_secs: u64) -> Result<Vec<i64>>;
+
+    /// Takes a token from the bucket `key`, which holds up to `burst` tokens
+    /// and regains one every `refill_ms`. Returns whether one was available.
+    async fn take_rate_token(&self, key: &str, burst: u32, refill_ms: u64) -> Result<bool>;
+    /// Counts a rate limit violation by `chat_id`, returning how many there
+    /// have been since the first one in the current `window_secs`.
+    async fn add_rate_strike(&self, chat_id: i64, window_secs: u64) -> Result<u32>;
+    async fn set_flood_mute(&self, chat_id: i64, duration_secs: u64) -> Result<()>;
+    /// Seconds left on the user's flood mute, if any.
+    async fn get_flood_mute(&self, chat_id: i64) -> Result<Option<u64>>;
+
+    /// Keeps `message` among the last few relayed in `conversation`, so a
+    /// report can include them.
+    async fn record_recent_message(&self, conversation: &str, message: &RecentMessage) -> Result<()>;
+    /// Those messages, oldest first.
+    async fn get_recent_messages(&self, conversation: &str) -> Result<Vec<RecentMessage>>;
+
+    /// Marks the user as shadow-banned for matchmaking until `until_secs`
+    /// (a Unix time).
+    async fn set_shadowban(&self, chat_id: i64, until_secs: u64) -> Result<()>;
+    async fn clear_shadowban(&self, chat_id: i64) -> Result<()>;
+    /// Keeps a copy of the user's sanctions, possibly none, for `ttl_secs`.
+    async fn cache_sanctions(&self, chat_id: i64, sanctions: &[Sanction], ttl_secs: u64) -> Result<()>;
+    /// That copy, or `None` once it expired or was forgotten.
+    async fn get_cached_sanctions(&self, chat_id: i64) -> Result<Option<Vec<Sanction>>>;
+    async fn forget_cached_sanctions(&self, chat_id: i64) -> Result<()>;
+    /// Keeps a copy of the user's /language choice, possibly none, for
+    /// `ttl_secs`.
+    async fn cache_language(&self, chat_id: i64, language: Option<&str>, ttl_secs: u64) -> Result<()>;
+    /// That copy, or `None` once it expired.
+    async fn get_cached_language(&self, chat_id: i64) -> Result<Option<Option<String>>>;
+}
+
+#[async_trait]
+pub trait RoomStore: Send + Sync {
+    async fn create_room(&self, name: String, max_members: usize) -> Result<ChatRoom>;
+    async fn get_room(&self, room_id: &str) -> Result<Option<ChatRoom>>;
+    async fn list_rooms(&self) -> Result<Vec<ChatRoom>>;
+    /// Adds the user to the room unless it is full, gone, or holds someone
+    /// they blocked or were blocked by.
+    async fn join_room(&self, room_id: &str, user_state: &mut UserState) -> Result<bool>;
+    async fn leave_room(&self, room_id: &str, user_state: &mut UserState) -> Result<()>;
+}
+
+/// Durable per-user data: profiles, matchmaking preferences and moods.
+#[async_trait]
+pub trait ProfileStore: Send + Sync {
+    async fn save_profile(&self, chat_id: i64, profile: UserProfile) -> Result<()>;
+    async fn get_profile(&self, chat_id: i64) -> Result<Option<UserProfile>>;
+    async fn save_interests(&self, chat_id: i64, interests: &[String]) -> Result<()>;
+    async fn get_interests(&self, chat_id: i64) -> Result<Vec<String>>;
+    async fn save_language(&self, chat_id: i64, language: Option<&str>) -> Result<()>;
+    async fn get_language(&self, chat_id: i64) -> Result<Option<String>>;
+    async fn save_mood_history(&self, chat_id: i64, mood: &MoodEntry) -> Result<()>;
+    async fn get_mood_history(&self, chat_id: i64) -> Result<Vec<MoodEntry>>;
+    async fn get_mood_stats(&self) -> Result<HashMap<String, i32>>;
+
+    /// Records a moderation flag against the user.
+    async fn flag_user(&self, chat_id: i64, reason: &str) -> Result<()>;
+    async fn get_flag_count(&self, chat_id: i64) -> Result<u64>;
+}
+
+/// Durable moderation data: user reports, how they were resolved, the
+/// sanctions admins put on users, who is staff, and the pseudonyms staff
+/// know users by.
+#[async_trait]
+pub trait ModerationStore: Send + Sync {
+    async fn file_report(&self, report: &Report) -> Result<()>;
+    async fn get_report(&self, report_id: &str) -> Result<Option<Report>>;
+    /// Open reports, oldest first.
+    async fn list_open_reports(&self, limit: usize) -> Result<Vec<Report>>;
+    /// Whether an open report, filed by someone else, names the user or
+    /// quotes their messages.
+    async fn has_open_reports_about(&self, chat_id: i64) -> Result<bool>;
+    /// Marks an open report as resolved. Returns `false` when there is no
+    /// such open report.
+    async fn resolve_report(&self, report_id: &str, admin_id: i64, resolution: &str) -> Result<bool>;
+
+    /// Puts a sanction on the user, replacing any earlier one of its kind.
+    async fn add_sanction(&self, sanction: &Sanction) -> Result<()>;
+    /// The user's sanctions that have not expired.
+    async fn get_sanctions(&self, chat_id: i64) -> Result<Vec<Sanction>>;
+    /// Lifts the user's sanctions of `kind`, or all of them. Returns how
+    /// many were lifted.
+    async fn lift_sanctions(&self, chat_id: i64, kind: Option<SanctionKind>) -> Result<u64>;
+
+    /// Gives the user a staff role, replacing any earlier one.
+    async fn set_role(&self, member: &StaffMember) -> Result<()>;
+    async fn get_role(&self, chat_id: i64) -> Result<Option<StaffMember>>;
+    async fn list_staff(&self, role: Role) -> Result<Vec<StaffMember>>;
+    /// Takes the user's staff role away. Returns `false` when they had none.
+    async fn remove_role(&self, chat_id: i64) -> Result<bool>;
+
+    async fn get_pseudonym(&self, chat_id: i64) -> Result<Option<String>>;
+    /// Gives the user `pseudonym` in place of any earlier one. Returns
+    /// `false` when another user already has it.
+    async fn claim_pseudonym(&self, chat_id: i64, pseudonym: &str) -> Result<bool>;
+    async fn resolve_pseudonym(&self, pseudonym: &str) -> Result<Option<i64>>;
+}
@@ -0,0 +1,211 @@
+use aho_corasick::AhoCorasick;
+use std::ops::RangeInclusive;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Finds listed words in user text, seeing through the usual disguises:
+/// accents and look-alike letters, leetspeak (`sh1t`, `fvck`), separators
+/// (`f.u.c.k`, `f u c k`) and stretched letters (`fuuuck`).
+///
+/// A listed word only matches whole words. A leading or trailing `*` lets it
+/// run into the rest of the word on that side, so `fuck*` catches "fucking"
+/// and `*fuck*` catches "motherfucker", while `cunt` leaves "Scunthorpe" and
+/// `babi` leaves "babinsa" alone.
+pub struct WordMatcher<T> {
+    automaton: Option<AhoCorasick>,
+    patterns: Vec<Pattern<T>>,
+}
+
+struct Pattern<T> {
+    value: T,
+    whole_start: bool,
+    whole_end: bool,
+}
+
+/// A listed word found in the text.
+pub struct WordMatch<'a, T> {
+    pub value: &'a T,
+    /// Chars of the original text the match covers.
+    pub chars: RangeInclusive<usize>,
+}
+
+impl<T> WordMatcher<T> {
+    /// Builds a matcher over `(word, value)` pairs. Words that normalize to
+    /// nothing are dropped.
+    pub fn new(words: impl IntoIterator<Item = (String, T)>) -> Self {
+        let mut needles = Vec::new();
+        let mut patterns = Vec::new();
+        for (word, value) in words {
+            let word = word.trim();
+            let needle = normalize(word.trim_matches('*')).text;
+            if needle.is_empty() {
+                continue;
+            }
+            needles.push(needle);
+            patterns.push(Pattern {
+                value,
+                whole_start: !word.starts_with('*'),
+                whole_end: !word.ends_with('*'),
+            });
+        }
+
+        let automaton = if needles.is_empty() {
+            None
+        } else {
+            // Only fails for automatons far larger than any word list
+            Some(AhoCorasick::new(&needles).expect("word list too large for the matcher"))
+        };
+        Self { automaton, patterns }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.patterns.iter().map(|pattern| &pattern.value)
+    }
+
+    /// Every listed word in `text`, overlapping matches included.
+    pub fn find<'a>(&'a self, text: &str) -> Vec<WordMatch<'a, T>> {
+        let Some(automaton) = &self.automaton else {
+            return Vec::new();
+        };
+        let normalized = normalize(text);
+        let bytes = normalized.text.as_bytes();
+
+        automaton
+            .find_overlapping_iter(&normalized.text)
+            .filter_map(|found| {
+                let pattern = &self.patterns[found.pattern().as_usize()];
+                let (start, end) = (found.start(), found.end());
+                if pattern.whole_start && start > 0 && bytes[start - 1] != b' ' {
+                    return None;
+                }
+                if pattern.whole_end && end < bytes.len() && bytes[end] != b' ' {
+                    return None;
+                }
+                Some(WordMatch {
+                    value: &pattern.value,
+                    chars: normalized.origins[start].0..=normalized.origins[end - 1].1,
+                })
+            })
+            .collect()
+    }
+}
+
+// A folded char and the span of original chars it stands for
+type Folded = (char, (usize, usize));
+
+struct Normalized {
+    /// Folded words separated by single spaces.
+    text: String,
+    /// For each byte of `text`, the original chars it came from.
+    origins: Vec<(usize, usize)>,
+}
+
+fn normalize(text: &str) -> Normalized {
+    // Decompose, drop accents and lowercase, remembering where each char came from
+    let mut chars: Vec<Folded> = Vec::new();
+    for (origin, c) in text.chars().enumerate() {
+        for decomposed in c.nfkd().filter(|c| !is_combining_mark(*c)) {
+            for lower in decomposed.to_lowercase() {
+                chars.push((unconfuse(lower), (origin, origin)));
+            }
+        }
+    }
+
+    // Runs of single letters spell a word out: "f u c k"
+    let mut words: Vec<(Vec<Folded>, bool)> = Vec::new();
+    for word in chars.split(|(c, _)| c.is_whitespace()).flat_map(fold_word) {
+        match (word.len(), words.last_mut()) {
+            (0, _) => {}
+            (1, Some((last, true))) => last.extend(word),
+            (len, _) => words.push((word, len == 1)),
+        }
+    }
+
+    let mut normalized = Normalized { text: String::new(), origins: Vec::new() };
+    for (word, _) in &words {
+        let word = collapse_runs(word);
+        if !normalized.text.is_empty() {
+            normalized.text.push(' ');
+            normalized.origins.push(word[0].1);
+        }
+        for (c, span) in word {
+            normalized.text.push(c);
+            normalized.origins.extend(std::iter::repeat_n(span, c.len_utf8()));
+        }
+    }
+    normalized
+}
+
+// Folds one whitespace-separated word: leetspeak becomes letters, apostrophes
+// are dropped, and anything else that is not a letter or digit splits it, so
+// "babi,anjing" is two words
+fn fold_word(word: &[Folded]) -> Vec<Vec<Folded>> {
+    let has_letter = word.iter().any(|(c, _)| c.is_alphabetic());
+    let mut parts = vec![Vec::new()];
+    for (i, &(c, span)) in word.iter().enumerate() {
+        let followed = word[i + 1..].iter().any(|(c, _)| c.is_alphanumeric());
+        let folded = match c {
+            'v' => 'u',
+            c if c.is_alphabetic() => c,
+            // Digits only stand for letters among letters: "b4b1", not "2024"
+            c if c.is_ascii_digit() => match (has_letter, c) {
+                (true, '0') => 'o',
+                (true, '1') => 'i',
+                (true, '3') => 'e',
+                (true, '4') => 'a',
+                (true, '5') => 's',
+                (true, '7') => 't',
+                (true, '8') => 'b',
+                _ => c,
+            },
+            // Symbols only stand for letters inside a word, so a closing "!" stays punctuation
+            '@' if followed => 'a',
+            '$' if followed => 's',
+            '!' | '|' if followed => 'i',
+            '+' if followed => 't',
+            '€' if followed => 'e',
+            '\'' | '’' => continue,
+            _ => {
+                parts.push(Vec::new());
+                continue;
+            }
+        };
+        parts.last_mut().expect("starts with a part").push((folded, span));
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+// Squeezes three or more of the same letter into one: "fuuuck" but not "shiitake"
+fn collapse_runs(word: &[Folded]) -> Vec<Folded> {
+    let mut collapsed = Vec::with_capacity(word.len());
+    for run in word.chunk_by(|(a, _), (b, _)| a == b) {
+        if run.len() >= 3 {
+            let (first, last) = (run[0].1, run[run.len() - 1].1);
+            collapsed.push((run[0].0, (first.0, last.1)));
+        } else {
+            collapsed.extend_from_slice(run);
+        }
+    }
+    collapsed
+}
+
+// Cyrillic and Greek letters that pass for Latin ones
+fn unconfuse(c: char) -> char {
+    match c {
+        'а' | 'α' => 'a',
+        'в' | 'β' => 'b',
+        'с' => 'c',
+        'е' | 'ε' => 'e',
+        'і' | 'ι' => 'i',
+        'к' | 'κ' => 'k',
+        'м' => 'm',
+        'н' | 'η' => 'h',
+        'о' | 'ο' => 'o',
+        'р' | 'ρ' => 'p',
+        'ѕ' => 's',
+        'т' | 'τ' => 't',
+        'у' | 'υ' => 'y',
+        'х' | 'χ' => 'x',
+        _ => c,
+    }
+}
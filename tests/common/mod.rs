@@ -0,0 +1,349 @@
+//! Drives the bot's real handler tree against a fake Telegram Bot API.
+//!
+//! Every Bot API call made by the handlers hits a local mock server, which
+//! answers with a plausible result and records the call so tests can assert
+//! on what the bot sent.
+
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::sync::{
+    atomic::{AtomicI32, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use telegram_bot::{handlers, models::AppState};
+use teloxide::{
+    dispatching::UpdateHandler,
+    prelude::*,
+    types::{Me, UpdateKind},
+};
+use wiremock::{matchers::method, Mock, MockServer, Request, Respond, ResponseTemplate};
+
+// Messages the fake API reports as sent by the bot are numbered from here,
+// well clear of the ids given to messages users send
+const FIRST_BOT_MESSAGE_ID: i32 = 10_000;
+const BOT_USER_ID: i64 = 123456;
+
+/// A Bot API call made by the bot.
+#[derive(Debug, Clone)]
+pub struct ApiCall {
+    pub method: String,
+    pub params: HashMap<String, Value>,
+    /// Id of the message the fake API reported as sent, if any.
+    pub sent_message_id: Option<i32>,
+}
+
+impl ApiCall {
+    pub fn chat_id(&self) -> Option<i64> {
+        match self.params.get("chat_id")? {
+            Value::Number(id) => id.as_i64(),
+            Value::String(id) => id.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn param(&self, name: &str) -> Option<String> {
+        match self.params.get(name)? {
+            Value::String(value) => Some(value.clone()),
+            value => Some(value.to_string()),
+        }
+    }
+
+    pub fn text(&self) -> Option<String> {
+        self.param("text")
+    }
+}
+
+/// Bot API errors the fake API answers sends with, by recipient.
+#[derive(Default)]
+struct Failures {
+    blocked: HashSet<i64>,
+    // chat_id -> retry_after seconds of upcoming flood control errors
+    flood_control: HashMap<i64, VecDeque<u64>>,
+}
+
+pub struct TestBot {
+    pub state: Arc<AppState>,
+    // Kept alive for as long as the bot points at it
+    _server: MockServer,
+    calls: Arc<Mutex<Vec<ApiCall>>>,
+    failures: Arc<Mutex<Failures>>,
+    /// Talks to the fake API, for running background jobs by hand.
+    pub bot: Bot,
+    me: Me,
+    handler: UpdateHandler<Box<dyn Error + Send + Sync>>,
+    next_update_id: AtomicI32,
+    next_message_id: AtomicI32,
+}
+
+impl TestBot {
+    pub async fn new() -> Self {
+        Self::with_state(AppState::in_memory()).await
+    }
+
+    pub async fn with_state(state: AppState) -> Self {
+        let server = MockServer::start().await;
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let failures = Arc::new(Mutex::new(Failures::default()));
+        let responder = ApiResponder {
+            calls: calls.clone(),
+            failures: failures.clone(),
+            next_message_id: AtomicI32::new(FIRST_BOT_MESSAGE_ID),
+        };
+        Mock::given(method("POST")).respond_with(responder).mount(&server).await;
+
+        let bot = Bot::new("123456:TEST").set_api_url(url::Url::parse(&server.uri()).unwrap());
+        let me: Me = serde_json::from_value(json!({
+            "id": BOT_USER_ID,
+            "is_bot": true,
+            "first_name": "Anonymous Chat",
+            "username": "anonymous_chat_test_bot",
+            "can_join_groups": false,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": false,
+        }))
+        .unwrap();
+
+        let state = Arc::new(state);
+        Self {
+            handler: handlers::schema(state.clone()),
+            state,
+            _server: server,
+            calls,
+            failures,
+            bot,
+            me,
+            next_update_id: AtomicI32::new(1),
+            next_message_id: AtomicI32::new(1),
+        }
+    }
+
+    /// Sends a text message (or command) from a private chat with `chat_id`.
+    pub async fn send_text(&self, chat_id: i64, text: &str) {
+        self.send_message(chat_id, json!({ "text": text })).await;
+    }
+
+    /// Sends a message from `chat_id` replying to `reply_to_message_id` in
+    /// that chat, which may be one of the user's own messages or a copy the
+    /// bot delivered. Returns its message id.
+    pub async fn send_reply(&self, chat_id: i64, content: Value, reply_to_message_id: i32) -> i32 {
+        let author_id = if reply_to_message_id >= FIRST_BOT_MESSAGE_ID { BOT_USER_ID } else { chat_id };
+        let replied_to = json!({
+            "message_id": reply_to_message_id,
+            "date": 1_700_000_000,
+            "chat": { "id": chat_id, "type": "private", "first_name": "User" },
+            "from": { "id": author_id, "is_bot": author_id == BOT_USER_ID, "first_name": "User" },
+            "text": "",
+        });
+        let mut content = content;
+        content["reply_to_message"] = replied_to;
+        self.send_message(chat_id, content).await
+    }
+
+    /// Edits message `message_id` from `chat_id` so its content fields are
+    /// `content`.
+    pub async fn edit_message(&self, chat_id: i64, message_id: i32, content: Value) {
+        let mut message = user_message(chat_id, message_id);
+        message["edit_date"] = json!(1_700_000_100);
+        message.as_object_mut().unwrap().extend(content.as_object().unwrap().clone());
+        self.dispatch(json!({ "edited_message": message })).await;
+    }
+
+    /// Sends a message from `chat_id` whose content fields (`text`, `photo`,
+    /// `sticker`, ...) are given by `content`. Returns its message id.
+    pub async fn send_message(&self, chat_id: i64, content: Value) -> i32 {
+        let message_id = self.next_message_id.fetch_add(1, Ordering::SeqCst);
+        let mut message = user_message(chat_id, message_id);
+        message.as_object_mut().unwrap().extend(content.as_object().unwrap().clone());
+
+        self.dispatch(json!({ "message": message })).await;
+        message_id
+    }
+
+    /// Runs a raw update (without `update_id`) through the handler tree and
+    /// fails the test if a handler returns an error.
+    pub async fn dispatch(&self, mut update: Value) {
+        update["update_id"] = json!(self.next_update_id.fetch_add(1, Ordering::SeqCst));
+        // teloxide cannot deserialize an Update from a serde_json::Value directly
+        let update: Update = serde_json::from_str(&update.to_string()).expect("invalid test update");
+        if let UpdateKind::Error(raw) = &update.kind {
+            panic!("test update was not recognised by teloxide: {}", raw);
+        }
+
+
+        let result = self
+            .handler
+            .dispatch(dptree::deps![self.bot.clone(), self.me.clone(), update])
+            .await;
+        if let std::ops::ControlFlow::Break(Err(e)) = result {
+            panic!("handler failed: {}", e);
+        }
+    }
+
+    /// Bot API calls made since the last call to `take_calls`.
+    pub async fn take_calls(&self) -> Vec<ApiCall> {
+        std::mem::take(&mut *self.calls.lock().unwrap())
+    }
+
+    /// Texts of `sendMessage` calls to `chat_id` since the last `take_calls`.
+    pub async fn take_texts_to(&self, chat_id: i64) -> Vec<String> {
+        self.take_calls()
+            .await
+            .into_iter()
+            .filter(|call| call.method == "SendMessage" && call.chat_id() == Some(chat_id))
+            .filter_map(|call| call.text())
+            .collect()
+    }
+
+    /// Waits for background work to send `chat_id` a message containing
+    /// `needle`, and returns every call made since the last `take_calls`.
+    pub async fn wait_for_text_to(&self, chat_id: i64, needle: &str) -> Vec<ApiCall> {
+        let mut calls = Vec::new();
+        for _ in 0..500 {
+            calls.extend(self.take_calls().await);
+            if calls.iter().any(|call| call.chat_id() == Some(chat_id)
+                && call.text().is_some_and(|text| text.contains(needle)))
+            {
+                return calls;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("{} was never sent {:?}: {:?}", chat_id, needle, calls);
+    }
+
+    /// Makes every message to `chat_id` fail as if they blocked the bot.
+    pub fn block_bot(&self, chat_id: i64) {
+        self.failures.lock().unwrap().blocked.insert(chat_id);
+    }
+
+    pub fn unblock_bot(&self, chat_id: i64) {
+        self.failures.lock().unwrap().blocked.remove(&chat_id);
+    }
+
+    /// Makes the next message to `chat_id` hit flood control.
+    pub fn flood_control_next_send(&self, chat_id: i64, retry_after_secs: u64) {
+        self.failures.lock().unwrap().flood_control.entry(chat_id).or_default().push_back(retry_after_secs);
+    }
+
+    /// Starts two users and pairs them through /find.
+    pub async fn pair(&self, user1_id: i64, user2_id: i64) {
+        self.send_text(user1_id, "/start").await;
+        self.send_text(user2_id, "/start").await;
+        self.send_text(user1_id, "/find").await;
+        self.send_text(user2_id, "/find").await;
+        self.take_calls().await;
+    }
+}
+
+fn user_message(chat_id: i64, message_id: i32) -> Value {
+    json!({
+        "message_id": message_id,
+        "date": 1_700_000_000,
+        "chat": { "id": chat_id, "type": "private", "first_name": "User" },
+        "from": { "id": chat_id, "is_bot": false, "first_name": "User", "language_code": "en" },
+    })
+}
+
+fn parse_call(request: &Request) -> ApiCall {
+    let method = request.url.path().rsplit('/').next().unwrap_or_default();
+    ApiCall {
+        method: normalize_method(method),
+        params: parse_params(request),
+        sent_message_id: None,
+    }
+}
+
+// teloxide sends `SendMessage`-style names; the Bot API is case-insensitive
+fn normalize_method(method: &str) -> String {
+    let mut chars = method.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn parse_params(request: &Request) -> HashMap<String, Value> {
+    let content_type = request
+        .headers
+        .iter()
+        .find(|(name, _)| name.as_str().eq_ignore_ascii_case("content-type"))
+        .map(|(_, values)| values.last().as_str().to_string())
+        .unwrap_or_default();
+
+    match content_type.split_once("boundary=") {
+        Some((_, boundary)) => parse_multipart(&String::from_utf8_lossy(&request.body), boundary),
+        None => serde_json::from_slice(&request.body).unwrap_or_default(),
+    }
+}
+
+fn parse_multipart(body: &str, boundary: &str) -> HashMap<String, Value> {
+    body.split(&format!("--{}", boundary))
+        .filter_map(|part| {
+            let (headers, value) = part.split_once("\r\n\r\n")?;
+            let name = headers.split("name=\"").nth(1)?.split('"').next()?;
+            Some((name.to_string(), Value::String(value.trim_end_matches("\r\n").to_string())))
+        })
+        .collect()
+}
+
+struct ApiResponder {
+    calls: Arc<Mutex<Vec<ApiCall>>>,
+    failures: Arc<Mutex<Failures>>,
+    next_message_id: AtomicI32,
+}
+
+impl ApiResponder {
+    fn failure(&self, call: &ApiCall) -> Option<ResponseTemplate> {
+        if !call.method.starts_with("Send") || call.method == "SendChatAction" {
+            return None;
+        }
+        let chat_id = call.chat_id()?;
+        let mut failures = self.failures.lock().unwrap();
+        if failures.blocked.contains(&chat_id) {
+            return Some(ResponseTemplate::new(403).set_body_json(json!({
+                "ok": false,
+                "error_code": 403,
+                "description": "Forbidden: bot was blocked by the user",
+            })));
+        }
+        let retry_after = failures.flood_control.get_mut(&chat_id)?.pop_front()?;
+        Some(ResponseTemplate::new(429).set_body_json(json!({
+            "ok": false,
+            "error_code": 429,
+            "description": format!("Too Many Requests: retry after {}", retry_after),
+            "parameters": { "retry_after": retry_after },
+        })))
+    }
+}
+
+impl Respond for ApiResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let mut call = parse_call(request);
+        if let Some(failure) = self.failure(&call) {
+            self.calls.lock().unwrap().push(call);
+            return failure;
+        }
+        let message_id = self.next_message_id.fetch_add(1, Ordering::SeqCst);
+
+        let result = if call.method == "CopyMessage" {
+            call.sent_message_id = Some(message_id);
+            json!({ "message_id": message_id })
+        } else if (call.method.starts_with("Send") && call.method != "SendChatAction")
+            || call.method.starts_with("Edit")
+        {
+            call.sent_message_id = Some(message_id);
+            json!({
+                "message_id": message_id,
+                "date": 1_700_000_000,
+                "chat": { "id": call.chat_id().unwrap_or_default(), "type": "private", "first_name": "User" },
+                "text": call.text().unwrap_or_default(),
+            })
+        } else {
+            json!(true)
+        };
+
+        self.calls.lock().unwrap().push(call);
+        ResponseTemplate::new(200).set_body_json(json!({ "ok": true, "result": result }))
+    }
+}
@@ -0,0 +1,158 @@
+//! Known false positives and false negatives for the word matcher and the
+//! link and personal info detectors.
+
+use telegram_bot::services::{
+    content_filter::{builtin_words, ContentFilter, FilterWord, Severity, Verdict, ANY_LANGUAGE},
+    info_detector::{InfoAction, InfoKind, InfoPolicy},
+};
+
+fn word(word: &str, severity: Severity) -> FilterWord {
+    FilterWord {
+        word: word.to_string(),
+        language: ANY_LANGUAGE.to_string(),
+        severity,
+    }
+}
+
+fn filter() -> ContentFilter {
+    let mut words = builtin_words();
+    words.extend(["cunt", "cock", "ass"].map(|listed| word(listed, Severity::Block)));
+    ContentFilter::with_words(words)
+}
+
+// Innocent text that contains, or nearly contains, a listed word
+const FALSE_POSITIVES: &[&str] = &[
+    "I grew up in Scunthorpe",
+    "babinsa datang ke desa",
+    "Reading Charles Dickens tonight",
+    "Fancy a cocktail?",
+    "The class assignment is due",
+    "An assassin walks into a bar",
+    "shiitake mushrooms",
+    "my dog is hit by a car",
+    "Ibis, bass and babies",
+    "I have 5 apples and 3 pears",
+    "Call me at 2024",
+    "very vivid visions",
+    "Oh no!",
+];
+
+// Disguised words that must still be caught
+const FALSE_NEGATIVES: &[&str] = &[
+    "fuck",
+    "FUCK",
+    "f.u.c.k",
+    "f-u-c-k you",
+    "f u c k",
+    "fvck",
+    "fuuuuuck",
+    "what the fuck!",
+    "motherfucker",
+    "fück",
+    "ｆｕｃｋ",
+    "sh1t",
+    "$hit",
+    "bullshit",
+    "b!tch",
+    "b4b1",
+    "b.a.b.i",
+    "BABI",
+    "babi,anjing",
+    "nice соck",
+];
+
+#[test]
+fn innocent_text_is_allowed() {
+    let filter = filter();
+    for text in FALSE_POSITIVES {
+        assert_eq!(filter.check(text, None), Verdict::Allow(text.to_string()), "{:?}", text);
+    }
+}
+
+#[test]
+fn disguised_words_are_blocked() {
+    let filter = filter();
+    for text in FALSE_NEGATIVES {
+        assert_eq!(filter.check(text, None), Verdict::Block, "{:?}", text);
+    }
+}
+
+#[test]
+fn censoring_masks_the_whole_disguised_word() {
+    let filter = ContentFilter::with_words(vec![word("darn", Severity::Censor)]);
+    let cases = [
+        ("Darn it", "**** it"),
+        ("d.a.r.n it", "******* it"),
+        ("oh d a r n", "oh *******"),
+        ("daaaarn!", "*******!"),
+        ("darning socks", "darning socks"),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(filter.check(text, None), Verdict::Allow(expected.to_string()), "{:?}", text);
+    }
+}
+
+fn blocking_all_info() -> ContentFilter {
+    let policy = InfoKind::ALL
+        .iter()
+        .fold(InfoPolicy::allow_all(), |policy, kind| policy.with(*kind, InfoAction::Block));
+    ContentFilter::with_words(Vec::new()).with_info_policy(policy)
+}
+
+#[test]
+fn info_is_detected_by_kind() {
+    let filter = blocking_all_info();
+    let cases = [
+        ("join t.me/+AbCdEf123 now", InfoKind::Invite),
+        ("https://telegram.me/joinchat/xyz", InfoKind::Invite),
+        ("mail me: jane.doe+chat@example.co.id", InfoKind::Email),
+        ("see https://example.com/page?id=1", InfoKind::Link),
+        ("www.example.org", InfoKind::Link),
+        ("cheap followers at followers.xyz", InfoKind::Link),
+        ("add me @jane_doe99", InfoKind::Username),
+        ("NIK 3174012501990003", InfoKind::NationalId),
+        ("ssn 123-45-6789", InfoKind::NationalId),
+        ("call +62 812-3456-7890", InfoKind::Phone),
+        ("0812 3456 7890", InfoKind::Phone),
+        ("(555) 123-4567", InfoKind::Phone),
+    ];
+    for (text, kind) in cases {
+        assert_eq!(filter.check(text, None), Verdict::Withhold { kind }, "{:?}", text);
+    }
+}
+
+#[test]
+fn ordinary_text_has_no_info() {
+    let filter = blocking_all_info();
+    for text in [
+        "Call me at 2024",
+        "The score was 3-2",
+        "Meet at 10.30 on 2024-05-01?",
+        "Email me later @ home",
+        "I paid $1,250.00",
+        "Hi there.how are you",
+    ] {
+        assert_eq!(filter.check(text, None), Verdict::Allow(text.to_string()), "{:?}", text);
+    }
+}
+
+#[test]
+fn info_actions_mask_warn_and_allow() {
+    let policy = InfoPolicy::allow_all()
+        .with(InfoKind::Phone, InfoAction::Mask)
+        .with(InfoKind::Username, InfoAction::Warn);
+    let filter = ContentFilter::with_words(vec![word("darn", Severity::Censor)]).with_info_policy(policy);
+
+    assert_eq!(
+        filter.check("darn, call 0812 3456 7890", None),
+        Verdict::Allow("****, call **************".to_string())
+    );
+    assert_eq!(
+        filter.check("ask @jane_doe99", None),
+        Verdict::Warn { content: "ask @jane_doe99".to_string(), kind: InfoKind::Username }
+    );
+    assert_eq!(
+        filter.check("mail jane@example.com", None),
+        Verdict::Allow("mail jane@example.com".to_string())
+    );
+}
@@ -0,0 +1,1259 @@
+mod common;
+
+use common::TestBot;
+use serde_json::json;
+
+const ALICE: i64 = 1001;
+const BOB: i64 = 1002;
+const CAROL: i64 = 1003;
+
+// How staff refer to the user
+async fn pseudonym(bot: &TestBot, chat_id: i64) -> String {
+    telegram_bot::services::pseudonyms::pseudonym_of(&bot.state, chat_id).await.unwrap()
+}
+
+#[tokio::test]
+async fn find_pairs_two_searching_users() {
+    let bot = TestBot::new().await;
+    bot.send_text(ALICE, "/start").await;
+    bot.send_text(BOB, "/start").await;
+
+    bot.send_text(ALICE, "/find").await;
+    let texts = bot.take_texts_to(ALICE).await;
+    assert!(texts.iter().any(|text| text.contains("Looking for a chat partner")), "{:?}", texts);
+
+    bot.send_text(BOB, "/find").await;
+    let calls = bot.take_calls().await;
+    for user in [ALICE, BOB] {
+        assert!(
+            calls.iter().any(|call| call.chat_id() == Some(user)
+                && call.text().is_some_and(|text| text.contains("Chat partner found"))),
+            "no match announcement for {}: {:?}",
+            user,
+            calls
+        );
+    }
+
+    let alice = bot.state.sessions.get_user_state(ALICE).await.unwrap().unwrap();
+    assert_eq!(alice.partner_id, Some(BOB));
+}
+
+#[tokio::test]
+async fn text_is_relayed_to_partner() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+
+    bot.send_text(ALICE, "hello there").await;
+    assert_eq!(bot.take_texts_to(BOB).await, vec!["hello there"]);
+}
+
+#[tokio::test]
+async fn inappropriate_text_is_not_relayed() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+
+    bot.send_text(ALICE, "this is shit").await;
+    let calls = bot.take_calls().await;
+    assert!(calls.iter().all(|call| call.chat_id() != Some(BOB)), "{:?}", calls);
+    assert!(calls.iter().any(|call| call.chat_id() == Some(ALICE)
+        && call.text().is_some_and(|text| text.contains("inappropriate content"))));
+}
+
+#[tokio::test]
+async fn photo_is_relayed_to_partner() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+
+    bot.send_message(ALICE, json!({
+        "photo": [
+            { "file_id": "small", "file_unique_id": "s", "width": 90, "height": 90 },
+            { "file_id": "large", "file_unique_id": "l", "width": 800, "height": 800 },
+        ],
+        "caption": "look",
+    }))
+    .await;
+
+    let calls = bot.take_calls().await;
+    let photo = calls
+        .iter()
+        .find(|call| call.method == "SendPhoto")
+        .expect("photo was not relayed");
+    assert_eq!(photo.chat_id(), Some(BOB));
+    assert_eq!(photo.param("photo").as_deref(), Some("large"));
+    assert_eq!(photo.param("caption").as_deref(), Some("look"));
+}
+
+#[tokio::test]
+async fn leave_notifies_partner() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+
+    bot.send_text(ALICE, "/leave").await;
+    let texts = bot.take_texts_to(BOB).await;
+    assert!(texts.iter().any(|text| text.contains("partner has left")), "{:?}", texts);
+
+    bot.send_text(BOB, "anyone there?").await;
+    let texts = bot.take_texts_to(BOB).await;
+    assert!(texts.iter().any(|text| text.contains("not connected")), "{:?}", texts);
+}
+
+#[tokio::test]
+async fn blocked_users_are_not_matched_again() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+
+    bot.send_text(ALICE, "/block").await;
+    bot.send_text(ALICE, "/find").await;
+    bot.send_text(BOB, "/find").await;
+    bot.take_calls().await;
+
+    let alice = bot.state.sessions.get_user_state(ALICE).await.unwrap().unwrap();
+    assert_eq!(alice.partner_id, None);
+
+    bot.send_text(CAROL, "/start").await;
+    bot.send_text(CAROL, "/find").await;
+    let carol = bot.state.sessions.get_user_state(CAROL).await.unwrap().unwrap();
+    assert!(matches!(carol.partner_id, Some(ALICE) | Some(BOB)));
+}
+
+#[tokio::test]
+async fn room_messages_reach_other_members() {
+    let bot = TestBot::new().await;
+    bot.send_text(ALICE, "/start").await;
+    bot.send_text(BOB, "/start").await;
+
+    bot.send_text(ALICE, "/createroom Lounge 5").await;
+    let room = bot.state.rooms.list_rooms().await.unwrap().pop().expect("room was not created");
+    bot.send_text(ALICE, &format!("/joinroom {}", room.room_id)).await;
+    bot.send_text(BOB, &format!("/joinroom {}", room.room_id)).await;
+    bot.take_calls().await;
+
+    bot.send_text(ALICE, "hi room").await;
+    let texts = bot.take_texts_to(BOB).await;
+    assert_eq!(texts, vec!["👤 Anonymous: hi room"]);
+}
+
+#[tokio::test]
+async fn video_caption_is_filtered_and_relayed() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+
+    bot.send_message(ALICE, json!({
+        "video": {
+            "file_id": "clip", "file_unique_id": "c",
+            "width": 640, "height": 480, "duration": 3, "mime_type": "video/mp4",
+        },
+        "caption": "watch this",
+    }))
+    .await;
+
+    let calls = bot.take_calls().await;
+    let video = calls
+        .iter()
+        .find(|call| call.method == "SendVideo")
+        .expect("video was not relayed");
+    assert_eq!(video.chat_id(), Some(BOB));
+    assert_eq!(video.param("caption").as_deref(), Some("watch this"));
+}
+
+#[tokio::test]
+async fn contact_is_relayed_only_after_warning() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+    let contact = json!({ "contact": { "phone_number": "+15550100", "first_name": "Alice" } });
+
+    bot.send_message(ALICE, contact.clone()).await;
+    let calls = bot.take_calls().await;
+    assert!(calls.iter().all(|call| call.chat_id() != Some(BOB)), "{:?}", calls);
+    assert!(calls.iter().any(|call| call.text().is_some_and(|text| text.contains("Send the contact again"))));
+
+    bot.send_message(ALICE, contact).await;
+    let calls = bot.take_calls().await;
+    assert!(calls
+        .iter()
+        .any(|call| call.method == "SendContact" && call.chat_id() == Some(BOB)));
+}
+
+#[tokio::test]
+async fn room_media_reaches_every_other_member() {
+    let bot = TestBot::new().await;
+    for user in [ALICE, BOB, CAROL] {
+        bot.send_text(user, "/start").await;
+    }
+    bot.send_text(ALICE, "/createroom Gallery 5").await;
+    let room = bot.state.rooms.list_rooms().await.unwrap().pop().expect("room was not created");
+    for user in [ALICE, BOB, CAROL] {
+        bot.send_text(user, &format!("/joinroom {}", room.room_id)).await;
+    }
+    bot.take_calls().await;
+
+    bot.send_message(ALICE, json!({
+        "photo": [{ "file_id": "sunset", "file_unique_id": "s", "width": 800, "height": 600 }],
+        "caption": "view from here",
+    }))
+    .await;
+
+    let photos: Vec<_> = bot
+        .take_calls()
+        .await
+        .into_iter()
+        .filter(|call| call.method == "SendPhoto")
+        .collect();
+    let mut recipients: Vec<_> = photos.iter().filter_map(|call| call.chat_id()).collect();
+    recipients.sort();
+    assert_eq!(recipients, vec![BOB, CAROL]);
+    for photo in &photos {
+        assert_eq!(photo.param("photo").as_deref(), Some("sunset"));
+        assert_eq!(photo.param("caption").as_deref(), Some("👤 Anonymous: view from here"));
+    }
+}
+
+#[tokio::test]
+async fn replies_are_threaded_on_both_sides() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+
+    let original_id = bot.send_message(ALICE, json!({ "text": "how are you?" })).await;
+    let bob_copy_id = bot.take_calls().await[0].sent_message_id.unwrap();
+
+    // Bob replies to the copy he received; Alice sees a reply to her original
+    bot.send_reply(BOB, json!({ "text": "great, you?" }), bob_copy_id).await;
+    let calls = bot.take_calls().await;
+    assert_eq!(calls[0].chat_id(), Some(ALICE));
+    assert_eq!(calls[0].param("reply_to_message_id"), Some(original_id.to_string()));
+
+    // Alice replies to her own message; Bob sees a reply to his copy of it
+    bot.send_reply(ALICE, json!({ "text": "also, hi" }), original_id).await;
+    let calls = bot.take_calls().await;
+    assert_eq!(calls[0].chat_id(), Some(BOB));
+    assert_eq!(calls[0].param("reply_to_message_id"), Some(bob_copy_id.to_string()));
+}
+
+#[tokio::test]
+async fn room_replies_are_threaded_for_every_member() {
+    let bot = TestBot::new().await;
+    for user in [ALICE, BOB, CAROL] {
+        bot.send_text(user, "/start").await;
+    }
+    bot.send_text(ALICE, "/createroom Lounge 5").await;
+    let room = bot.state.rooms.list_rooms().await.unwrap().pop().expect("room was not created");
+    for user in [ALICE, BOB, CAROL] {
+        bot.send_text(user, &format!("/joinroom {}", room.room_id)).await;
+    }
+    bot.take_calls().await;
+
+    let original_id = bot.send_message(ALICE, json!({ "text": "question for all" })).await;
+    let copies = bot.take_calls().await;
+    let copy_for = |user: i64| {
+        copies
+            .iter()
+            .find(|call| call.chat_id() == Some(user))
+            .and_then(|call| call.sent_message_id)
+            .unwrap()
+    };
+    let (bob_copy_id, carol_copy_id) = (copy_for(BOB), copy_for(CAROL));
+
+    bot.send_reply(BOB, json!({ "text": "answer" }), bob_copy_id).await;
+    let calls = bot.take_calls().await;
+    let reply_target = |user: i64| {
+        calls
+            .iter()
+            .find(|call| call.chat_id() == Some(user))
+            .and_then(|call| call.param("reply_to_message_id"))
+    };
+    assert_eq!(reply_target(ALICE), Some(original_id.to_string()));
+    assert_eq!(reply_target(CAROL), Some(carol_copy_id.to_string()));
+}
+
+#[tokio::test]
+async fn edits_are_applied_to_the_partners_copy() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+
+    let original_id = bot.send_message(ALICE, json!({ "text": "see you at 5" })).await;
+    let bob_copy_id = bot.take_calls().await[0].sent_message_id.unwrap();
+
+    bot.edit_message(ALICE, original_id, json!({ "text": "see you at 6" })).await;
+    let calls = bot.take_calls().await;
+    assert_eq!(calls.len(), 1, "{:?}", calls);
+    assert_eq!(calls[0].method, "EditMessageText");
+    assert_eq!(calls[0].chat_id(), Some(BOB));
+    assert_eq!(calls[0].param("message_id"), Some(bob_copy_id.to_string()));
+    assert_eq!(calls[0].text().as_deref(), Some("see you at 6"));
+}
+
+#[tokio::test]
+async fn unsend_deletes_only_your_own_messages() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+
+    let original_id = bot.send_message(ALICE, json!({ "text": "oops, wrong chat" })).await;
+    let bob_copy_id = bot.take_calls().await[0].sent_message_id.unwrap();
+
+    // Bob cannot unsend Alice's message through his copy of it
+    bot.send_reply(BOB, json!({ "text": "/unsend" }), bob_copy_id).await;
+    let calls = bot.take_calls().await;
+    assert!(calls.iter().all(|call| call.method != "DeleteMessage"), "{:?}", calls);
+
+    bot.send_reply(ALICE, json!({ "text": "/unsend" }), original_id).await;
+    let calls = bot.take_calls().await;
+    let delete = calls
+        .iter()
+        .find(|call| call.method == "DeleteMessage")
+        .expect("copy was not deleted");
+    assert_eq!(delete.chat_id(), Some(BOB));
+    assert_eq!(delete.param("message_id"), Some(bob_copy_id.to_string()));
+    assert!(calls.iter().any(|call| call.chat_id() == Some(ALICE)
+        && call.text().is_some_and(|text| text.contains("Message unsent"))));
+}
+
+#[tokio::test]
+async fn unsend_still_reaches_past_partners() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+    let original_id = bot.send_message(ALICE, json!({ "text": "my number is on my profile" })).await;
+    let bob_copy_id = bot.take_calls().await[0].sent_message_id.unwrap();
+
+    // Alice has since moved on to Carol
+    bot.send_text(CAROL, "/start").await;
+    bot.send_text(CAROL, "/find").await;
+    bot.send_text(ALICE, "/next").await;
+    bot.take_calls().await;
+
+    bot.send_reply(ALICE, json!({ "text": "/unsend" }), original_id).await;
+    let calls = bot.take_calls().await;
+    let deletes: Vec<_> = calls.iter().filter(|call| call.method == "DeleteMessage").collect();
+    assert_eq!(deletes.len(), 1, "{:?}", calls);
+    assert_eq!(deletes[0].chat_id(), Some(BOB));
+    assert_eq!(deletes[0].param("message_id"), Some(bob_copy_id.to_string()));
+    assert!(calls.iter().any(|call| call.chat_id() == Some(ALICE)
+        && call.text().is_some_and(|text| text.contains("Message unsent"))));
+}
+
+#[tokio::test]
+async fn presence_is_shared_only_by_users_who_opt_in() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+    bot.send_text(ALICE, "/presence on").await;
+    bot.take_calls().await;
+
+    bot.send_text(ALICE, "hi!").await;
+    let calls = bot.take_calls().await;
+    assert_eq!(calls.len(), 2, "{:?}", calls);
+    assert_eq!(calls[0].method, "SendChatAction");
+    assert_eq!(calls[0].chat_id(), Some(BOB));
+    assert_eq!(calls[0].param("action").as_deref(), Some("typing"));
+    assert_eq!(calls[1].chat_id(), Some(BOB));
+
+    bot.send_message(ALICE, json!({
+        "photo": [{ "file_id": "sunset", "file_unique_id": "s", "width": 800, "height": 600 }],
+    })).await;
+    let calls = bot.take_calls().await;
+    assert_eq!(calls[0].method, "SendChatAction");
+    assert_eq!(calls[0].param("action").as_deref(), Some("upload_photo"));
+    assert_eq!(calls[1].method, "SendPhoto");
+
+    // Bob has not opted in, so Alice gets no typing indicator or read receipt
+    bot.send_text(BOB, "hello").await;
+    let calls = bot.take_calls().await;
+    assert_eq!(calls.len(), 1, "{:?}", calls);
+
+    // Once Bob has, any update from Bob marks Alice's last message seen
+    bot.send_text(BOB, "/presence on").await;
+    bot.take_calls().await;
+    let alice_message_id = bot.send_message(ALICE, json!({ "text": "still there?" })).await;
+    bot.take_calls().await;
+    bot.send_text(BOB, "/help").await;
+    let calls = bot.take_calls().await;
+    let reactions: Vec<_> = calls.iter().filter(|call| call.method == "SetMessageReaction").collect();
+    assert_eq!(reactions.len(), 1, "{:?}", calls);
+    assert_eq!(reactions[0].chat_id(), Some(ALICE));
+    assert_eq!(reactions[0].param("message_id"), Some(alice_message_id.to_string()));
+    assert!(reactions[0].param("reaction").is_some_and(|reaction| reaction.contains("👀")));
+    assert!(!calls.iter().any(|call| call.chat_id() == Some(ALICE) && call.method.starts_with("Send")));
+
+    // Only once per message
+    bot.send_text(BOB, "/help").await;
+    assert!(!bot.take_calls().await.iter().any(|call| call.method == "SetMessageReaction"));
+}
+
+#[tokio::test]
+async fn personal_info_is_masked_warned_or_withheld() {
+    let bot = TestBot::new().await;
+    bot.pair(ALICE, BOB).await;
+
+    bot.send_text(ALICE, "text me on 0812 3456 7890").await;
+    assert_eq!(bot.take_texts_to(BOB).await, vec!["text me on **************"]);
+
+    bot.send_text(ALICE, "see example.com").await;
+    let calls = bot.take_calls().await;
+    let to = |chat_id: i64| -> Vec<String> {
+        calls.iter().filter(|call| call.chat_id() == Some(chat_id)).filter_map(|call| call.text()).collect()
+    };
+    assert_eq!(to(BOB), vec!["see example.com"]);
+    assert!(to(ALICE).iter().any(|text| text.contains("contains a link")), "{:?}", to(ALICE));
+
+    bot.send_text(ALICE, "join t.me/+secretgroup").await;
+    assert!(bot.take_calls().await.iter().all(|call| call.chat_id() != Some(BOB)));
+}
+
+#[tokio::test]
+async fn room_captions_are_checked_for_personal_info() {
+    let bot = TestBot::new().await;
+    for user in [ALICE, BOB] {
+        bot.send_text(user, "/start").await;
+    }
+    bot.send_text(ALICE, "/createroom Lobby 5").await;
+    let room = bot.state.rooms.list_rooms().await.unwrap().pop().expect("room was not created");
+    for user in [ALICE, BOB] {
+        bot.send_text(user, &format!("/joinroom {}", room.room_id)).await;
+    }
+    bot.take_calls().await;
+
+    bot.send_message(ALICE, json!({
+        "photo": [{ "file_id": "card", "file_unique_id": "c", "width": 800, "height": 600 }],
+        "caption": "my email is jane@example.com",
+    }))
+    .await;
+
+    let calls = bot.take_calls().await;
+    let photo = calls.iter().find(|call| call.method == "SendPhoto").expect("photo was not relayed");
+    assert_eq!(photo.chat_id(), Some(BOB));
+    assert_eq!(photo.param("caption").as_deref(), Some("👤 Anonymous: my email is ****************"));
+}
+
+mod matchmaking {
+    use super::*;
+    use std::time::Duration;
+    use telegram_bot::{
+        models::AppState,
+        services::{
+            inactivity::{self, INACTIVITY_TIMEOUT},
+            matchmaking::{self, MatchSettings},
+        },
+    };
+
+    const DAVE: i64 = 1004;
+    const NEVER: u64 = 3600;
+
+    async fn bot_with_settings(matching: MatchSettings) -> TestBot {
+        TestBot::with_state(AppState { matching, ..AppState::in_memory() }).await
+    }
+
+    async fn partner_of(bot: &TestBot, chat_id: i64) -> Option<i64> {
+        bot.state.sessions.get_user_state(chat_id).await.unwrap().unwrap().partner_id
+    }
+
+    #[tokio::test]
+    async fn shared_interests_are_preferred_until_the_fallback() {
+        let bot = bot_with_settings(MatchSettings {
+            fallback_secs: 1,
+            language_fallback_secs: NEVER,
+            search_timeout_secs: NEVER,
+        })
+        .await;
+        for (user, find) in [(ALICE, "/find music"), (CAROL, "/find #Gaming"), (BOB, "/find Music")] {
+            bot.send_text(user, "/start").await;
+            bot.send_text(user, find).await;
+        }
+        let texts = bot.take_texts_to(BOB).await;
+        assert!(texts.iter().any(|text| text.contains("Shared interests: music")), "{:?}", texts);
+        assert_eq!(partner_of(&bot, ALICE).await, Some(BOB));
+
+        // Nobody else shares an interest, so they wait until the fallback
+        bot.send_text(DAVE, "/start").await;
+        bot.send_text(DAVE, "/find cooking").await;
+        assert_eq!(partner_of(&bot, DAVE).await, None);
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        matchmaking::match_waiting_users(&bot.bot, &bot.state).await.unwrap();
+        assert_eq!(partner_of(&bot, CAROL).await, Some(DAVE));
+    }
+
+    #[tokio::test]
+    async fn same_language_partners_come_first() {
+        let bot = bot_with_settings(MatchSettings {
+            fallback_secs: NEVER,
+            language_fallback_secs: 1,
+            search_timeout_secs: NEVER,
+        })
+        .await;
+        // Everyone's Telegram client is in English; /language overrides it
+        for (user, language) in [(ALICE, "id"), (BOB, "en"), (CAROL, "ID"), (DAVE, "fr")] {
+            bot.send_text(user, "/start").await;
+            bot.send_text(user, &format!("/language {}", language)).await;
+            bot.send_text(user, "/find").await;
+        }
+        assert_eq!(partner_of(&bot, ALICE).await, Some(CAROL));
+        assert_eq!(partner_of(&bot, BOB).await, None);
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        matchmaking::match_waiting_users(&bot.bot, &bot.state).await.unwrap();
+        assert_eq!(partner_of(&bot, BOB).await, Some(DAVE));
+    }
+
+    #[tokio::test]
+    async fn next_moves_on_without_losing_the_profile() {
+        let bot = TestBot::new().await;
+        bot.pair(ALICE, BOB).await;
+        bot.send_text(ALICE, "/setprofile Ali 🦊 hello").await;
+        bot.send_text(ALICE, "/setmood happy sunny").await;
+        bot.send_text(CAROL, "/start").await;
+        bot.send_text(CAROL, "/find").await;
+        bot.take_calls().await;
+
+        bot.send_text(ALICE, "/next").await;
+        let texts = bot.take_texts_to(BOB).await;
+        assert!(texts.iter().any(|text| text.contains("partner has left")), "{:?}", texts);
+        assert_eq!(partner_of(&bot, BOB).await, None);
+
+        let alice = bot.state.sessions.get_user_state(ALICE).await.unwrap().unwrap();
+        assert_eq!(alice.partner_id, Some(CAROL));
+        assert_eq!(alice.profile.map(|profile| profile.nickname).as_deref(), Some("Ali"));
+        assert_eq!(alice.daily_mood.map(|mood| mood.mood).as_deref(), Some("happy"));
+    }
+
+    #[tokio::test]
+    async fn partners_who_blocked_the_bot_are_still_left() {
+        for command in ["/next", "/leave", "/block"] {
+            let bot = TestBot::new().await;
+            bot.pair(ALICE, BOB).await;
+            bot.block_bot(BOB);
+            bot.send_text(ALICE, command).await;
+            assert_eq!(partner_of(&bot, ALICE).await, None, "{}", command);
+            assert_eq!(partner_of(&bot, BOB).await, None, "{}", command);
+        }
+    }
+
+    #[tokio::test]
+    async fn searches_can_be_cancelled_or_expire() {
+        let bot = bot_with_settings(MatchSettings {
+            fallback_secs: NEVER,
+            language_fallback_secs: NEVER,
+            search_timeout_secs: 0,
+        })
+        .await;
+        bot.send_text(ALICE, "/start").await;
+        bot.send_text(ALICE, "/find").await;
+        bot.send_text(ALICE, "/cancel").await;
+        bot.send_text(ALICE, "/cancel").await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("Search cancelled")), "{:?}", texts);
+        assert!(texts.last().is_some_and(|text| text.contains("not searching")), "{:?}", texts);
+
+        // Only searches idle past the timeout expire, even for users who
+        // blocked the bot
+        for (user, find) in [(DAVE, "/find birds"), (BOB, "/find cats"), (CAROL, "/find dogs")] {
+            bot.send_text(user, "/start").await;
+            bot.send_text(user, find).await;
+        }
+        for user in [DAVE, BOB] {
+            bot.state.sessions.update_user_state(user, &|state| state.last_activity -= 60).await.unwrap();
+        }
+        bot.block_bot(DAVE);
+        bot.take_calls().await;
+        matchmaking::expire_stale_searches(&bot.bot, &bot.state).await.unwrap();
+        let texts = bot.take_texts_to(BOB).await;
+        assert!(texts.iter().any(|text| text.contains("No partner found")), "{:?}", texts);
+        assert!(!bot.state.sessions.get_user_state(DAVE).await.unwrap().unwrap().is_searching);
+        assert!(!bot.state.sessions.get_user_state(BOB).await.unwrap().unwrap().is_searching);
+        assert!(bot.state.sessions.get_user_state(CAROL).await.unwrap().unwrap().is_searching);
+    }
+
+    #[tokio::test]
+    async fn idle_users_are_disconnected_in_the_background() {
+        let bot = TestBot::new().await;
+        bot.pair(ALICE, BOB).await;
+        for user in [CAROL, DAVE] {
+            bot.send_text(user, "/start").await;
+        }
+        bot.send_text(CAROL, "/createroom Quiet 5").await;
+        let room = bot.state.rooms.list_rooms().await.unwrap().pop().expect("room was not created");
+        for user in [CAROL, DAVE] {
+            bot.send_text(user, &format!("/joinroom {}", room.room_id)).await;
+        }
+        for user in [ALICE, DAVE] {
+            bot.state.sessions
+                .update_user_state(user, &|state| state.last_activity -= INACTIVITY_TIMEOUT + 60)
+                .await
+                .unwrap();
+        }
+        bot.take_calls().await;
+
+        inactivity::reap_inactive_users(&bot.bot, &bot.state).await.unwrap();
+        let calls = bot.take_calls().await;
+        let to = |chat_id: i64| -> Vec<String> {
+            calls.iter().filter(|call| call.chat_id() == Some(chat_id)).filter_map(|call| call.text()).collect()
+        };
+        assert!(to(ALICE).iter().any(|text| text.contains("You have been disconnected")), "{:?}", to(ALICE));
+        assert!(to(BOB).iter().any(|text| text.contains("partner has been disconnected")), "{:?}", to(BOB));
+        assert!(to(CAROL).iter().any(|text| text.contains("left the chat room due to inactivity")), "{:?}", to(CAROL));
+        assert_eq!(partner_of(&bot, BOB).await, None);
+        let dave = bot.state.sessions.get_user_state(DAVE).await.unwrap().unwrap();
+        assert_eq!(dave.current_room, None);
+        let carol = bot.state.sessions.get_user_state(CAROL).await.unwrap().unwrap();
+        assert_eq!(carol.current_room, Some(room.room_id));
+    }
+}
+
+mod reports {
+    use super::*;
+    use chrono::Utc;
+    use telegram_bot::{models::{RecentMessage, ReportStatus}, services::{relay, roles}};
+
+    #[tokio::test]
+    async fn reports_snapshot_the_chat_and_admins_resolve_them() {
+        let bot = TestBot::new().await;
+        bot.pair(ALICE, BOB).await;
+        roles::bootstrap(&bot.state, &[CAROL]).await.unwrap();
+
+        bot.send_text(ALICE, "hi").await;
+        bot.send_text(BOB, "send me money").await;
+        bot.send_text(ALICE, "/leave").await;
+        bot.take_calls().await;
+
+        // Reporting still works right after the chat ends
+        bot.send_text(ALICE, "/report scam").await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("has been filed")), "{:?}", texts);
+
+        let report = bot.state.moderation.list_open_reports(10).await.unwrap().pop().expect("report was not filed");
+        assert_eq!(report.reporter_id, ALICE);
+        assert_eq!(report.reported_id, Some(BOB));
+        assert_eq!(report.reason, "scam");
+        let said: Vec<_> = report.messages.iter().map(|message| (message.sender_id, message.content.as_str())).collect();
+        assert_eq!(said, vec![(ALICE, "hi"), (BOB, "send me money")]);
+
+        bot.send_text(BOB, "/reports").await;
+        let texts = bot.take_texts_to(BOB).await;
+        assert!(texts.iter().any(|text| text.contains("only available for administrators")), "{:?}", texts);
+
+        bot.send_text(CAROL, "/reports").await;
+        let texts = bot.take_texts_to(CAROL).await;
+        assert!(texts.iter().any(|text| text.contains(&format!("#{}", report.report_id))), "{:?}", texts);
+
+        bot.send_text(CAROL, &format!("/review {}", report.report_id)).await;
+        let texts = bot.take_texts_to(CAROL).await;
+        let expected = format!("{} (reported): send me money", pseudonym(&bot, BOB).await);
+        assert!(texts.iter().any(|text| text.contains(&expected)), "{:?}", texts);
+        let chat_ids = texts.iter().flat_map(|text| text.split(|c: char| !c.is_alphanumeric()));
+        assert!(chat_ids.into_iter().all(|word| word != BOB.to_string()), "{:?}", texts);
+
+        bot.send_text(CAROL, &format!("/resolve {} warned the user", report.report_id)).await;
+        let calls = bot.take_calls().await;
+        assert!(calls.iter().any(|call| call.chat_id() == Some(ALICE)
+            && call.text().is_some_and(|text| text.contains("has been reviewed"))));
+        let report = bot.state.moderation.get_report(&report.report_id).await.unwrap().unwrap();
+        assert_eq!(report.status, ReportStatus::Resolved);
+        assert_eq!(report.resolution.as_deref(), Some("warned the user"));
+        assert!(bot.state.moderation.list_open_reports(10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn long_reports_are_split_to_fit_telegram() {
+        let bot = TestBot::new().await;
+        bot.pair(ALICE, BOB).await;
+        roles::bootstrap(&bot.state, &[CAROL]).await.unwrap();
+        let conversation = relay::private_conversation_id(ALICE, BOB);
+        for _ in 0..20 {
+            let message = RecentMessage { sender_id: BOB, content: "😈".repeat(300), sent_at: Utc::now() };
+            bot.state.sessions.record_recent_message(&conversation, &message).await.unwrap();
+        }
+        bot.send_text(ALICE, &format!("/report {}", "spam ".repeat(2000))).await;
+        let report = bot.state.moderation.list_open_reports(10).await.unwrap().pop().expect("report was not filed");
+        bot.take_calls().await;
+
+        bot.send_text(CAROL, "/reports").await;
+        bot.send_text(CAROL, &format!("/review {}", report.report_id)).await;
+        let texts = bot.take_texts_to(CAROL).await;
+        assert!(texts.len() > 2, "{:?}", texts);
+        assert!(texts.iter().all(|text| text.encode_utf16().count() <= 4096), "{:?}", texts);
+        let lines = texts.iter().flat_map(|text| text.lines()).filter(|line| line.contains("😈")).count();
+        assert_eq!(lines, 20);
+    }
+
+    #[tokio::test]
+    async fn room_reports_include_room_messages() {
+        let bot = TestBot::new().await;
+        for user in [ALICE, BOB] {
+            bot.send_text(user, "/start").await;
+        }
+        bot.send_text(ALICE, "/createroom Lobby 5").await;
+        let room = bot.state.rooms.list_rooms().await.unwrap().pop().expect("room was not created");
+        for user in [ALICE, BOB] {
+            bot.send_text(user, &format!("/joinroom {}", room.room_id)).await;
+        }
+        bot.send_message(BOB, json!({ "sticker": {
+            "file_id": "rude", "file_unique_id": "r", "type": "regular",
+            "width": 512, "height": 512, "is_animated": false, "is_video": false,
+        }}))
+        .await;
+
+        bot.send_text(ALICE, "/report").await;
+        let report = bot.state.moderation.list_open_reports(10).await.unwrap().pop().expect("report was not filed");
+        assert_eq!(report.room_id.as_deref(), Some(room.room_id.as_str()));
+        assert_eq!(report.reported_id, None);
+        assert_eq!(report.reason, "No reason given");
+        assert_eq!(report.messages.len(), 1);
+        assert_eq!(report.messages[0].content, "[sticker]");
+    }
+}
+
+mod sanctions {
+    use super::*;
+    use telegram_bot::services::roles;
+
+    const DAVE: i64 = 1004;
+    const ADMIN: i64 = 1900;
+
+    async fn bot_with_admin() -> TestBot {
+        let bot = TestBot::new().await;
+        roles::bootstrap(&bot.state, &[ADMIN]).await.unwrap();
+        bot
+    }
+
+    #[tokio::test]
+    async fn bans_cut_users_off_until_lifted() {
+        let bot = bot_with_admin().await;
+        bot.pair(ALICE, BOB).await;
+
+        bot.send_text(BOB, &format!("/ban {}", pseudonym(&bot, ALICE).await)).await;
+        let texts = bot.take_texts_to(BOB).await;
+        assert!(texts.iter().any(|text| text.contains("only available for administrators")), "{:?}", texts);
+
+        bot.send_text(ADMIN, &format!("/ban {} 7d", pseudonym(&bot, ALICE).await)).await;
+        let calls = bot.take_calls().await;
+        let to = |chat_id: i64| -> Vec<String> {
+            calls.iter().filter(|call| call.chat_id() == Some(chat_id)).filter_map(|call| call.text()).collect()
+        };
+        assert!(to(ALICE).iter().any(|text| text.contains("You have been banned until")), "{:?}", to(ALICE));
+        assert!(to(BOB).iter().any(|text| text.contains("partner has left")), "{:?}", to(BOB));
+        assert!(to(ADMIN).iter().any(|text| text.contains("banned until")), "{:?}", to(ADMIN));
+
+        bot.send_text(ALICE, "/find").await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("You're banned")), "{:?}", texts);
+        let alice = bot.state.sessions.get_user_state(ALICE).await.unwrap().unwrap();
+        assert!(!alice.is_searching);
+
+        bot.send_text(ADMIN, &format!("/unban {}", pseudonym(&bot, ALICE).await)).await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("restrictions have been lifted")), "{:?}", texts);
+        bot.send_text(ALICE, "/find").await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("Looking for a chat partner")), "{:?}", texts);
+    }
+
+    #[tokio::test]
+    async fn out_of_range_durations_are_refused() {
+        let bot = bot_with_admin().await;
+        bot.send_text(ALICE, "/start").await;
+        bot.take_calls().await;
+
+        bot.send_text(ADMIN, &format!("/ban {} 99999999999999w", pseudonym(&bot, ALICE).await)).await;
+        let texts = bot.take_texts_to(ADMIN).await;
+        assert!(texts.iter().any(|text| text.contains("Durations look like")), "{:?}", texts);
+
+        bot.send_text(ALICE, "/find").await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("Looking for a chat partner")), "{:?}", texts);
+    }
+
+    #[tokio::test]
+    async fn users_are_known_only_by_rotatable_pseudonyms() {
+        let bot = bot_with_admin().await;
+        bot.send_text(ALICE, "/myid").await;
+        let old = pseudonym(&bot, ALICE).await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains(&old)), "{:?}", texts);
+
+        bot.send_text(ADMIN, &format!("/mute {}", ALICE)).await;
+        let texts = bot.take_texts_to(ADMIN).await;
+        assert!(texts.iter().any(|text| text.contains("Unknown user")), "{:?}", texts);
+
+        bot.send_text(ALICE, "/myid new").await;
+        let new = pseudonym(&bot, ALICE).await;
+        assert_ne!(old, new);
+        bot.send_text(ADMIN, &format!("/mute {}", old)).await;
+        let texts = bot.take_texts_to(ADMIN).await;
+        assert!(texts.iter().any(|text| text.contains("Unknown user")), "{:?}", texts);
+
+        bot.send_text(ADMIN, &format!("/mute {}", new.to_lowercase())).await;
+        let texts = bot.take_texts_to(ADMIN).await;
+        assert!(texts.iter().any(|text| text.contains(&format!("User {} muted", new))), "{:?}", texts);
+
+        bot.send_text(ALICE, "/myid new").await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("once a day")), "{:?}", texts);
+        assert_eq!(pseudonym(&bot, ALICE).await, new);
+    }
+
+    #[tokio::test]
+    async fn reported_users_keep_their_pseudonym_until_reviewed() {
+        let bot = bot_with_admin().await;
+        bot.pair(ALICE, BOB).await;
+        bot.send_text(BOB, "send me money").await;
+        bot.send_text(ALICE, "/report scam").await;
+        let old = pseudonym(&bot, BOB).await;
+        bot.take_calls().await;
+
+        // The reporter may still move on
+        let reporter = pseudonym(&bot, ALICE).await;
+        bot.send_text(ALICE, "/myid new").await;
+        assert_ne!(pseudonym(&bot, ALICE).await, reporter);
+
+        bot.send_text(BOB, "/myid new").await;
+        let texts = bot.take_texts_to(BOB).await;
+        assert!(texts.iter().any(|text| text.contains("report about you is open")), "{:?}", texts);
+        bot.send_text(ADMIN, &format!("/ban {}", old)).await;
+        let texts = bot.take_texts_to(ADMIN).await;
+        assert!(texts.iter().any(|text| text.contains(&format!("User {} banned", old))), "{:?}", texts);
+    }
+
+    #[tokio::test]
+    async fn muted_users_keep_their_commands() {
+        let bot = bot_with_admin().await;
+        bot.pair(ALICE, BOB).await;
+
+        bot.send_text(ADMIN, &format!("/mute {} 1h", pseudonym(&bot, ALICE).await)).await;
+        bot.take_calls().await;
+
+        bot.send_text(ALICE, "hello?").await;
+        let calls = bot.take_calls().await;
+        assert!(calls.iter().all(|call| call.chat_id() != Some(BOB)), "{:?}", calls);
+        assert!(calls.iter().any(|call| call.chat_id() == Some(ALICE)
+            && call.text().is_some_and(|text| text.contains("You're muted"))));
+
+        bot.send_text(ALICE, "/leave").await;
+        let texts = bot.take_texts_to(BOB).await;
+        assert!(texts.iter().any(|text| text.contains("partner has left")), "{:?}", texts);
+    }
+
+    #[tokio::test]
+    async fn shadow_banned_users_only_meet_each_other() {
+        let bot = bot_with_admin().await;
+        for user in [ALICE, BOB, CAROL, DAVE] {
+            bot.send_text(user, "/start").await;
+        }
+        bot.take_calls().await;
+        for user in [ALICE, CAROL] {
+            bot.send_text(ADMIN, &format!("/shadowban {}", pseudonym(&bot, user).await)).await;
+        }
+        // Shadow-banned users are never told
+        assert!(bot.take_texts_to(ALICE).await.is_empty());
+
+        for user in [ALICE, BOB, CAROL, DAVE] {
+            bot.send_text(user, "/find").await;
+        }
+        for (user, partner) in [(ALICE, CAROL), (BOB, DAVE)] {
+            let user_state = bot.state.sessions.get_user_state(user).await.unwrap().unwrap();
+            assert_eq!(user_state.partner_id, Some(partner));
+        }
+    }
+
+    #[tokio::test]
+    async fn shadow_bans_end_chats_with_everyone_else() {
+        let bot = bot_with_admin().await;
+        bot.pair(ALICE, BOB).await;
+        bot.send_text(ADMIN, &format!("/shadowban {}", pseudonym(&bot, ALICE).await)).await;
+        let calls = bot.take_calls().await;
+        for user in [ALICE, BOB] {
+            let texts: Vec<_> = calls.iter().filter(|call| call.chat_id() == Some(user)).filter_map(|call| call.text()).collect();
+            assert!(texts.iter().any(|text| text.contains("partner has left")), "{:?}", texts);
+            assert!(texts.iter().all(|text| !text.contains("ban")), "{:?}", texts);
+        }
+        bot.send_text(ALICE, "still there?").await;
+        assert!(bot.take_calls().await.iter().all(|call| call.chat_id() != Some(BOB)));
+
+        // Two shadow-banned users keep talking
+        bot.send_text(ADMIN, &format!("/shadowban {}", pseudonym(&bot, CAROL).await)).await;
+        bot.send_text(ALICE, "/find").await;
+        bot.send_text(CAROL, "/start").await;
+        bot.send_text(CAROL, "/find").await;
+        bot.send_text(ADMIN, &format!("/shadowban {} 1h", pseudonym(&bot, ALICE).await)).await;
+        let alice = bot.state.sessions.get_user_state(ALICE).await.unwrap().unwrap();
+        assert_eq!(alice.partner_id, Some(CAROL));
+    }
+
+    #[tokio::test]
+    async fn shadow_banned_room_messages_go_nowhere() {
+        let bot = bot_with_admin().await;
+        for user in [ALICE, BOB] {
+            bot.send_text(user, "/start").await;
+        }
+        bot.send_text(ALICE, "/createroom Lobby 5").await;
+        let room = bot.state.rooms.list_rooms().await.unwrap().pop().expect("room was not created");
+        for user in [ALICE, BOB] {
+            bot.send_text(user, &format!("/joinroom {}", room.room_id)).await;
+        }
+        bot.send_text(ADMIN, &format!("/shadowban {}", pseudonym(&bot, ALICE).await)).await;
+        bot.take_calls().await;
+
+        bot.send_text(ALICE, "buy my stuff").await;
+        assert!(bot.take_calls().await.is_empty());
+        bot.send_text(BOB, "hi all").await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("hi all")), "{:?}", texts);
+    }
+}
+
+mod staff_roles {
+    use super::*;
+    use telegram_bot::{models::{Permission, Role}, services::roles};
+
+    const OWNER: i64 = 1900;
+
+    #[tokio::test]
+    async fn roles_grant_their_own_commands() {
+        let bot = TestBot::new().await;
+        roles::bootstrap(&bot.state, &[OWNER]).await.unwrap();
+
+        bot.send_text(OWNER, &format!("/promote {} admin", pseudonym(&bot, ALICE).await)).await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("You are now an admin")), "{:?}", texts);
+
+        // Admins can make moderators, but not more admins
+        bot.send_text(ALICE, &format!("/promote {} admin", pseudonym(&bot, BOB).await)).await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("roles below yours")), "{:?}", texts);
+        bot.send_text(ALICE, &format!("/promote {} moderator", pseudonym(&bot, BOB).await)).await;
+        assert_eq!(roles::role_of(&bot.state, BOB).await.unwrap(), Some(Role::Moderator));
+
+        // A fresh /start no longer wipes the role
+        bot.send_text(BOB, "/start").await;
+        bot.send_text(BOB, "/reports").await;
+        let texts = bot.take_texts_to(BOB).await;
+        assert!(texts.iter().any(|text| text.contains("No open reports")), "{:?}", texts);
+        for command in ["/reloadfilter", "/broadcast hi", &format!("/demote {}", pseudonym(&bot, ALICE).await)] {
+            bot.send_text(BOB, command).await;
+            let texts = bot.take_texts_to(BOB).await;
+            assert!(texts.iter().any(|text| text.contains("not available to a moderator")), "{}: {:?}", command, texts);
+        }
+
+        bot.send_text(ALICE, &format!("/demote {}", pseudonym(&bot, OWNER).await)).await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("only demote staff below you")), "{:?}", texts);
+        bot.send_text(ALICE, &format!("/demote {}", pseudonym(&bot, BOB).await)).await;
+        bot.send_text(BOB, "/reports").await;
+        let texts = bot.take_texts_to(BOB).await;
+        assert!(texts.iter().any(|text| text.contains("only available for administrators")), "{:?}", texts);
+    }
+
+    #[tokio::test]
+    async fn staff_cannot_sanction_their_equals_or_superiors() {
+        let bot = TestBot::new().await;
+        roles::bootstrap(&bot.state, &[OWNER]).await.unwrap();
+        bot.send_text(OWNER, &format!("/promote {} admin", pseudonym(&bot, ALICE).await)).await;
+        bot.send_text(ALICE, &format!("/promote {} moderator", pseudonym(&bot, BOB).await)).await;
+        bot.send_text(ALICE, &format!("/promote {} moderator", pseudonym(&bot, CAROL).await)).await;
+        bot.take_calls().await;
+
+        for target in [OWNER, ALICE, CAROL] {
+            bot.send_text(BOB, &format!("/ban {}", pseudonym(&bot, target).await)).await;
+            let texts = bot.take_texts_to(BOB).await;
+            assert!(texts.iter().any(|text| text.contains("only sanction users below you")), "{:?}", texts);
+        }
+        bot.send_text(ALICE, "/reports").await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("No open reports")), "{:?}", texts);
+
+        // Only someone above a sanctioned user may lift it
+        bot.send_text(ALICE, &format!("/mute {}", pseudonym(&bot, CAROL).await)).await;
+        bot.send_text(BOB, &format!("/unban {}", pseudonym(&bot, CAROL).await)).await;
+        let texts = bot.take_texts_to(BOB).await;
+        assert!(texts.iter().any(|text| text.contains("only lift sanctions from users below you")), "{:?}", texts);
+        bot.send_text(ALICE, &format!("/unban {}", pseudonym(&bot, CAROL).await)).await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("Lifted 1 sanctions")), "{:?}", texts);
+    }
+
+    #[tokio::test]
+    async fn owners_follow_admin_chat_ids() {
+        let bot = TestBot::new().await;
+        roles::bootstrap(&bot.state, &[OWNER, ALICE]).await.unwrap();
+        bot.send_text(OWNER, &format!("/promote {} admin", pseudonym(&bot, BOB).await)).await;
+
+        // Dropping an owner from the list revokes them on the next startup,
+        // leaving everyone else's roles alone
+        roles::bootstrap(&bot.state, &[OWNER]).await.unwrap();
+        assert_eq!(roles::role_of(&bot.state, ALICE).await.unwrap(), None);
+        assert_eq!(roles::role_of(&bot.state, OWNER).await.unwrap(), Some(Role::Owner));
+        assert_eq!(roles::role_of(&bot.state, BOB).await.unwrap(), Some(Role::Admin));
+        assert!(!Role::Admin.can(Permission::ManageAdmins) && Role::Owner.can(Permission::ManageAdmins));
+    }
+}
+
+mod broadcasts {
+    use super::*;
+    use telegram_bot::services::roles;
+
+    const OWNER: i64 = 1900;
+
+    async fn bot_with_users() -> TestBot {
+        let bot = TestBot::new().await;
+        roles::bootstrap(&bot.state, &[OWNER]).await.unwrap();
+        for user in [OWNER, ALICE, BOB, CAROL] {
+            bot.send_text(user, "/start").await;
+        }
+        bot.take_calls().await;
+        bot
+    }
+
+    #[tokio::test]
+    async fn broadcasts_report_blocked_users_and_wait_out_flood_control() {
+        let bot = bot_with_users().await;
+        bot.block_bot(BOB);
+        bot.flood_control_next_send(CAROL, 1);
+
+        bot.send_text(OWNER, "/broadcast maintenance at noon").await;
+        let calls = bot.wait_for_text_to(OWNER, "finished").await;
+        let sends_to = |user: i64| {
+            calls.iter().filter(|call| call.chat_id() == Some(user)
+                && call.text().is_some_and(|text| text.contains("maintenance at noon"))).count()
+        };
+        assert_eq!((sends_to(ALICE), sends_to(BOB), sends_to(CAROL)), (1, 1, 2));
+        let summary = calls.iter().filter_map(|call| call.text()).find(|text| text.contains("finished")).unwrap();
+        assert!(summary.contains("Delivered: 2") && summary.contains("Blocked the bot: 1"), "{}", summary);
+
+        // Users who blocked the bot are left out until they start it again
+        bot.send_text(OWNER, "/broadcast second").await;
+        let calls = bot.wait_for_text_to(OWNER, "finished").await;
+        assert!(calls.iter().all(|call| call.chat_id() != Some(BOB)), "{:?}", calls);
+        bot.unblock_bot(BOB);
+        bot.send_text(BOB, "/start").await;
+        assert!(bot.state.sessions.reachable_user_ids().await.unwrap().contains(&BOB));
+    }
+
+    #[tokio::test]
+    async fn broadcasts_can_be_scheduled() {
+        let bot = bot_with_users().await;
+
+        bot.send_text(OWNER, "/broadcast at 2000-01-01 09:00 too late").await;
+        let texts = bot.take_texts_to(OWNER).await;
+        assert!(texts.iter().any(|text| text.contains("already passed")), "{:?}", texts);
+
+        bot.send_text(OWNER, "/broadcast in 1s see you soon").await;
+        let texts = bot.take_texts_to(OWNER).await;
+        assert!(texts.iter().any(|text| text.contains("will be sent at") && text.contains("lost if the bot restarts")), "{:?}", texts);
+        assert!(bot.take_texts_to(ALICE).await.is_empty());
+
+        let calls = bot.wait_for_text_to(ALICE, "see you soon").await;
+        assert!(calls.iter().all(|call| call.text().is_none_or(|text| !text.contains("in 1s"))), "{:?}", calls);
+    }
+
+    #[tokio::test]
+    async fn a_schedule_may_end_its_line() {
+        let bot = bot_with_users().await;
+
+        bot.send_text(OWNER, "/broadcast in 1s\nBack soon.\n\nThe team").await;
+        let texts = bot.take_texts_to(OWNER).await;
+        assert!(texts.iter().any(|text| text.contains("will be sent at")), "{:?}", texts);
+        assert!(bot.take_texts_to(ALICE).await.is_empty());
+
+        bot.wait_for_text_to(ALICE, "Back soon.\n\nThe team").await;
+    }
+}
+
+mod rate_limiting {
+    use super::*;
+    use telegram_bot::{
+        models::AppState,
+        services::rate_limit::{Budget, RateLimits},
+    };
+
+    async fn bot_with_limits(limits: RateLimits) -> TestBot {
+        TestBot::with_state(AppState { rate_limits: limits, ..AppState::in_memory() }).await
+    }
+
+    #[tokio::test]
+    async fn floods_are_cut_off_then_muted() {
+        let bot = bot_with_limits(RateLimits {
+            messages: Budget::new(2, 60_000),
+            strikes_before_mute: 3,
+            ..RateLimits::default()
+        })
+        .await;
+        bot.pair(ALICE, BOB).await;
+
+        for text in ["one", "two", "three"] {
+            bot.send_text(ALICE, text).await;
+        }
+        let calls = bot.take_calls().await;
+        let to = |chat_id: i64| -> Vec<String> {
+            calls.iter().filter(|call| call.chat_id() == Some(chat_id)).filter_map(|call| call.text()).collect()
+        };
+        assert_eq!(to(BOB), vec!["one", "two"]);
+        assert!(to(ALICE).iter().any(|text| text.contains("Slow down")), "{:?}", to(ALICE));
+
+        // One notice per cooldown, then a mute that also covers commands
+        bot.send_text(ALICE, "four").await;
+        assert!(bot.take_calls().await.is_empty());
+        bot.send_text(ALICE, "five").await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("muted for 5 minutes")), "{:?}", texts);
+
+        bot.send_text(ALICE, "/help").await;
+        assert!(bot.take_calls().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn replies_and_edits_count_against_the_budget() {
+        let bot = bot_with_limits(RateLimits { messages: Budget::new(2, 60_000), ..RateLimits::default() }).await;
+        bot.send_text(CAROL, "/start").await;
+        bot.take_calls().await;
+
+        // Unconnected users are told so once, then slowed down like anyone
+        for text in ["anyone?", "hello?", "hello??"] {
+            bot.send_text(CAROL, text).await;
+        }
+        let texts = bot.take_texts_to(CAROL).await;
+        assert_eq!(texts.iter().filter(|text| text.contains("not connected")).count(), 2, "{:?}", texts);
+        assert!(texts.iter().any(|text| text.contains("Slow down")), "{:?}", texts);
+
+        bot.pair(ALICE, BOB).await;
+        let message_id = bot.send_message(ALICE, json!({ "text": "see you at 5" })).await;
+        for text in ["see you at 6", "see you at 7"] {
+            bot.edit_message(ALICE, message_id, json!({ "text": text })).await;
+        }
+        let calls = bot.take_calls().await;
+        let edits: Vec<_> = calls.iter().filter(|call| call.method == "EditMessageText").filter_map(|call| call.text()).collect();
+        assert_eq!(edits, vec!["see you at 6"]);
+    }
+
+    #[tokio::test]
+    async fn rooms_share_one_budget() {
+        let bot = bot_with_limits(RateLimits { room: Budget::new(3, 60_000), ..RateLimits::default() }).await;
+        for user in [ALICE, BOB, CAROL] {
+            bot.send_text(user, "/start").await;
+        }
+        bot.send_text(ALICE, "/createroom Busy 5").await;
+        let room = bot.state.rooms.list_rooms().await.unwrap().pop().expect("room was not created");
+        for user in [ALICE, BOB, CAROL] {
+            bot.send_text(user, &format!("/joinroom {}", room.room_id)).await;
+        }
+        bot.take_calls().await;
+
+        for user in [ALICE, BOB, ALICE, BOB] {
+            bot.send_text(user, "hello room").await;
+        }
+        let calls = bot.take_calls().await;
+        let to = |chat_id: i64| -> Vec<String> {
+            calls.iter().filter(|call| call.chat_id() == Some(chat_id)).filter_map(|call| call.text()).collect()
+        };
+        assert_eq!(to(CAROL).len(), 3);
+        assert!(to(BOB).iter().any(|text| text.contains("room is busy")), "{:?}", to(BOB));
+    }
+}
+
+mod content_filter {
+    use super::*;
+    use std::sync::Arc;
+    use telegram_bot::{
+        models::AppState,
+        services::{
+            content_filter::{ContentFilter, WordListFile},
+            roles,
+        },
+    };
+
+    async fn bot_with_word_file(name: &str, words: serde_json::Value) -> (TestBot, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("filter-{}-{}.json", name, std::process::id()));
+        std::fs::write(&path, words.to_string()).unwrap();
+        let filter = ContentFilter::load(Box::new(WordListFile::new(path.to_string_lossy())))
+            .await
+            .unwrap();
+        let state = AppState { content_filter: Arc::new(filter), ..AppState::in_memory() };
+        (TestBot::with_state(state).await, path)
+    }
+
+    #[tokio::test]
+    async fn severities_censor_block_and_flag() {
+        let (bot, path) = bot_with_word_file("severities", json!({
+            "*": { "darn": "censor", "scam": "flag" },
+            "en": { "bloody": "block" },
+        }))
+        .await;
+        bot.pair(ALICE, BOB).await;
+
+        bot.send_text(ALICE, "Darn it").await;
+        assert_eq!(bot.take_texts_to(BOB).await, vec!["**** it"]);
+
+        bot.send_text(ALICE, "bloody hell").await;
+        assert!(bot.take_texts_to(BOB).await.is_empty());
+        assert_eq!(bot.state.profiles.get_flag_count(ALICE).await.unwrap(), 0);
+
+        bot.send_text(ALICE, "join my scam").await;
+        assert!(bot.take_texts_to(BOB).await.is_empty());
+        assert_eq!(bot.state.profiles.get_flag_count(ALICE).await.unwrap(), 1);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn word_lists_follow_the_chosen_language() {
+        let (bot, path) = bot_with_word_file("language", json!({
+            "en": { "bloody": "block" },
+            "id": { "anjing": "block" },
+        }))
+        .await;
+        for user in [ALICE, BOB] {
+            bot.send_text(user, "/language id").await;
+        }
+        bot.pair(ALICE, BOB).await;
+
+        bot.send_text(ALICE, "anjing").await;
+        assert!(bot.take_texts_to(BOB).await.is_empty());
+        bot.send_text(ALICE, "bloody hell").await;
+        assert_eq!(bot.take_texts_to(BOB).await, vec!["bloody hell"]);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn changing_language_mid_chat_applies_to_the_next_message() {
+        let (bot, path) = bot_with_word_file("language_change", json!({
+            "en": { "bloody": "block" },
+            "id": { "anjing": "block" },
+        }))
+        .await;
+        for user in [ALICE, BOB] {
+            bot.send_text(user, "/language id").await;
+        }
+        bot.pair(ALICE, BOB).await;
+
+        bot.send_text(ALICE, "bloody hell").await;
+        assert_eq!(bot.take_texts_to(BOB).await, vec!["bloody hell"]);
+        assert_eq!(bot.state.sessions.get_cached_language(ALICE).await.unwrap(), Some(Some("id".to_string())));
+
+        for user in [ALICE, BOB] {
+            bot.send_text(user, "/language en").await;
+        }
+        bot.take_calls().await;
+        bot.send_text(ALICE, "bloody hell").await;
+        assert!(bot.take_texts_to(BOB).await.is_empty());
+        bot.send_text(ALICE, "anjing").await;
+        assert_eq!(bot.take_texts_to(BOB).await, vec!["anjing"]);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn admins_can_reload_word_lists() {
+        let (bot, path) = bot_with_word_file("reload", json!({ "*": {} })).await;
+        bot.pair(ALICE, BOB).await;
+        roles::bootstrap(&bot.state, &[CAROL]).await.unwrap();
+
+        std::fs::write(&path, json!({ "*": { "spoiler": "block" } }).to_string()).unwrap();
+        bot.send_text(ALICE, "/reloadfilter").await;
+        let texts = bot.take_texts_to(ALICE).await;
+        assert!(texts.iter().any(|text| text.contains("only available for administrators")), "{:?}", texts);
+
+        bot.send_text(CAROL, "/reloadfilter").await;
+        let texts = bot.take_texts_to(CAROL).await;
+        assert!(texts.iter().any(|text| text.contains("Reloaded 1 filtered words")), "{:?}", texts);
+
+        bot.send_text(ALICE, "big spoiler ahead").await;
+        assert!(bot.take_texts_to(BOB).await.is_empty());
+
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,178 @@
+//! How `/find` input is read, and the matchmaking rules, checked against the
+//! in-memory store and, when `TEST_REDIS_URL` is set, against the Redis
+//! script, so both backends pair the same users. `TEST_REDIS_URL` must point
+//! at a scratch database: it is emptied before the checks run. The bot's own
+//! `REDIS_URL` is never used.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use telegram_bot::models::UserState;
+use telegram_bot::services::{
+    matchmaking::{normalize_language, parse_interest_tags},
+    memory_store::MemoryStore,
+    redis_service::RedisStore,
+    storage::SessionStore,
+};
+
+// Fallback times that are never reached, and ones that always are
+const NEVER: u64 = 3600;
+const NOW: u64 = 0;
+const FOREVER: u64 = 253_402_300_799; // 9999-12-31
+
+#[test]
+fn interest_tags_are_normalized() {
+    assert_eq!(parse_interest_tags("#Music  gaming MUSIC"), vec!["music", "gaming"]);
+    assert_eq!(parse_interest_tags("rock&roll c++ #"), Vec::<String>::new());
+    assert_eq!(parse_interest_tags("a b c d e f g"), vec!["a", "b", "c", "d", "e"]);
+    assert_eq!(parse_interest_tags("   "), Vec::<String>::new());
+}
+
+#[test]
+fn language_codes_are_reduced_to_the_primary_language() {
+    assert_eq!(normalize_language("en-US").as_deref(), Some("en"));
+    assert_eq!(normalize_language("pt_BR").as_deref(), Some("pt"));
+    assert_eq!(normalize_language("ID").as_deref(), Some("id"));
+    assert_eq!(normalize_language("fil").as_deref(), Some("fil"));
+    for invalid in ["", "e", "english", "e1", "-US"] {
+        assert_eq!(normalize_language(invalid), None, "{:?}", invalid);
+    }
+}
+
+async fn searching(store: &dyn SessionStore, chat_id: i64, tags: &[&str], language: Option<&str>) {
+    let mut state = UserState::new(chat_id);
+    state.is_searching = true;
+    store.set_user_state(&state).await.unwrap();
+    let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+    store.set_search_tags(chat_id, &tags).await.unwrap();
+    store.set_search_language(chat_id, language).await.unwrap();
+}
+
+async fn waiting(store: &dyn SessionStore, chat_id: i64, tags: &[&str], language: Option<&str>) {
+    waiting_for(store, chat_id, tags, language, 0).await;
+}
+
+async fn waiting_for(store: &dyn SessionStore, chat_id: i64, tags: &[&str], language: Option<&str>, secs: u64) {
+    searching(store, chat_id, tags, language).await;
+    store.add_to_search_queue(chat_id, now_secs() - secs).await.unwrap();
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+// Takes a matched caller off the queue, as `find_match` does once the pair
+// is connected
+async fn partner_for(store: &dyn SessionStore, chat_id: i64, fallback: u64, language_fallback: u64) -> Option<i64> {
+    let partner = store.find_random_partner(chat_id, fallback, language_fallback).await.unwrap();
+    if partner.is_some() {
+        store.remove_from_search_queue(chat_id).await.unwrap();
+    }
+    partner.map(|(partner_id, _)| partner_id)
+}
+
+async fn reset(store: &dyn SessionStore, chat_ids: &[i64]) {
+    for &chat_id in chat_ids {
+        store.cleanup_user_state(chat_id).await.unwrap();
+        store.clear_shadowban(chat_id).await.unwrap();
+    }
+}
+
+async fn check_matching_rules(store: &dyn SessionStore) {
+    // Shared interests win over an earlier arrival
+    waiting(store, 1, &["music"], None).await;
+    waiting(store, 2, &["games"], None).await;
+    searching(store, 3, &["games"], None).await;
+    assert_eq!(partner_for(store, 3, NEVER, NEVER).await, Some(2));
+    reset(store, &[1, 2, 3]).await;
+
+    // Without shared interests, only once the tag fallback has passed
+    waiting(store, 1, &["music"], None).await;
+    searching(store, 2, &["games"], None).await;
+    assert_eq!(partner_for(store, 2, NEVER, NEVER).await, None);
+    assert_eq!(partner_for(store, 2, NOW, NEVER).await, Some(1));
+    reset(store, &[1, 2]).await;
+
+    // A shared language counts for more than shared interests
+    waiting(store, 1, &["games"], None).await;
+    waiting(store, 2, &[], Some("id")).await;
+    searching(store, 3, &["games"], Some("id")).await;
+    assert_eq!(partner_for(store, 3, NOW, NEVER).await, Some(2));
+    reset(store, &[1, 2, 3]).await;
+
+    // Different languages only after the language fallback; an unknown
+    // language matches any
+    waiting(store, 1, &[], Some("en")).await;
+    searching(store, 2, &[], Some("fr")).await;
+    assert_eq!(partner_for(store, 2, NOW, NEVER).await, None);
+    assert_eq!(partner_for(store, 2, NOW, NOW).await, Some(1));
+    searching(store, 3, &[], None).await;
+    waiting(store, 4, &[], Some("en")).await;
+    assert_eq!(partner_for(store, 3, NOW, NEVER).await, Some(4));
+    reset(store, &[1, 2, 3, 4]).await;
+
+    // A blocked user never meets the one who blocked them; blocks outlive
+    // the session, so these users are not reused
+    waiting(store, 11, &[], None).await;
+    searching(store, 12, &[], None).await;
+    store.block_user(11, 12).await.unwrap();
+    assert_eq!(partner_for(store, 12, NOW, NOW).await, None);
+    reset(store, &[11, 12]).await;
+
+    // Shadow-banned users only meet each other
+    waiting(store, 1, &[], None).await;
+    store.set_shadowban(1, FOREVER).await.unwrap();
+    waiting(store, 2, &[], None).await;
+    searching(store, 3, &[], None).await;
+    assert_eq!(partner_for(store, 3, NOW, NOW).await, Some(2));
+    searching(store, 4, &[], None).await;
+    store.set_shadowban(4, FOREVER).await.unwrap();
+    assert_eq!(partner_for(store, 4, NOW, NOW).await, Some(1));
+    reset(store, &[1, 2, 3, 4]).await;
+
+    // Users who stopped searching are skipped
+    waiting(store, 1, &[], None).await;
+    waiting(store, 2, &[], None).await;
+    store.cancel_search(1).await.unwrap();
+    searching(store, 3, &[], None).await;
+    assert_eq!(partner_for(store, 3, NOW, NOW).await, Some(2));
+    reset(store, &[1, 2, 3]).await;
+
+    // Skipping a stale entry, or finding the partner taken, costs nobody
+    // their place in the queue
+    waiting_for(store, 1, &[], None, 200).await;
+    store.cancel_search(1).await.unwrap();
+    store.add_to_search_queue(1, now_secs() - 200).await.unwrap();
+    waiting_for(store, 2, &["music"], None, 100).await;
+    waiting_for(store, 3, &["games"], None, 100).await;
+    let taken = store.find_random_partner(2, 50, NEVER).await.unwrap();
+    assert!(taken.is_some_and(|(partner_id, since)| partner_id == 3 && since <= now_secs() - 100));
+    store.add_to_search_queue(3, taken.unwrap().1).await.unwrap();
+    assert_eq!(store.get_waiting_users(100).await.unwrap(), vec![2, 3]);
+    reset(store, &[1, 2, 3]).await;
+
+    // A late end to a chat the user already left keeps their new search
+    waiting(store, 1, &[], None).await;
+    searching(store, 2, &[], None).await;
+    assert_eq!(partner_for(store, 2, NOW, NOW).await, Some(1));
+    store.end_private_chat(1, 2).await.unwrap();
+    waiting(store, 1, &[], None).await;
+    store.end_private_chat(2, 1).await.unwrap();
+    assert!(store.get_user_state(1).await.unwrap().unwrap().is_searching);
+    reset(store, &[1, 2]).await;
+}
+
+#[tokio::test]
+async fn memory_store_follows_the_matching_rules() {
+    check_matching_rules(&MemoryStore::new()).await;
+}
+
+#[tokio::test]
+async fn redis_store_follows_the_matching_rules() {
+    let Ok(url) = std::env::var("TEST_REDIS_URL") else {
+        eprintln!("TEST_REDIS_URL is not set, skipping the Redis matching rules");
+        return;
+    };
+    let client = redis::Client::open(url).unwrap();
+    let mut redis = redis::aio::ConnectionManager::new(client).await.unwrap();
+    let _: () = redis::cmd("FLUSHDB").query_async(&mut redis).await.unwrap();
+    check_matching_rules(&RedisStore::new(redis)).await;
+}